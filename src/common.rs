@@ -1,43 +1,139 @@
 use crate::common::auto_update_status::AutoUpdateStatus;
-use crate::common::backup::backup_program_state;
-use crate::common::save::{read_last_session_save_file, read_save_file, save_program_state};
+use crate::common::backup_destination::get_stale_destination_errors;
+use crate::common::instance_lock::acquire_instance_lock;
+use crate::common::last_session::LastSession;
+use crate::common::persistence::{queue_backup, queue_load, queue_save};
 use crate::common::update::get_release_list;
+use crate::day_stats::improved_daystat::assign_unique_day_ids;
 use crate::state::error_states::HappyChartError;
 use crate::state::happy_chart_state::HappyChartState;
-use crate::LOG_FILE_NAME;
 use chrono::Local;
 use eframe::egui;
 use eframe::epaint::ColorImage;
 use egui::{Context, ViewportCommand};
 use self_update::cargo_crate_version;
 use std::fs::OpenOptions;
+use std::path::Path;
 use std::sync::Arc;
-use tracing::{error, info};
+use std::time::{Duration, SystemTime};
+use tracing::{error, info, warn};
 use tracing_subscriber::fmt;
 use tracing_subscriber::layer::SubscriberExt;
 
 pub mod auto_update_status;
 pub mod backup;
+pub mod backup_destination;
+pub mod backup_manifest;
+pub mod backup_retention;
 pub mod color;
+pub mod data_paths;
 pub mod encryption;
 pub mod export;
+pub mod external_change;
+pub mod history;
+pub mod instance_lock;
 pub mod last_session;
 pub mod math;
+pub mod merge;
 pub mod mood_tag;
+pub mod persistence;
+pub mod profiles;
+pub mod rekey;
+pub mod restore;
 pub mod save;
+pub mod save_format;
+pub mod sync;
+pub mod text_diff;
+pub mod unlock;
 pub mod update;
 
-/// Quit function run when the user clicks the quit button
+/// Quit function run when the user clicks the quit button, the program closes once every pending write has finished, see `close_when_saved`
 #[tracing::instrument(skip(ctx, app))]
-pub fn quit(ctx: &Context, app: &HappyChartState) -> Result<(), HappyChartError> {
+pub fn quit(ctx: &Context, app: &mut HappyChartState) -> Result<(), HappyChartError> {
     info!("Quit program sequence started");
-    save_program_state(ctx, app)?;
-
-    ctx.send_viewport_cmd(ViewportCommand::Close);
-    info!("Quit program sequence completed");
+    // a read-only session has nothing it is allowed to save
+    if !app.read_only {
+        queue_save(ctx, app)?;
+    }
+    app.quit_requested = true;
     Ok(())
 }
 
+/// Closes the program once a requested quit has no writes left pending, a failed write cancels the quit so its error is shown
+#[tracing::instrument(skip(ctx, app))]
+pub fn close_when_saved(ctx: &Context, app: &HappyChartState) {
+    if app.quit_requested && app.persistence.is_idle() {
+        ctx.send_viewport_cmd(ViewportCommand::Close);
+        info!("Quit program sequence completed");
+    }
+}
+
+/// Saves unsaved changes once the program state has gone unchanged for the autosave idle period.
+/// Nothing is saved while an error that blocks saving is outstanding, as the state in memory may not be safe to write, for example while the save file is still locked.
+#[tracing::instrument(skip(ctx, app))]
+pub fn autosave(ctx: &Context, app: &mut HappyChartState) {
+    let Ok(idle_seconds) = u64::try_from(app.program_options.autosave_idle_seconds) else {
+        return;
+    };
+    if !app.has_unsaved_changes()
+        || app.first_load
+        || app.loading
+        || app.read_only
+        || app.external_change.is_some()
+        || app.encryption_change_running
+        || app.error_states.iter().any(HappyChartError::blocks_saving)
+    {
+        return;
+    }
+
+    let idle_period = Duration::from_secs(idle_seconds);
+    let idle_time = app.last_change_time.elapsed().unwrap_or_default();
+    if idle_time < idle_period {
+        ctx.request_repaint_after(idle_period - idle_time);
+        return;
+    }
+
+    info!("Autosaving after {:?} without changes", idle_time);
+    if let Err(err) = queue_save(ctx, app) {
+        error!("Error autosaving: {}", err);
+        // wait another idle period before trying again, instead of retrying every frame
+        app.last_change_time = SystemTime::now();
+        app.error_states.push(err);
+    }
+}
+
+/// Saves unsaved changes when the window is closed, closing is held off until every pending write has finished.
+/// If the changes can not be saved, closing is cancelled once so the error is shown, closing again closes without saving.
+#[tracing::instrument(skip(ctx, app))]
+pub fn save_on_close(ctx: &Context, app: &mut HappyChartState) {
+    if app.quit_requested {
+        if !app.persistence.is_idle() {
+            ctx.send_viewport_cmd(ViewportCommand::CancelClose);
+        }
+        return;
+    }
+    if !app.has_unsaved_changes()
+        || app.first_load
+        || app.loading
+        || app.read_only
+        || app.encryption_change_running
+        || app.error_states.iter().any(HappyChartError::blocks_saving)
+    {
+        if !app.persistence.is_idle() {
+            ctx.send_viewport_cmd(ViewportCommand::CancelClose);
+            app.quit_requested = true;
+        }
+        return;
+    }
+
+    info!("Saving unsaved changes before closing");
+    ctx.send_viewport_cmd(ViewportCommand::CancelClose);
+    if let Err(err) = quit(ctx, app) {
+        error!("Error saving before closing: {}", err);
+        app.error_states.push(err);
+    }
+}
+
 /// First load governs error states on its own, no need to read output
 #[tracing::instrument(skip(app, ctx))]
 pub fn first_load(app: &mut HappyChartState, ctx: &Context, load_save: bool) {
@@ -46,30 +142,58 @@ pub fn first_load(app: &mut HappyChartState, ctx: &Context, load_save: bool) {
     // for example, day quality averages do not need to change between launches
     app.first_load = false;
 
+    // the profile is locked before anything is read, so a second copy of happy chart opens it read-only instead of overwriting it later
     if load_save {
-        match read_save_file() {
-            Ok(save_file_days) => {
-                app.days = save_file_days;
+        match acquire_instance_lock(&app.data_paths) {
+            Ok(()) => {
+                app.instance_lock_held = true;
+                app.last_lock_heartbeat = SystemTime::now();
             }
             Err(err) => {
-                error!("Error reading save file: {}", err);
+                error!("Unable to lock profile: {}", err);
+                app.read_only = matches!(err, HappyChartError::InstanceLocked { .. });
                 app.error_states.push(err);
             }
         }
     }
 
+    if load_save {
+        queue_load(ctx, app);
+    } else {
+        finish_first_load(app, ctx, None);
+    }
+}
+
+/// Runs the rest of program startup once the save file has been read by the persistence worker, or unlocked with its encryption key
+#[tracing::instrument(skip(app, ctx, last_session))]
+pub fn finish_first_load(
+    app: &mut HappyChartState,
+    ctx: &Context,
+    last_session: Option<LastSession>,
+) {
     app.days.sort_by(|day1, day2| {
         day1.get_date()
             .timestamp()
             .cmp(&day2.get_date().timestamp())
     });
+    let reassigned_ids = assign_unique_day_ids(&mut app.days);
 
-    app.starting_length = app.days.len();
+    app.mark_unchanged();
+    if reassigned_ids > 0 {
+        // marked as changed so the new ids are saved, and are the ids every later read and sync sees
+        warn!(
+            "{} days repeated the id of another day and were given new ids",
+            reassigned_ids
+        );
+        app.mark_changed();
+    }
+    // edits from a previously loaded journal or profile do not apply to this one
+    app.edit_history.clear();
+    app.entry_lock_overrides.clear();
+    app.purge_expired_trash();
 
-    if load_save {
-        let ls = read_last_session_save_file();
+    if let Some(ls) = last_session {
         app.open_modulus = ls.open_modulus;
-        app.program_options = ls.program_options;
         app.last_open_date = ls.last_open_date;
         app.last_backup_date = ls.last_backup_date;
         app.open_animation_animating = app.program_options.do_opening_animation;
@@ -114,28 +238,24 @@ pub fn first_load(app: &mut HappyChartState, ctx: &Context, load_save: bool) {
             }
         }
 
-        // check if user last backup day is +- 3 hours between the margin of their auto backup day count
-        if app.program_options.auto_backup_days > -1
-            && Local::now()
-                .signed_duration_since(ls.last_backup_date)
-                .num_days()
-                > i64::from(app.program_options.auto_backup_days)
-        {
-            match backup_program_state(ctx, app, false) {
-                Ok(_) => {
-                    app.last_backup_date = Local::now();
-                }
-                Err(err) => {
-                    error!("Error backing up program state: {:?}", err);
-                    app.error_states.push(err);
-                }
+        // each destination is backed up to if its own auto backup day count has elapsed since the newest backup in its folder,
+        // a read-only session leaves backing up to the copy of happy chart that holds the lock
+        if !app.read_only {
+            if let Err(err) = queue_backup(ctx, app, false) {
+                error!("Error backing up program state: {:?}", err);
+                app.error_states.push(err);
             }
         }
+
+        app.error_states
+            .extend(get_stale_destination_errors(app, Local::now()));
     }
 
-    set_file_logging_state(app.program_options.log_to_file);
+    set_file_logging_state(app.program_options.log_to_file, &app.data_paths.log_file());
 
-    app.remove_old_backup_files();
+    if !app.read_only {
+        app.remove_old_backup_files();
+    }
     app.stats
         .calc_all_stats(&app.days, app.program_options.streak_leniency);
 }
@@ -143,13 +263,13 @@ pub fn first_load(app: &mut HappyChartState, ctx: &Context, load_save: bool) {
 /// Changes to this state take a single program restart due to tracing requiring such for a global default.
 /// This can be circumvented but is insignificant anyway.
 #[tracing::instrument]
-pub fn set_file_logging_state(log_to_file: bool) {
+pub fn set_file_logging_state(log_to_file: bool, log_file_path: &Path) {
     info!("File logging set to: {}", log_to_file);
     if log_to_file {
         let log_file = match OpenOptions::new()
             .append(true)
             .create(true)
-            .open(LOG_FILE_NAME)
+            .open(log_file_path)
         {
             Err(e) => {
                 error!("Error opening log file: {}", e);
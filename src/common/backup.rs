@@ -1,81 +1,245 @@
-use crate::common::save::save_program_state;
+use crate::common::backup_destination::get_last_backup_time;
+use crate::common::backup_manifest::{verify_backup_archive, write_backup_zip, BackupEntry};
+use crate::common::encryption::SaveCipher;
+use crate::common::rekey::rekey_save_data;
+use crate::common::save::{prepare_save_job, run_save_job, sibling_file_path, SaveJob};
 use crate::prelude::HappyChartState;
 use crate::state::error_states::HappyChartError;
 use crate::{
-    BACKUP_FILENAME_PREFIX, BACKUP_FILE_EXTENSION, LAST_SESSION_FILE_NAME, MANUAL_BACKUP_SUFFIX,
-    NEW_SAVE_FILE_NAME, SAVE_FILE_NAME,
+    BACKUP_FILENAME_PREFIX, BACKUP_FILE_EXTENSION, BACKUP_FILE_TIME_FORMAT, LAST_SESSION_FILE_NAME,
+    MANUAL_BACKUP_SUFFIX, PRE_RESTORE_BACKUP_SUFFIX, SAVE_FILE_NAME, SQLITE_SAVE_FILE_NAME,
+    TEMP_FILE_EXTENSION,
 };
-use chrono::{DateTime, Datelike, Local};
+use chrono::{DateTime, Local};
 use egui::Context;
 use std::fs;
 use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
-use tracing::info;
-use zip::write::FileOptions;
-use zip::CompressionMethod;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
 
+/// Names a backup after the time it was made, so no two backups overwrite each other unless made in the same second
 #[tracing::instrument]
-fn get_backup_file_name(time: &DateTime<Local>, is_manual: bool) -> String {
+fn get_backup_file_name(time: &DateTime<Local>, suffix: &str) -> String {
     format!(
-        "{}{}-{}-{}{}.{}",
+        "{}{}{}.{}",
         BACKUP_FILENAME_PREFIX,
-        time.month(),
-        time.day(),
-        time.year(),
-        {
-            if is_manual {
-                MANUAL_BACKUP_SUFFIX
-            } else {
-                ""
-            }
-        },
+        time.format(BACKUP_FILE_TIME_FORMAT),
+        suffix,
         BACKUP_FILE_EXTENSION
     )
 }
 
+/// Encrypts a file stored in a backup with the backup cipher.
+/// Save files encrypted with the save file key are re-encrypted with the backup cipher, so a backup can be restored with the backup key alone.
+/// A SQLite database is re-encrypted in a copy staged in `staging_dir`.
+#[tracing::instrument(skip(data, save_cipher, backup_cipher))]
+fn protect_backup_entry(
+    file_name: &str,
+    data: Vec<u8>,
+    save_cipher: Option<&SaveCipher>,
+    backup_cipher: Option<&SaveCipher>,
+    staging_dir: &Path,
+) -> Result<Vec<u8>, HappyChartError> {
+    let Some(backup_cipher) = backup_cipher else {
+        return Ok(data);
+    };
+    if let Some(save_cipher) = save_cipher {
+        if let Some(rekeyed) = rekey_save_data(
+            file_name,
+            &data,
+            save_cipher,
+            Some(backup_cipher),
+            staging_dir,
+        )? {
+            return Ok(rekeyed);
+        }
+    }
+    // the rows of a SQLite database are encrypted individually, so the database itself is left readable
+    if file_name == SQLITE_SAVE_FILE_NAME {
+        return Ok(data);
+    }
+    backup_cipher.encrypt(&data)
+}
+
+/// The archives written by a backup, and an error for each destination that could not be backed up to
+#[derive(Debug, Default)]
+pub struct BackupOutcome {
+    pub written: Vec<PathBuf>,
+    pub errors: Vec<HappyChartError>,
+}
+
+/// A copy of everything needed to back up the program state, so the backup can be written on the persistence worker
+pub struct BackupJob {
+    /// The program state is saved first, and the files it writes are what is backed up
+    save: SaveJob,
+    backup_cipher: Option<SaveCipher>,
+    /// The name and folder of every destination the backup is written to
+    destinations: Vec<(String, PathBuf)>,
+    archive_file_name: String,
+}
+
+/// Prepares a backup to every destination if `is_manual`, otherwise only to the destinations an automatic backup is due for.
+/// Returns None if no destination is due a backup.
 #[tracing::instrument(skip(ctx, app))]
-pub fn backup_program_state(
+pub fn prepare_backup_job(
     ctx: &Context,
     app: &HappyChartState,
     is_manual: bool,
-) -> Result<(), HappyChartError> {
-    let time = Local::now();
-    save_program_state(ctx, app)?;
-    let _ = fs::create_dir_all(&app.program_options.backup_save_path);
-    let archive_file_name = get_backup_file_name(&time, is_manual);
-    let archive_path = app
+) -> Result<Option<BackupJob>, HappyChartError> {
+    let now = Local::now();
+    let destinations = app
         .program_options
-        .backup_save_path
-        .clone()
-        .join(Path::new(&archive_file_name));
-    let file = File::create(&archive_path).map_err(HappyChartError::SaveBackupIO)?;
-
-    let mut arch = zip::ZipWriter::new(file);
-    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
-    if let Ok(mut old_save_file) = File::open(SAVE_FILE_NAME) {
-        let _ = arch.start_file(SAVE_FILE_NAME, options);
-        let mut old_file_bytes = vec![];
-        let _ = old_save_file.read_to_end(&mut old_file_bytes);
-        let _ = arch.write_all(&old_file_bytes);
+        .get_backup_destinations()
+        .map(|destination| (destination.clone(), app.get_destination_dir(destination)))
+        .filter(|(destination, backup_dir)| {
+            is_manual || destination.is_auto_backup_due(get_last_backup_time(backup_dir), now)
+        })
+        .map(|(destination, backup_dir)| (destination.name, backup_dir))
+        .collect::<Vec<(String, PathBuf)>>();
+    if destinations.is_empty() {
+        return Ok(None);
+    }
+
+    let suffix = if is_manual { MANUAL_BACKUP_SUFFIX } else { "" };
+    Ok(Some(BackupJob {
+        save: prepare_save_job(ctx, app)?,
+        backup_cipher: app.get_backup_cipher()?,
+        destinations,
+        archive_file_name: get_backup_file_name(&now, suffix),
+    }))
+}
+
+/// Saves the program state and writes a backup of it to every destination of the job.
+/// A destination that can not be written to does not stop the backup to the other destinations.
+#[tracing::instrument(skip(job, report_progress))]
+pub fn run_backup_job(job: BackupJob, report_progress: impl Fn(String, f32)) -> BackupOutcome {
+    report_progress("Creating backup".to_string(), 0.0);
+    let archive = match create_backup_archive(job.save, job.backup_cipher.as_ref()) {
+        Ok(archive) => archive,
+        Err(err) => {
+            return BackupOutcome {
+                written: vec![],
+                errors: vec![err],
+            }
+        }
+    };
+
+    let destination_count = job.destinations.len();
+    let mut outcome = BackupOutcome::default();
+    for (index, (name, backup_dir)) in job.destinations.into_iter().enumerate() {
+        report_progress(
+            format!("Writing backup to {}", name),
+            (index + 1) as f32 / (destination_count + 1) as f32,
+        );
+        match write_archive_to_folder(&backup_dir, &job.archive_file_name, &archive) {
+            Ok(archive_path) => outcome.written.push(archive_path),
+            Err(err) => {
+                error!("Error backing up to destination {:?}: {}", name, err);
+                outcome.errors.push(HappyChartError::BackupDestination {
+                    name,
+                    path: backup_dir,
+                    error: Box::new(err),
+                });
+            }
+        }
+    }
+    outcome
+}
+
+/// Everything needed to back up the program state on the persistence worker before a backup is restored over it
+pub struct RestoreBackupJob {
+    save: SaveJob,
+    backup_cipher: Option<SaveCipher>,
+    /// The folder of the primary destination
+    backup_dir: PathBuf,
+}
+
+/// Prepares a backup of the program state to the primary destination, taken before a backup is restored over it
+#[tracing::instrument(skip(ctx, app))]
+pub fn prepare_restore_backup_job(
+    ctx: &Context,
+    app: &HappyChartState,
+) -> Result<RestoreBackupJob, HappyChartError> {
+    Ok(RestoreBackupJob {
+        save: prepare_save_job(ctx, app)?,
+        backup_cipher: app.get_backup_cipher()?,
+        backup_dir: app.get_backup_dir(),
+    })
+}
+
+/// Saves the program state and backs it up, returns the path of the backup
+#[tracing::instrument(skip_all)]
+pub fn run_restore_backup_job(job: RestoreBackupJob) -> Result<PathBuf, HappyChartError> {
+    let archive = create_backup_archive(job.save, job.backup_cipher.as_ref())?;
+    write_archive_to_folder(
+        &job.backup_dir,
+        &get_backup_file_name(&Local::now(), PRE_RESTORE_BACKUP_SUFFIX),
+        &archive,
+    )
+}
+
+/// Saves the program state, then zips the save file and last session file, returns the bytes of the archive
+#[tracing::instrument(skip(save, backup_cipher))]
+fn create_backup_archive(
+    save: SaveJob,
+    backup_cipher: Option<&SaveCipher>,
+) -> Result<Vec<u8>, HappyChartError> {
+    let data_paths = save.data_paths.clone();
+    let storage_backend = save.storage_backend;
+    let save_cipher = save.save_cipher.clone();
+    let day_count = save.days.len();
+    run_save_job(save)?;
+
+    let mut entries = vec![];
+    if let Ok(old_file_bytes) = fs::read(data_paths.legacy_save_file()) {
+        entries.push((SAVE_FILE_NAME, old_file_bytes));
     } else {
         // no old save file present, so we can just
     }
-    let mut new_save_file =
-        File::open(NEW_SAVE_FILE_NAME).map_err(HappyChartError::SaveBackupIO)?;
-    let mut last_session_file =
-        File::open(LAST_SESSION_FILE_NAME).map_err(HappyChartError::SaveBackupIO)?;
-    let _ = arch.start_file(NEW_SAVE_FILE_NAME, options);
-    let mut new_file_bytes = vec![];
-    let _ = new_save_file.read_to_end(&mut new_file_bytes);
-    let _ = arch.write_all(&new_file_bytes);
-    let _ = arch.start_file(LAST_SESSION_FILE_NAME, options);
-    let mut last_session_file_bytes = vec![];
-    let _ = last_session_file.read_to_end(&mut last_session_file_bytes);
-    let _ = arch.write_all(&last_session_file_bytes);
-    let _ = arch.finish();
+    let new_file_bytes = fs::read(storage_backend.get_save_file_path(&data_paths))
+        .map_err(HappyChartError::SaveBackupIO)?;
+    let last_session_file_bytes =
+        fs::read(data_paths.last_session_file()).map_err(HappyChartError::SaveBackupIO)?;
+    entries.push((storage_backend.get_save_file_name(), new_file_bytes));
+    entries.push((LAST_SESSION_FILE_NAME, last_session_file_bytes));
+
+    let entries = entries
+        .into_iter()
+        .map(|(file_name, data)| {
+            protect_backup_entry(
+                file_name,
+                data,
+                save_cipher.as_ref(),
+                backup_cipher,
+                data_paths.data_dir(),
+            )
+            .map(|data| (file_name.to_string(), data))
+        })
+        .collect::<Result<Vec<BackupEntry>, HappyChartError>>()?;
+
+    write_backup_zip(&entries, Local::now(), Some(day_count))
+}
+
+/// Writes an archive into a backup folder and verifies it, returns the path of the archive
+#[tracing::instrument(skip(archive))]
+fn write_archive_to_folder(
+    backup_dir: &Path,
+    archive_file_name: &str,
+    archive: &[u8],
+) -> Result<PathBuf, HappyChartError> {
+    fs::create_dir_all(backup_dir).map_err(HappyChartError::SaveBackupIO)?;
+    let archive_path = backup_dir.join(archive_file_name);
+
+    // the archive is written next to its final path and only moved into place once complete, so a failed backup never replaces a good one
+    let temp_path = sibling_file_path(&archive_path, TEMP_FILE_EXTENSION);
+    File::create(&temp_path)
+        .and_then(|mut file| file.write_all(archive).and_then(|()| file.sync_all()))
+        .map_err(HappyChartError::SaveBackupIO)?;
+    fs::rename(&temp_path, &archive_path).map_err(HappyChartError::SaveBackupIO)?;
+    verify_backup_archive(&archive_path)?;
 
     info!("Successfully saved backup in path {:?}", archive_path);
 
-    Ok(())
+    Ok(archive_path)
 }
@@ -0,0 +1,135 @@
+use crate::common::backup_retention::{
+    list_automatic_backups, parse_backup_file_time, BackupRetentionPolicy, GfsRetentionCounts,
+};
+use crate::prelude::HappyChartState;
+use crate::state::error_states::HappyChartError;
+use crate::{BACKUP_FILENAME_PREFIX, BACKUP_FILE_EXTENSION};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// A folder backups are written to, with its own schedule and retention.
+/// The primary destination is stored flattened into `ProgramOptions`, so its fields keep the names they had before there were multiple destinations.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct BackupDestination {
+    /// Shown in the options menu and in errors about this destination
+    #[serde(rename = "backup_destination_name")]
+    pub name: String,
+    /// The folder backups are stored in, a relative path is relative to the data folder
+    #[serde(rename = "backup_save_path")]
+    pub path: PathBuf,
+    /// Days to elapse FULLY between automatically backing up the program state to a zip
+    pub auto_backup_days: i32,
+    /// Number of days FULLY elapsed before a backup is considered stale and will be deleted when there are enough backups present
+    pub backup_age_keep_days: i32,
+    /// The minimum number of backups before we try to automatically clean up stale backups
+    pub number_of_kept_backups: i32,
+    /// How automatic backups are pruned
+    pub backup_retention_policy: BackupRetentionPolicy,
+    /// Used when `backup_retention_policy` is `GrandfatherFatherSon`
+    pub gfs_retention_counts: GfsRetentionCounts,
+    /// Warn at startup if this destination has not received a backup in this many days, -1 for disabled
+    #[serde(rename = "backup_warn_after_days")]
+    pub warn_after_days: i32,
+}
+
+impl Default for BackupDestination {
+    fn default() -> Self {
+        Self {
+            name: "Primary".to_string(),
+            path: PathBuf::from("./backups/"),
+            auto_backup_days: -1,
+            backup_age_keep_days: -1,
+            number_of_kept_backups: -1,
+            backup_retention_policy: BackupRetentionPolicy::default(),
+            gfs_retention_counts: GfsRetentionCounts::default(),
+            warn_after_days: -1,
+        }
+    }
+}
+
+impl BackupDestination {
+    /// Returns true if an automatic backup should be written to this destination, given when it last received one
+    pub fn is_auto_backup_due(
+        &self,
+        last_backup: Option<DateTime<Local>>,
+        now: DateTime<Local>,
+    ) -> bool {
+        self.auto_backup_days > -1
+            && last_backup.is_none_or(|last_backup| {
+                now.signed_duration_since(last_backup).num_days() > i64::from(self.auto_backup_days)
+            })
+    }
+
+    /// Returns true if this destination should be warned about, given when it last received a backup
+    pub fn is_stale(&self, last_backup: Option<DateTime<Local>>, now: DateTime<Local>) -> bool {
+        self.warn_after_days > -1
+            && last_backup.is_none_or(|last_backup| {
+                now.signed_duration_since(last_backup).num_days() > i64::from(self.warn_after_days)
+            })
+    }
+}
+
+/// Returns an error for each destination that has not received a backup within its warning day count
+#[tracing::instrument(skip(app))]
+pub fn get_stale_destination_errors(
+    app: &HappyChartState,
+    now: DateTime<Local>,
+) -> Vec<HappyChartError> {
+    app.program_options
+        .get_backup_destinations()
+        .filter_map(|destination| {
+            let backup_dir = app.get_destination_dir(destination);
+            let last_backup = get_last_backup_time(&backup_dir);
+            destination.is_stale(last_backup, now).then(|| {
+                warn!(
+                    "Backup destination {:?} has not received a backup since {:?}",
+                    destination.name, last_backup
+                );
+                HappyChartError::StaleBackupDestination {
+                    name: destination.name.clone(),
+                    path: backup_dir,
+                    last_backup,
+                    warn_after_days: destination.warn_after_days,
+                }
+            })
+        })
+        .collect()
+}
+
+/// Returns when the newest backup in a backup folder was made, manual backups included, or None if there are none
+#[tracing::instrument]
+pub fn get_last_backup_time(backup_dir: &Path) -> Option<DateTime<Local>> {
+    let newest_automatic_backup = list_automatic_backups(backup_dir)
+        .first()
+        .map(|backup| backup.time);
+    let newest_named_backup = fs::read_dir(backup_dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.file_name().to_str().and_then(parse_backup_file_time))
+        .max();
+    newest_automatic_backup.max(newest_named_backup)
+}
+
+/// Returns every backup archive in a backup folder
+pub fn list_backup_archive_paths(backup_dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(backup_dir)
+        .map(|dir_list| {
+            dir_list
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| {
+                            name.starts_with(BACKUP_FILENAME_PREFIX)
+                                && name.ends_with(BACKUP_FILE_EXTENSION)
+                        })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
@@ -0,0 +1,311 @@
+use crate::common::backup_destination::list_backup_archive_paths;
+use crate::state::error_states::HappyChartError;
+use crate::BACKUP_MANIFEST_FILE_NAME;
+use chrono::{DateTime, Local};
+use self_update::cargo_crate_version;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// The file name and contents of a single entry in a backup archive
+pub type BackupEntry = (String, Vec<u8>);
+
+/// The path of a backup archive, and the manifest of the archive if it passed verification
+pub type BackupVerification = (PathBuf, Result<BackupManifest, HappyChartError>);
+
+/// Stored in every backup archive, describes the other entries so the archive can be verified without being decrypted
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupManifest {
+    /// The version of happy chart that wrote the archive
+    pub app_version: String,
+    pub created: DateTime<Local>,
+    /// The number of days in the journal the archive was made from, None for archives made before it was recorded
+    #[serde(default)]
+    pub day_count: Option<usize>,
+    /// The number of files in the archive, not counting the manifest
+    #[serde(alias = "entry_count")]
+    pub file_count: usize,
+    pub entries: Vec<BackupManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupManifestEntry {
+    pub file_name: String,
+    pub size: usize,
+    /// Lowercase hex SHA-256 of the entry, as stored in the archive
+    pub sha256: String,
+}
+
+impl BackupManifest {
+    pub fn new(
+        entries: &[BackupEntry],
+        created: DateTime<Local>,
+        day_count: Option<usize>,
+    ) -> Self {
+        Self {
+            app_version: cargo_crate_version!().to_string(),
+            created,
+            day_count,
+            file_count: entries.len(),
+            entries: entries
+                .iter()
+                .map(|(file_name, data)| BackupManifestEntry {
+                    file_name: file_name.clone(),
+                    size: data.len(),
+                    sha256: sha256_hex(data),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Why a backup archive failed verification
+#[derive(Debug)]
+pub enum BackupProblem {
+    /// The archive itself could not be read, it is most likely truncated
+    Unreadable(std::io::Error),
+    /// The archive has no manifest, either it was made before manifests existed, or it is incomplete
+    MissingManifest,
+    InvalidManifest(serde_json::Error),
+    FileCountMismatch {
+        expected: usize,
+        found: usize,
+    },
+    MissingEntry(String),
+    ChecksumMismatch(String),
+}
+
+impl Display for BackupProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unreadable(err) => write!(f, "unreadable archive {}", err),
+            Self::MissingManifest => write!(f, "missing manifest"),
+            Self::InvalidManifest(err) => write!(f, "invalid manifest {}", err),
+            Self::FileCountMismatch { expected, found } => {
+                write!(f, "expected {} files, found {}", expected, found)
+            }
+            Self::MissingEntry(file_name) => write!(f, "missing entry {}", file_name),
+            Self::ChecksumMismatch(file_name) => write!(f, "checksum mismatch in {}", file_name),
+        }
+    }
+}
+
+/// Returns the lowercase hex SHA-256 of the data
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Zips the entries along with a manifest describing them, returning the bytes of the archive.
+/// `day_count` is the number of days in the journal the entries were saved from.
+#[tracing::instrument(skip(entries))]
+pub fn write_backup_zip(
+    entries: &[BackupEntry],
+    created: DateTime<Local>,
+    day_count: Option<usize>,
+) -> Result<Vec<u8>, HappyChartError> {
+    let manifest = serde_json::to_vec_pretty(&BackupManifest::new(entries, created, day_count))
+        .map_err(HappyChartError::Serialization)?;
+
+    let mut writer = ZipWriter::new(Cursor::new(vec![]));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (file_name, data) in entries
+        .iter()
+        .map(|(file_name, data)| (file_name.as_str(), data))
+        .chain([(BACKUP_MANIFEST_FILE_NAME, &manifest)])
+    {
+        writer
+            .start_file(file_name, options)
+            .map_err(|err| HappyChartError::SaveBackupIO(err.into()))?;
+        writer
+            .write_all(data)
+            .map_err(HappyChartError::SaveBackupIO)?;
+    }
+    writer
+        .finish()
+        .map(Cursor::into_inner)
+        .map_err(|err| HappyChartError::SaveBackupIO(err.into()))
+}
+
+/// Reads every entry of a backup archive, the manifest is returned separately from the other entries if there is one
+#[tracing::instrument(skip(data))]
+pub fn read_backup_zip(data: &[u8]) -> Result<(Option<Vec<u8>>, Vec<BackupEntry>), std::io::Error> {
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+    let mut manifest = None;
+    let mut entries = vec![];
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let mut entry_data = vec![];
+        entry.read_to_end(&mut entry_data)?;
+        if entry.name() == BACKUP_MANIFEST_FILE_NAME {
+            manifest = Some(entry_data);
+        } else {
+            entries.push((entry.name().to_string(), entry_data));
+        }
+    }
+    Ok((manifest, entries))
+}
+
+/// Checks every entry of a backup archive against its manifest, returning the manifest if the archive is intact
+#[tracing::instrument]
+pub fn verify_backup_archive(path: &Path) -> Result<BackupManifest, HappyChartError> {
+    let corrupt = |problem| HappyChartError::CorruptBackup {
+        path: path.to_path_buf(),
+        problem,
+    };
+
+    let data = fs::read(path).map_err(|err| corrupt(BackupProblem::Unreadable(err)))?;
+    let (manifest, entries) =
+        read_backup_zip(&data).map_err(|err| corrupt(BackupProblem::Unreadable(err)))?;
+    let manifest = serde_json::from_slice::<BackupManifest>(
+        &manifest.ok_or_else(|| corrupt(BackupProblem::MissingManifest))?,
+    )
+    .map_err(|err| corrupt(BackupProblem::InvalidManifest(err)))?;
+
+    if manifest.file_count != entries.len() || manifest.entries.len() != entries.len() {
+        return Err(corrupt(BackupProblem::FileCountMismatch {
+            expected: manifest.file_count,
+            found: entries.len(),
+        }));
+    }
+
+    let entries = entries.into_iter().collect::<HashMap<String, Vec<u8>>>();
+    for manifest_entry in &manifest.entries {
+        let data = entries.get(&manifest_entry.file_name).ok_or_else(|| {
+            corrupt(BackupProblem::MissingEntry(
+                manifest_entry.file_name.clone(),
+            ))
+        })?;
+        if data.len() != manifest_entry.size || sha256_hex(data) != manifest_entry.sha256 {
+            return Err(corrupt(BackupProblem::ChecksumMismatch(
+                manifest_entry.file_name.clone(),
+            )));
+        }
+    }
+
+    Ok(manifest)
+}
+
+/// Verifies every backup archive in each of the backup folders, returning the path of each archive along with the result of verifying it
+#[tracing::instrument(skip(report_progress))]
+pub fn verify_all_backups(
+    backup_dirs: &[PathBuf],
+    report_progress: impl Fn(String, f32),
+) -> Vec<BackupVerification> {
+    let paths = backup_dirs
+        .iter()
+        .flat_map(|backup_dir| list_backup_archive_paths(backup_dir))
+        .collect::<Vec<PathBuf>>();
+    let path_count = paths.len();
+    paths
+        .into_iter()
+        .enumerate()
+        .map(|(index, path)| {
+            report_progress(
+                format!("Verifying backup {} of {}", index + 1, path_count),
+                index as f32 / path_count as f32,
+            );
+            let result = verify_backup_archive(&path);
+            match &result {
+                Ok(_) => info!("Verified backup {:?}", path),
+                Err(err) => warn!("Backup failed verification: {}", err),
+            }
+            (path, result)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn write_test_archive(data: &[u8]) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "happy_chart_backup_manifest_test_{}",
+            rand::random::<u64>()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("backup.zip");
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn test_entries() -> Vec<BackupEntry> {
+        vec![
+            ("save.json".to_string(), b"days".to_vec()),
+            ("last_session.json".to_string(), b"options".to_vec()),
+        ]
+    }
+
+    #[test]
+    fn intact_archive_passes_verification() {
+        let archive = write_backup_zip(&test_entries(), Local::now(), Some(2)).unwrap();
+        let path = write_test_archive(&archive);
+
+        let manifest = verify_backup_archive(&path).unwrap();
+        assert_eq!(manifest.file_count, 2);
+        assert_eq!(manifest.day_count, Some(2));
+        assert_eq!(manifest.entries[0].sha256, sha256_hex(b"days"));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn corrupt_entry_fails_verification() {
+        let archive = write_backup_zip(&test_entries(), Local::now(), Some(2)).unwrap();
+        let (manifest, _) = read_backup_zip(&archive).unwrap();
+        let manifest = manifest.unwrap();
+
+        // the same manifest, with one entry changed after it was written
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for (file_name, data) in [
+            ("save.json", b"dayz".as_slice()),
+            ("last_session.json", b"options".as_slice()),
+            (BACKUP_MANIFEST_FILE_NAME, manifest.as_slice()),
+        ] {
+            writer
+                .start_file(file_name, FileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        let path = write_test_archive(&writer.finish().unwrap().into_inner());
+
+        let result = verify_backup_archive(&path);
+        assert!(matches!(
+            result,
+            Err(HappyChartError::CorruptBackup {
+                problem: BackupProblem::ChecksumMismatch(ref file_name),
+                ..
+            }) if file_name == "save.json"
+        ));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn archive_without_manifest_fails_verification() {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        writer
+            .start_file("save.json", FileOptions::default())
+            .unwrap();
+        writer.write_all(b"days").unwrap();
+        let path = write_test_archive(&writer.finish().unwrap().into_inner());
+
+        assert!(matches!(
+            verify_backup_archive(&path),
+            Err(HappyChartError::CorruptBackup {
+                problem: BackupProblem::MissingManifest,
+                ..
+            })
+        ));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,265 @@
+use crate::common::backup_destination::BackupDestination;
+use crate::{
+    BACKUP_FILENAME_PREFIX, BACKUP_FILE_EXTENSION, BACKUP_FILE_TIME_FORMAT,
+    LEGACY_BACKUP_FILE_DATE_FORMAT, MANUAL_BACKUP_SUFFIX, PRE_RESTORE_BACKUP_SUFFIX,
+};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use strum_macros::EnumIter;
+use tracing::{debug, warn};
+
+/// How automatic backups are pruned, manual backups and backups taken before a restore are never pruned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumIter)]
+pub enum BackupRetentionPolicy {
+    /// Backups older than `backup_age_keep_days` are removed, oldest first, while more than `number_of_kept_backups` of them remain
+    AgeAndCount,
+    /// The newest backup of each of the most recent days, weeks and months with backups is kept, see `GfsRetentionCounts`
+    GrandfatherFatherSon,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for BackupRetentionPolicy {
+    fn default() -> Self {
+        Self::AgeAndCount
+    }
+}
+
+impl Display for BackupRetentionPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AgeAndCount => write!(f, "Age and count"),
+            Self::GrandfatherFatherSon => write!(f, "Daily, weekly and monthly"),
+        }
+    }
+}
+
+/// The number of days, weeks and months the grandfather-father-son policy keeps a backup for
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GfsRetentionCounts {
+    pub daily: u32,
+    pub weekly: u32,
+    pub monthly: u32,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for GfsRetentionCounts {
+    fn default() -> Self {
+        Self {
+            daily: 7,
+            weekly: 4,
+            monthly: 12,
+        }
+    }
+}
+
+/// An automatic backup in the backup folder, and when it was made
+#[derive(Debug, Clone)]
+pub struct BackupFile {
+    pub path: PathBuf,
+    pub time: DateTime<Local>,
+}
+
+/// Returns when a backup was made from its file name, backups named before the time of day was included are treated as made at midnight
+pub fn parse_backup_file_time(file_name: &str) -> Option<DateTime<Local>> {
+    let time = file_name
+        .strip_prefix(BACKUP_FILENAME_PREFIX)?
+        .strip_suffix(BACKUP_FILE_EXTENSION)?
+        .strip_suffix('.')?;
+    let time = time.strip_suffix(MANUAL_BACKUP_SUFFIX).unwrap_or(time);
+    let time = time.strip_suffix(PRE_RESTORE_BACKUP_SUFFIX).unwrap_or(time);
+
+    NaiveDateTime::parse_from_str(time, BACKUP_FILE_TIME_FORMAT)
+        .or_else(|_| {
+            NaiveDate::parse_from_str(time, LEGACY_BACKUP_FILE_DATE_FORMAT)
+                .map(|date| date.and_time(NaiveTime::MIN))
+        })
+        .ok()?
+        .and_local_timezone(Local)
+        .earliest()
+}
+
+/// Lists every automatic backup in the backup folder, newest first.
+/// The time a backup was made is read from its name, falling back to when it was last modified, as creation times are unreliable on some filesystems.
+#[tracing::instrument]
+pub fn list_automatic_backups(backup_dir: &Path) -> Vec<BackupFile> {
+    let Ok(dir_list) = fs::read_dir(backup_dir) else {
+        warn!("Unable to read backup folder {:?}", backup_dir);
+        return vec![];
+    };
+
+    let mut backups = dir_list
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_str()?.to_string();
+            if !file_name.starts_with(BACKUP_FILENAME_PREFIX)
+                || !file_name.ends_with(BACKUP_FILE_EXTENSION)
+                || file_name.contains(MANUAL_BACKUP_SUFFIX)
+                || file_name.contains(PRE_RESTORE_BACKUP_SUFFIX)
+            {
+                return None;
+            }
+            let time = parse_backup_file_time(&file_name).or_else(|| {
+                entry
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+                    .map(DateTime::<Local>::from)
+            })?;
+            debug!("Backup: {} made at: {}", file_name, time);
+            Some(BackupFile {
+                path: entry.path(),
+                time,
+            })
+        })
+        .collect::<Vec<BackupFile>>();
+
+    backups.sort_by_key(|backup| Reverse(backup.time));
+    backups
+}
+
+/// Returns the automatic backups the retention policy of a destination would remove from its backup folder, without removing anything
+#[tracing::instrument(skip(destination))]
+pub fn get_backups_to_prune(
+    backup_dir: &Path,
+    destination: &BackupDestination,
+    now: DateTime<Local>,
+) -> Vec<BackupFile> {
+    let backups = list_automatic_backups(backup_dir);
+    match destination.backup_retention_policy {
+        BackupRetentionPolicy::AgeAndCount => select_by_age_and_count(
+            backups,
+            destination.backup_age_keep_days,
+            destination.number_of_kept_backups,
+            now,
+        ),
+        BackupRetentionPolicy::GrandfatherFatherSon => {
+            select_by_gfs(backups, destination.gfs_retention_counts)
+        }
+    }
+}
+
+/// `backups` must be sorted newest first, the oldest stale backups are selected first
+fn select_by_age_and_count(
+    backups: Vec<BackupFile>,
+    backup_age_keep_days: i32,
+    number_of_kept_backups: i32,
+    now: DateTime<Local>,
+) -> Vec<BackupFile> {
+    if backup_age_keep_days < 0 || number_of_kept_backups < 0 {
+        return vec![];
+    }
+
+    let stale_backups = backups
+        .into_iter()
+        .rev()
+        .filter(|backup| {
+            now.signed_duration_since(backup.time).num_days() > i64::from(backup_age_keep_days)
+        })
+        .collect::<Vec<BackupFile>>();
+
+    let number_to_remove = stale_backups
+        .len()
+        .saturating_sub(number_of_kept_backups as usize);
+    stale_backups.into_iter().take(number_to_remove).collect()
+}
+
+/// `backups` must be sorted newest first, every backup that is not the newest of a kept day, week, or month is selected
+fn select_by_gfs(backups: Vec<BackupFile>, counts: GfsRetentionCounts) -> Vec<BackupFile> {
+    let mut kept = HashSet::new();
+    keep_newest_per_period(&backups, counts.daily, &mut kept, |time| time.date_naive());
+    keep_newest_per_period(&backups, counts.weekly, &mut kept, |time| {
+        let week = time.iso_week();
+        (week.year(), week.week())
+    });
+    keep_newest_per_period(&backups, counts.monthly, &mut kept, |time| {
+        (time.year(), time.month())
+    });
+
+    backups
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| !kept.contains(index))
+        .map(|(_, backup)| backup)
+        .collect()
+}
+
+/// Marks the newest backup of each of the `count` most recent periods as kept
+fn keep_newest_per_period<K: Eq + Hash>(
+    backups: &[BackupFile],
+    count: u32,
+    kept: &mut HashSet<usize>,
+    period_of: impl Fn(&DateTime<Local>) -> K,
+) {
+    let mut seen_periods = HashSet::new();
+    for (index, backup) in backups.iter().enumerate() {
+        if seen_periods.len() >= count as usize {
+            break;
+        }
+        if seen_periods.insert(period_of(&backup.time)) {
+            kept.insert(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn backup_at(name: &str, year: i32, month: u32, day: u32, hour: u32) -> BackupFile {
+        BackupFile {
+            path: PathBuf::from(name),
+            time: Local
+                .with_ymd_and_hms(year, month, day, hour, 0, 0)
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn gfs_keeps_the_newest_backup_of_each_day_week_and_month() {
+        // newest first, 2024-03-04 is a monday, so 2024-03-03 ends the previous ISO week
+        let backups = vec![
+            backup_at("monday evening", 2024, 3, 4, 18),
+            backup_at("monday morning", 2024, 3, 4, 9),
+            backup_at("sunday", 2024, 3, 3, 20),
+            backup_at("friday", 2024, 3, 1, 12),
+            backup_at("end of february", 2024, 2, 29, 12),
+            backup_at("mid february", 2024, 2, 20, 12),
+            backup_at("end of january", 2024, 1, 31, 12),
+        ];
+        let counts = GfsRetentionCounts {
+            daily: 2,
+            weekly: 2,
+            monthly: 2,
+        };
+
+        let pruned = select_by_gfs(backups, counts)
+            .into_iter()
+            .map(|backup| backup.path)
+            .collect::<Vec<PathBuf>>();
+        assert_eq!(
+            pruned,
+            ["monday morning", "friday", "mid february", "end of january"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn gfs_with_no_counts_prunes_everything() {
+        let backups = vec![
+            backup_at("newest", 2024, 3, 4, 18),
+            backup_at("oldest", 2024, 2, 4, 18),
+        ];
+        let counts = GfsRetentionCounts {
+            daily: 0,
+            weekly: 0,
+            monthly: 0,
+        };
+        assert_eq!(select_by_gfs(backups, counts).len(), 2);
+    }
+}
@@ -0,0 +1,367 @@
+use crate::common::backup_destination::BackupDestination;
+use crate::common::save::read_last_session_save_file;
+use crate::{
+    APP_DIRECTORY_NAME, BACKUP_FILENAME_PREFIX, BACKUP_FILE_EXTENSION, DATA_DIR_ARG,
+    LAST_SESSION_FILE_NAME, LOCK_FILE_NAME, LOG_FILE_NAME, NEW_SAVE_FILE_NAME,
+    PORTABLE_MARKER_FILE_NAME, PREVIOUS_GENERATION_EXTENSION, PROFILES_DIRECTORY_NAME,
+    PROFILE_SETTINGS_FILE_NAME, SAVE_FILE_NAME, SQLITE_SAVE_FILE_NAME, SYNC_STATE_FILE_NAME,
+};
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{error, info, warn};
+
+/// The reason happy chart chose the folders it stores its data in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataLocation {
+    /// The folder was given using the `--data-dir` command line argument
+    CommandLine,
+    /// A portable marker file is next to the executable, so data is stored next to the executable
+    Portable,
+    /// The platform data and config folders, for example `~/.local/share` and `~/.config` on linux
+    Platform,
+}
+
+impl Display for DataLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CommandLine => write!(f, "Command line ({})", DATA_DIR_ARG),
+            Self::Portable => write!(f, "Portable ({})", PORTABLE_MARKER_FILE_NAME),
+            Self::Platform => write!(f, "Platform default"),
+        }
+    }
+}
+
+/// The folders happy chart reads and writes its files in, resolved once on launch.
+/// Each named profile stores its files in a folder of its own below the data and config folders, the default profile uses them directly.
+#[derive(Debug, Clone)]
+pub struct DataPaths {
+    /// Folder holding the save file, log file, and backups by default
+    data_dir: PathBuf,
+    /// Folder holding the last session file, which contains the program options
+    config_dir: PathBuf,
+    location: DataLocation,
+    /// The data folder of the default profile
+    root_data_dir: PathBuf,
+    /// The config folder of the default profile
+    root_config_dir: PathBuf,
+    /// Name of the profile in use, None for the default profile
+    profile: Option<String>,
+}
+
+impl DataPaths {
+    /// Resolves the data folders from the programs command line arguments, the first argument is expected to be the program name.
+    /// Priority is given to `--data-dir`, then to a portable marker file next to the executable, then to the platform folders.
+    #[tracing::instrument(skip(args))]
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        if let Some(dir) = get_data_dir_arg(args) {
+            info!("Using data directory from command line: {:?}", dir);
+            return Self::in_single_dir(dir, DataLocation::CommandLine);
+        }
+
+        if let Some(dir) = get_portable_dir() {
+            info!("Portable marker found, using data directory: {:?}", dir);
+            return Self::in_single_dir(dir, DataLocation::Portable);
+        }
+
+        match (dirs::data_dir(), dirs::config_dir()) {
+            (Some(data_dir), Some(config_dir)) => {
+                let data_dir = data_dir.join(APP_DIRECTORY_NAME);
+                let config_dir = config_dir.join(APP_DIRECTORY_NAME);
+                Self {
+                    data_dir: data_dir.clone(),
+                    config_dir: config_dir.clone(),
+                    location: DataLocation::Platform,
+                    root_data_dir: data_dir,
+                    root_config_dir: config_dir,
+                    profile: None,
+                }
+            }
+            _ => {
+                warn!("No platform data directory available, using the working directory");
+                Self::in_single_dir(PathBuf::from("."), DataLocation::Platform)
+            }
+        }
+    }
+
+    /// Stores every file in a single folder
+    pub fn in_single_dir(dir: PathBuf, location: DataLocation) -> Self {
+        Self {
+            data_dir: dir.clone(),
+            config_dir: dir.clone(),
+            location,
+            root_data_dir: dir.clone(),
+            root_config_dir: dir,
+            profile: None,
+        }
+    }
+
+    /// Returns the folders of a named profile, or of the default profile if None is given
+    pub fn with_profile(&self, profile: Option<&str>) -> Self {
+        let (data_dir, config_dir) = match profile {
+            Some(profile) => (
+                self.profiles_dir().join(profile),
+                self.root_config_dir
+                    .join(PROFILES_DIRECTORY_NAME)
+                    .join(profile),
+            ),
+            None => (self.root_data_dir.clone(), self.root_config_dir.clone()),
+        };
+        Self {
+            data_dir,
+            config_dir,
+            location: self.location,
+            root_data_dir: self.root_data_dir.clone(),
+            root_config_dir: self.root_config_dir.clone(),
+            profile: profile.map(ToString::to_string),
+        }
+    }
+
+    /// Creates the data and config folders if they do not exist yet
+    #[tracing::instrument]
+    pub fn create_dirs(&self) {
+        for dir in [&self.data_dir, &self.config_dir] {
+            if let Err(err) = fs::create_dir_all(dir) {
+                error!("Unable to create directory {:?}: {}", dir, err);
+            }
+        }
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    pub fn config_dir(&self) -> &Path {
+        &self.config_dir
+    }
+
+    pub fn location(&self) -> DataLocation {
+        self.location
+    }
+
+    /// Name of the profile in use, None for the default profile
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// Folder holding the data folder of every named profile
+    pub fn profiles_dir(&self) -> PathBuf {
+        self.root_data_dir.join(PROFILES_DIRECTORY_NAME)
+    }
+
+    /// Shared between every profile, so it is always in the config folder of the default profile
+    pub fn profile_settings_file(&self) -> PathBuf {
+        self.root_config_dir.join(PROFILE_SETTINGS_FILE_NAME)
+    }
+
+    pub fn save_file(&self) -> PathBuf {
+        self.data_dir.join(NEW_SAVE_FILE_NAME)
+    }
+
+    /// The database used when the SQLite storage backend is selected
+    pub fn sqlite_save_file(&self) -> PathBuf {
+        self.data_dir.join(SQLITE_SAVE_FILE_NAME)
+    }
+
+    /// The save file used by very old versions of happy chart
+    pub fn legacy_save_file(&self) -> PathBuf {
+        self.data_dir.join(SAVE_FILE_NAME)
+    }
+
+    pub fn last_session_file(&self) -> PathBuf {
+        self.config_dir.join(LAST_SESSION_FILE_NAME)
+    }
+
+    /// Held by the running copy of happy chart that has this profile open
+    pub fn lock_file(&self) -> PathBuf {
+        self.data_dir.join(LOCK_FILE_NAME)
+    }
+
+    /// What this device knows about the sync folder, kept out of the sync folder itself
+    pub fn sync_state_file(&self) -> PathBuf {
+        self.data_dir.join(SYNC_STATE_FILE_NAME)
+    }
+
+    pub fn log_file(&self) -> PathBuf {
+        self.data_dir.join(LOG_FILE_NAME)
+    }
+
+    /// Paths stored in the program options are relative to the data folder, absolute paths are returned unchanged
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.data_dir.join(path)
+        }
+    }
+}
+
+/// Returns the folder given after `--data-dir`, either as the next argument or joined with an `=`
+fn get_data_dir_arg(args: impl IntoIterator<Item = String>) -> Option<PathBuf> {
+    let mut args = args.into_iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == DATA_DIR_ARG {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(dir) = arg
+            .strip_prefix(DATA_DIR_ARG)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            return Some(PathBuf::from(dir));
+        }
+    }
+    None
+}
+
+/// Returns the folder containing the executable if a portable marker file is next to it
+fn get_portable_dir() -> Option<PathBuf> {
+    let exe_dir = env::current_exe().ok()?.parent()?.to_path_buf();
+    exe_dir
+        .join(PORTABLE_MARKER_FILE_NAME)
+        .exists()
+        .then_some(exe_dir)
+}
+
+#[derive(Debug, Clone)]
+pub enum MigrationOutcome {
+    Moved,
+    /// The file was left in place, as a file already exists at the destination
+    AlreadyExists,
+    Failed(String),
+}
+
+/// A single file found in the working directory, and what happened when moving it into the data folder
+#[derive(Debug, Clone)]
+pub struct MigratedFile {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub outcome: MigrationOutcome,
+}
+
+/// Moves save files left in the working directory by older versions of happy chart into the data folders.
+/// Backups are moved from every relative backup folder in the migrated program options, as those were relative to the working directory then and are relative to the data folder now.
+/// Files that already exist in the data folders are never overwritten.
+/// In portable mode the files are looked for next to the executable rather than in the working directory, and a folder given with `--data-dir` is used as is, without migrating anything into it.
+#[tracing::instrument]
+pub fn migrate_working_directory_files(data_paths: &DataPaths) -> Vec<MigratedFile> {
+    let working_dir = match data_paths.location() {
+        DataLocation::CommandLine => return vec![],
+        DataLocation::Portable => get_portable_dir(),
+        DataLocation::Platform => env::current_dir().ok(),
+    };
+    let Some(working_dir) = working_dir else {
+        return vec![];
+    };
+
+    let previous_generation =
+        |file_name: &str| format!("{}.{}", file_name, PREVIOUS_GENERATION_EXTENSION);
+
+    let candidates = vec![
+        (PathBuf::from(SAVE_FILE_NAME), data_paths.data_dir()),
+        (PathBuf::from(NEW_SAVE_FILE_NAME), data_paths.data_dir()),
+        (
+            PathBuf::from(previous_generation(NEW_SAVE_FILE_NAME)),
+            data_paths.data_dir(),
+        ),
+        (
+            PathBuf::from(LAST_SESSION_FILE_NAME),
+            data_paths.config_dir(),
+        ),
+        (
+            PathBuf::from(previous_generation(LAST_SESSION_FILE_NAME)),
+            data_paths.config_dir(),
+        ),
+        (PathBuf::from(LOG_FILE_NAME), data_paths.data_dir()),
+    ];
+
+    let mut migrated = move_into_data_dirs(&working_dir, candidates);
+
+    // backups are moved once the last session is in the data folder, so the backup folders it configures are known.
+    // Versions that stored their files in the working directory never encrypted the program options, so the destinations can be read in plain text.
+    let mut backup_dirs = vec![BackupDestination::default().path];
+    let program_options = read_last_session_save_file(data_paths).program_options;
+    for destination in program_options.get_backup_destinations() {
+        if destination.path.is_relative() && !backup_dirs.contains(&destination.path) {
+            backup_dirs.push(destination.path.clone());
+        }
+    }
+    let backup_candidates = backup_dirs
+        .iter()
+        .flat_map(|backup_dir| {
+            let target_dir = data_paths.resolve(backup_dir);
+            list_backup_files(&working_dir.join(backup_dir))
+                .into_iter()
+                .map(move |file_name| (backup_dir.join(file_name), target_dir.clone()))
+        })
+        .collect::<Vec<(PathBuf, PathBuf)>>();
+    migrated.extend(move_into_data_dirs(&working_dir, backup_candidates));
+    migrated
+}
+
+/// Moves each file from its path relative to the working directory into the target folder, keeping the file name.
+/// Files already in place, or that are not there to move, are skipped.
+fn move_into_data_dirs(
+    working_dir: &Path,
+    candidates: Vec<(PathBuf, impl AsRef<Path>)>,
+) -> Vec<MigratedFile> {
+    candidates
+        .into_iter()
+        .filter_map(|(relative_path, target_dir)| {
+            let from = working_dir.join(&relative_path);
+            let to = target_dir.as_ref().join(relative_path.file_name()?);
+            if !from.is_file() || is_same_file(&from, &to) {
+                return None;
+            }
+            let outcome = move_file(&from, &to);
+            info!("Migrating {:?} to {:?}: {:?}", from, to, outcome);
+            Some(MigratedFile { from, to, outcome })
+        })
+        .collect()
+}
+
+/// Returns the names of the backup files in a folder, a configured backup folder may also hold files that are not backups, which are left alone
+fn list_backup_files(dir: &Path) -> Vec<std::ffi::OsString> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_file())
+        .map(|entry| entry.file_name())
+        .filter(|file_name| {
+            let file_name = file_name.to_string_lossy();
+            file_name.starts_with(BACKUP_FILENAME_PREFIX)
+                && file_name.ends_with(BACKUP_FILE_EXTENSION)
+        })
+        .collect()
+}
+
+fn is_same_file(path1: &Path, path2: &Path) -> bool {
+    match (path1.canonicalize(), path2.canonicalize()) {
+        (Ok(path1), Ok(path2)) => path1 == path2,
+        _ => false,
+    }
+}
+
+/// Moves a file, falling back to copying and removing it when the destination is on another drive
+fn move_file(from: &Path, to: &Path) -> MigrationOutcome {
+    if to.exists() {
+        return MigrationOutcome::AlreadyExists;
+    }
+
+    if let Some(parent) = to.parent() {
+        if let Err(err) = fs::create_dir_all(parent) {
+            return MigrationOutcome::Failed(err.to_string());
+        }
+    }
+
+    if fs::rename(from, to).is_ok() {
+        return MigrationOutcome::Moved;
+    }
+
+    match fs::copy(from, to).and_then(|_| fs::remove_file(from)) {
+        Ok(()) => MigrationOutcome::Moved,
+        Err(err) => MigrationOutcome::Failed(err.to_string()),
+    }
+}
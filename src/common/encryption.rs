@@ -1,57 +1,418 @@
+use crate::common::save_format::deserialize_save_data;
 use crate::prelude::{HappyChartState, ImprovedDayStat};
 use crate::state::error_states::HappyChartError;
-use crate::{MAX_ENCRYPT_KEY_LENGTH, MIN_ENCRYPT_KEY_LENGTH};
+use crate::{
+    MAX_ENCRYPT_KEY_LENGTH, MAX_KEY_DERIVATION_ITERATIONS, MAX_KEY_DERIVATION_MEMORY_KIB,
+    MAX_KEY_DERIVATION_PARALLELISM, MIN_ENCRYPT_KEY_LENGTH,
+};
+use argon2::{Algorithm, Argon2, Params, Version};
 use cocoon::MiniCocoon;
-use tracing::info;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+/// Marks the start of an encryption header, containers without it were encrypted with the legacy zero padded key
+const ENCRYPTION_HEADER_MAGIC: &[u8; 4] = b"HCE\0";
+const ENCRYPTION_HEADER_VERSION: u8 = 1;
+const SALT_SIZE: usize = 16;
+const KEY_SIZE: usize = 32;
+/// Every write uses a new salt, so only the most recently derived keys are kept
+const MAX_CACHED_KEYS: usize = 8;
+/// Magic, version, three little endian `u32` cost settings, and the salt
+const ENCRYPTION_HEADER_SIZE: usize = ENCRYPTION_HEADER_MAGIC.len() + 1 + 3 * 4 + SALT_SIZE;
+
+/// Argon2id cost settings used when deriving an encryption key from a password
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyDerivationSettings {
+    /// Memory used while deriving a key, in kibibytes
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl KeyDerivationSettings {
+    /// Returns true if every cost setting is within the limits happy chart derives keys with
+    pub fn is_within_limits(&self) -> bool {
+        self.memory_kib <= MAX_KEY_DERIVATION_MEMORY_KIB
+            && self.iterations <= MAX_KEY_DERIVATION_ITERATIONS
+            && self.parallelism <= MAX_KEY_DERIVATION_PARALLELISM
+    }
+}
+
+impl Default for KeyDerivationSettings {
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+/// Written in front of every encrypted container, describes how the key for the container is derived from the password
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct EncryptionHeader {
+    settings: KeyDerivationSettings,
+    salt: [u8; SALT_SIZE],
+}
+
+impl EncryptionHeader {
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(ENCRYPTION_HEADER_SIZE);
+        bytes.extend_from_slice(ENCRYPTION_HEADER_MAGIC);
+        bytes.push(ENCRYPTION_HEADER_VERSION);
+        bytes.extend_from_slice(&self.settings.memory_kib.to_le_bytes());
+        bytes.extend_from_slice(&self.settings.iterations.to_le_bytes());
+        bytes.extend_from_slice(&self.settings.parallelism.to_le_bytes());
+        bytes.extend_from_slice(&self.salt);
+        bytes
+    }
+
+    /// Splits encrypted data into its header and container, returns None if the data has no header and so uses the legacy format.
+    /// Cost settings above the limits are rejected, so a damaged or crafted header can not make deriving the key use unbounded memory and time.
+    fn parse(data: &[u8]) -> Option<Result<(Self, &[u8]), HappyChartError>> {
+        let rest = data.strip_prefix(ENCRYPTION_HEADER_MAGIC)?;
+        let (&version, rest) = rest.split_first()?;
+        if version != ENCRYPTION_HEADER_VERSION {
+            return Some(Err(HappyChartError::UnsupportedEncryptionHeader(version)));
+        }
+        if rest.len() < ENCRYPTION_HEADER_SIZE - ENCRYPTION_HEADER_MAGIC.len() - 1 {
+            return None;
+        }
+
+        let (settings_bytes, rest) = rest.split_at(3 * 4);
+        let read_u32 = |index: usize| {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&settings_bytes[index * 4..index * 4 + 4]);
+            u32::from_le_bytes(bytes)
+        };
+        let (salt_bytes, container) = rest.split_at(SALT_SIZE);
+        let mut salt = [0u8; SALT_SIZE];
+        salt.copy_from_slice(salt_bytes);
+
+        let settings = KeyDerivationSettings {
+            memory_kib: read_u32(0),
+            iterations: read_u32(1),
+            parallelism: read_u32(2),
+        };
+        if !settings.is_within_limits() {
+            warn!(
+                "Encryption header has key derivation settings above the limits: {:?}",
+                settings
+            );
+            return Some(Err(HappyChartError::UnsupportedKeyDerivationSettings(
+                settings,
+            )));
+        }
+
+        Some(Ok((Self { settings, salt }, container)))
+    }
+}
+
+/// Keys already derived for a header, shared between clones of a cipher
+type DerivedKeyCache = Arc<Mutex<Vec<(EncryptionHeader, [u8; KEY_SIZE])>>>;
+
+/// Encrypts and decrypts save data with a password.
+/// Keys are derived from the password using Argon2id and a random salt, which is stored in a header in front of the encrypted data.
+/// Derived keys are cached, as deriving them is deliberately slow.
+/// Clones share the cache, so a key derived on the persistence worker is not derived again on the UI thread.
+#[derive(Clone)]
+pub struct SaveCipher {
+    password: String,
+    /// The header used for everything encrypted by this cipher, a new salt is chosen for every write with `with_new_salt`
+    header: EncryptionHeader,
+    derived_keys: DerivedKeyCache,
+}
+
+impl SaveCipher {
+    pub fn new(password: &str, settings: KeyDerivationSettings) -> Self {
+        Self {
+            password: password.to_string(),
+            header: EncryptionHeader {
+                settings,
+                salt: rand::random(),
+            },
+            derived_keys: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Returns a cipher for the same password and settings with a new salt, keys already derived are shared with it
+    pub fn with_new_salt(&self) -> Self {
+        Self {
+            password: self.password.clone(),
+            header: EncryptionHeader {
+                settings: self.header.settings,
+                salt: rand::random(),
+            },
+            derived_keys: Arc::clone(&self.derived_keys),
+        }
+    }
+
+    /// Returns true if this cipher encrypts using the given password and settings
+    pub fn is_for(&self, password: &str, settings: KeyDerivationSettings) -> bool {
+        self.password == password && self.header.settings == settings
+    }
+
+    #[tracing::instrument(skip_all)]
+    fn derive_key(&self, header: &EncryptionHeader) -> Result<[u8; KEY_SIZE], HappyChartError> {
+        let cached_key = self.derived_keys.lock().ok().and_then(|derived_keys| {
+            derived_keys
+                .iter()
+                .find(|(cached_header, _)| cached_header == header)
+                .map(|(_, key)| *key)
+        });
+        if let Some(key) = cached_key {
+            return Ok(key);
+        }
+
+        info!(
+            "Deriving encryption key with settings: {:?}",
+            header.settings
+        );
+        let params = Params::new(
+            header.settings.memory_kib,
+            header.settings.iterations,
+            header.settings.parallelism,
+            Some(KEY_SIZE),
+        )
+        .map_err(HappyChartError::KeyDerivation)?;
+        let mut key = [0u8; KEY_SIZE];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(self.password.as_bytes(), &header.salt, &mut key)
+            .map_err(HappyChartError::KeyDerivation)?;
+
+        if let Ok(mut derived_keys) = self.derived_keys.lock() {
+            if derived_keys.len() >= MAX_CACHED_KEYS {
+                derived_keys.remove(0);
+            }
+            derived_keys.push((*header, key));
+        }
+        Ok(key)
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, HappyChartError> {
+        let key = self.derive_key(&self.header)?;
+        // a random seed is used so every container is given a unique nonce
+        let mut cocoon = MiniCocoon::from_key(&key, &rand::random::<[u8; 32]>());
+        let container = cocoon
+            .wrap(data)
+            .map_err(HappyChartError::EncryptionError)?;
+
+        let mut encrypted = self.header.to_bytes();
+        encrypted.extend_from_slice(&container);
+        Ok(encrypted)
+    }
+
+    /// Decrypts data encrypted by any cipher using the same password, as well as data encrypted with the legacy zero padded key
+    #[tracing::instrument(skip_all)]
+    pub fn decrypt(&self, encrypted_data: &[u8]) -> Result<Vec<u8>, HappyChartError> {
+        match EncryptionHeader::parse(encrypted_data) {
+            Some(header) => {
+                let (header, container) = header?;
+                let key = self.derive_key(&header)?;
+                MiniCocoon::from_key(&key, &[0; 32])
+                    .unwrap(container)
+                    .map_err(HappyChartError::DecryptionError)
+            }
+            None => {
+                warn!("Decrypting data encrypted with the legacy key, it will be upgraded on the next save");
+                MiniCocoon::from_key(&get_legacy_key_bytes(&self.password)[0..32], &[0; 32])
+                    .unwrap(encrypted_data)
+                    .map_err(HappyChartError::DecryptionError)
+            }
+        }
+    }
+}
+
+/// Pads the key with ascii zeros so it is always at least 32 bytes long, only used to read data encrypted before key derivation was added
+fn get_legacy_key_bytes(key: &str) -> Vec<u8> {
+    let mut key = key.to_string();
+    if key.len() < 32 {
+        key.push_str("00000000000000000000000000000000");
+    }
+    key.into_bytes()
+}
 
 #[tracing::instrument(skip_all)]
 pub fn decrypt_save_file(
-    app: &HappyChartState,
+    cipher: &SaveCipher,
     encrypted_data: &[u8],
 ) -> Result<Vec<ImprovedDayStat>, HappyChartError> {
     info!("Decrypting save file");
-    let mut key = app.encryption_key.to_string();
-    if key.len() < 32 {
-        key.push_str("00000000000000000000000000000000");
-    }
-
-    let cocoon = MiniCocoon::from_key(&key.as_bytes()[0..32], &[0; 32]);
-    let unwrapped = cocoon
-        .unwrap(encrypted_data)
-        .map_err(HappyChartError::DecryptionError)?;
+    let unwrapped = cipher.decrypt(encrypted_data)?;
 
     info!("Successfully decrypted save file, deserializing now.");
 
-    serde_json::from_slice::<Vec<ImprovedDayStat>>(&unwrapped)
-        .map_err(|err| HappyChartError::Deserialization(err, None))
+    deserialize_save_data(&unwrapped).map(|(_, days)| days)
+}
+
+/// Returns true if the given bytes are shaped like an encrypted container, with or without an encryption header.
+/// A `MiniCocoon` container is a 12 byte nonce, an 8 byte big endian payload length, a 16 byte tag, and then the payload itself.
+pub fn is_encrypted_container(data: &[u8]) -> bool {
+    const NONCE_SIZE: usize = 12;
+    const LENGTH_SIZE: usize = 8;
+    const TAG_SIZE: usize = 16;
+
+    let data = match EncryptionHeader::parse(data) {
+        Some(Ok((_, container))) => container,
+        Some(Err(_)) => return true,
+        None => data,
+    };
+
+    let Some(length_bytes) = data.get(NONCE_SIZE..NONCE_SIZE + LENGTH_SIZE) else {
+        return false;
+    };
+    let mut length = [0u8; LENGTH_SIZE];
+    length.copy_from_slice(length_bytes);
+
+    usize::try_from(u64::from_be_bytes(length))
+        .ok()
+        .and_then(|payload_length| payload_length.checked_add(NONCE_SIZE + LENGTH_SIZE + TAG_SIZE))
+        .is_some_and(|container_length| container_length == data.len())
 }
 
 #[tracing::instrument(skip_all)]
 pub fn encryption_save_file_checks(app: &HappyChartState) -> Result<(), HappyChartError> {
+    encryption_key_checks(&app.encryption_key, &app.encryption_key_second_check)
+}
+
+/// Checks that a key and its second entry match, and are within the allowed key lengths
+#[tracing::instrument(skip_all)]
+pub fn encryption_key_checks(key: &str, key_second_check: &str) -> Result<(), HappyChartError> {
     // keys are not the same
-    if app.encryption_key.ne(&app.encryption_key_second_check) {
+    if key.ne(key_second_check) {
         return Err(HappyChartError::EncryptionKeysDontMatch);
     }
 
     // either key is too short
-    if app.encryption_key.len() < MIN_ENCRYPT_KEY_LENGTH
-        && app.encryption_key_second_check.len() < MIN_ENCRYPT_KEY_LENGTH
-    {
+    if key.len() < MIN_ENCRYPT_KEY_LENGTH && key_second_check.len() < MIN_ENCRYPT_KEY_LENGTH {
         return Err(HappyChartError::EncryptKeyTooShort {
-            primary_key_problem: app.encryption_key.len() < MIN_ENCRYPT_KEY_LENGTH,
-            secondary_key_problem: app.encryption_key_second_check.len() < MIN_ENCRYPT_KEY_LENGTH,
+            primary_key_problem: key.len() < MIN_ENCRYPT_KEY_LENGTH,
+            secondary_key_problem: key_second_check.len() < MIN_ENCRYPT_KEY_LENGTH,
         });
     }
 
     // either key is too long
-    if app.encryption_key.len() > MAX_ENCRYPT_KEY_LENGTH
-        && app.encryption_key_second_check.len() > MAX_ENCRYPT_KEY_LENGTH
-    {
+    if key.len() > MAX_ENCRYPT_KEY_LENGTH && key_second_check.len() > MAX_ENCRYPT_KEY_LENGTH {
         return Err(HappyChartError::EncryptKeyTooLong {
-            primary_key_problem: app.encryption_key.len() > MAX_ENCRYPT_KEY_LENGTH,
-            secondary_key_problem: app.encryption_key_second_check.len() > MAX_ENCRYPT_KEY_LENGTH,
+            primary_key_problem: key.len() > MAX_ENCRYPT_KEY_LENGTH,
+            secondary_key_problem: key_second_check.len() > MAX_ENCRYPT_KEY_LENGTH,
         });
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::save::write_save_file;
+    use crate::common::save_format::serialize_save_data;
+    use std::{env, fs};
+
+    /// The smallest costs Argon2 accepts, so the tests do not spend their time deriving keys
+    const TEST_SETTINGS: KeyDerivationSettings = KeyDerivationSettings {
+        memory_kib: 8,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn encryption_header_round_trips() {
+        let header = EncryptionHeader {
+            settings: KeyDerivationSettings::default(),
+            salt: rand::random(),
+        };
+        let mut data = header.to_bytes();
+        assert_eq!(data.len(), ENCRYPTION_HEADER_SIZE);
+        data.extend_from_slice(b"container");
+
+        let (parsed, container) = EncryptionHeader::parse(&data).unwrap().unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(container, b"container");
+
+        // data without the magic is a legacy container
+        assert!(EncryptionHeader::parse(b"container").is_none());
+    }
+
+    #[test]
+    fn encryption_header_rejects_costs_above_the_limits() {
+        let oversized = [
+            KeyDerivationSettings {
+                memory_kib: MAX_KEY_DERIVATION_MEMORY_KIB + 1,
+                ..TEST_SETTINGS
+            },
+            KeyDerivationSettings {
+                iterations: MAX_KEY_DERIVATION_ITERATIONS + 1,
+                ..TEST_SETTINGS
+            },
+            KeyDerivationSettings {
+                parallelism: MAX_KEY_DERIVATION_PARALLELISM + 1,
+                ..TEST_SETTINGS
+            },
+        ];
+        for settings in oversized {
+            let header = EncryptionHeader {
+                settings,
+                salt: rand::random(),
+            };
+            let data = header.to_bytes();
+            let result = EncryptionHeader::parse(&data).unwrap();
+            assert!(matches!(
+                result,
+                Err(HappyChartError::UnsupportedKeyDerivationSettings(rejected)) if rejected == settings
+            ));
+        }
+    }
+
+    #[test]
+    fn legacy_save_file_is_resaved_with_a_header() {
+        let password = "correct horse battery staple";
+        let (_, days) = deserialize_save_data(
+            br#"[{"rating":10.0,"date":1700000000,"note":"first"},{"rating":20.0,"date":1700086400,"note":"second"}]"#,
+        )
+        .unwrap();
+        let serialized = serialize_save_data(&days, &[], None).unwrap();
+        let legacy_file = MiniCocoon::from_key(&get_legacy_key_bytes(password)[0..32], &[0; 32])
+            .wrap(&serialized)
+            .unwrap();
+
+        let cipher = SaveCipher::new(password, TEST_SETTINGS);
+        let decrypted = decrypt_save_file(&cipher, &legacy_file).unwrap();
+        assert_eq!(decrypted.len(), days.len());
+
+        let dir = env::temp_dir().join(format!(
+            "happy_chart_legacy_encryption_test_{}",
+            rand::random::<u64>()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("save.json");
+        write_save_file(&path, &decrypted, &[], Some(&cipher)).unwrap();
+        let resaved = fs::read(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let (header, _) = EncryptionHeader::parse(&resaved).unwrap().unwrap();
+        assert_eq!(header.settings, TEST_SETTINGS);
+        let reread =
+            decrypt_save_file(&SaveCipher::new(password, TEST_SETTINGS), &resaved).unwrap();
+        for (day, reread_day) in days.iter().zip(&reread) {
+            assert_eq!(day.get_id(), reread_day.get_id());
+            assert_eq!(day.get_note(), reread_day.get_note());
+        }
+    }
+
+    #[test]
+    fn every_write_gets_a_new_salt() {
+        let cipher = SaveCipher::new("correct horse battery staple", TEST_SETTINGS);
+        let first = cipher.encrypt(b"day list").unwrap();
+        let second = cipher.with_new_salt().encrypt(b"day list").unwrap();
+        let (first_header, _) = EncryptionHeader::parse(&first).unwrap().unwrap();
+        let (second_header, _) = EncryptionHeader::parse(&second).unwrap().unwrap();
+        assert_ne!(first_header.salt, second_header.salt);
+
+        // both can still be read with the password
+        assert_eq!(cipher.decrypt(&second).unwrap(), b"day list");
+        assert_eq!(cipher.with_new_salt().decrypt(&first).unwrap(), b"day list");
+    }
+}
@@ -9,7 +9,8 @@ pub fn export_stats_to_csv(path: PathBuf, app: &mut HappyChartState) {
     match csv::WriterBuilder::new().from_path(&path) {
         Ok(mut export_writer) => {
             app.days.iter().for_each(|day_stat| {
-                let written_data: &[String; 5] = &[
+                let written_data: &[String; 6] = &[
+                    day_stat.get_id().to_string(),
                     day_stat.get_date().to_string(),
                     day_stat.get_rating().to_string(),
                     day_stat.get_note().to_string(),
@@ -0,0 +1,219 @@
+use crate::common::data_paths::DataPaths;
+use crate::common::encryption::SaveCipher;
+use crate::common::persistence::PersistenceJob;
+use crate::common::restore::RestorePreview;
+use crate::day_stats::improved_daystat::assign_unique_day_ids;
+use crate::prelude::{HappyChartState, ImprovedDayStat};
+use crate::state::error_states::HappyChartError;
+use crate::storage::{open_storage, StorageBackendKind};
+use crate::SAVE_FILE_CHECK_SECONDS;
+use egui::Context;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// The save file as this copy of happy chart last read or wrote it, shared with the persistence worker so its own writes are not mistaken for another program changing the file
+#[derive(Debug, Clone, Default)]
+pub struct SaveFileWatch {
+    known_version: Arc<Mutex<Option<(PathBuf, SystemTime)>>>,
+}
+
+impl SaveFileWatch {
+    /// Records the save file at `path` as it is on disk now, returns false if it does not exist
+    pub fn record(&self, path: &Path) -> bool {
+        let Some(modified) = get_modified_time(path) else {
+            return false;
+        };
+        if let Ok(mut known_version) = self.known_version.lock() {
+            *known_version = Some((path.to_path_buf(), modified));
+        }
+        true
+    }
+
+    /// Returns true if the save file at `path` was changed since it was last recorded.
+    /// A save file that was never recorded, such as one just migrated to, counts as unchanged.
+    pub fn has_changed(&self, path: &Path) -> bool {
+        let Ok(known_version) = self.known_version.lock() else {
+            return false;
+        };
+        match (&*known_version, get_modified_time(path)) {
+            (Some((known_path, known_modified)), Some(modified)) => {
+                known_path == path && *known_modified != modified
+            }
+            _ => false,
+        }
+    }
+}
+
+fn get_modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Returns an error if the save file at `path` was changed by another program since this copy of happy chart last read or wrote it
+#[tracing::instrument(skip(save_file_watch))]
+pub fn check_save_file_unchanged(
+    save_file_watch: &SaveFileWatch,
+    path: &Path,
+) -> Result<(), HappyChartError> {
+    if save_file_watch.has_changed(path) {
+        warn!("Save file was changed by another program: {:?}", path);
+        return Err(HappyChartError::SaveFileChangedExternally(
+            path.to_path_buf(),
+        ));
+    }
+    Ok(())
+}
+
+/// What the day list read from disk after another program changed the save file holds, shown until the user chooses which version to keep
+#[derive(Debug)]
+pub struct ExternalChange {
+    pub days: Vec<ImprovedDayStat>,
+    /// Differences going from the day list in this window to the day list on disk
+    pub preview: RestorePreview,
+    /// Ids of days in both versions whose version on disk is kept when combining them, the version in this window is kept otherwise
+    pub use_disk_version: BTreeSet<Uuid>,
+}
+
+/// Everything needed to read the save file again on the persistence worker
+pub struct SaveFileCheckJob {
+    pub data_paths: DataPaths,
+    pub storage_backend: StorageBackendKind,
+    pub save_cipher: Option<SaveCipher>,
+    pub save_file_watch: SaveFileWatch,
+}
+
+/// Reads the day list from the save file, recording the version read so it is only offered once
+#[tracing::instrument(skip(job))]
+pub fn run_save_file_check_job(
+    job: SaveFileCheckJob,
+) -> Result<Vec<ImprovedDayStat>, HappyChartError> {
+    let path = job.storage_backend.get_save_file_path(&job.data_paths);
+    info!("Reading save file changed by another program: {:?}", path);
+    job.save_file_watch.record(&path);
+    let mut days = open_storage(&job.data_paths, job.storage_backend, job.save_cipher)?.load()?;
+    assign_unique_day_ids(&mut days);
+    Ok(days)
+}
+
+/// Regularly checks whether another program changed the save file, and queues reading it again if so
+#[tracing::instrument(skip(ctx, app))]
+pub fn check_for_external_save_file_change(ctx: &Context, app: &mut HappyChartState) {
+    let check_interval = Duration::from_secs(SAVE_FILE_CHECK_SECONDS);
+    ctx.request_repaint_after(check_interval);
+    if !app
+        .last_save_file_check
+        .elapsed()
+        .is_ok_and(|elapsed| elapsed >= check_interval)
+    {
+        return;
+    }
+    app.last_save_file_check = SystemTime::now();
+
+    // a pending write of our own changes the save file, so it is checked once it has finished
+    let save_locked = app
+        .error_states
+        .iter()
+        .any(|err| matches!(err, HappyChartError::EncryptedSaveFile(_)));
+    if app.external_change.is_some() || save_locked || !app.persistence.is_idle() {
+        return;
+    }
+
+    let path = app
+        .program_options
+        .storage_backend
+        .get_save_file_path(&app.data_paths);
+    if app.save_file_watch.has_changed(&path) {
+        queue_save_file_check(ctx, app);
+    }
+}
+
+/// Queues reading the save file again, the user is asked which version to keep if it differs from the day list in this window
+#[tracing::instrument(skip(ctx, app))]
+pub fn queue_save_file_check(ctx: &Context, app: &mut HappyChartState) {
+    let job = SaveFileCheckJob {
+        data_paths: app.data_paths.clone(),
+        storage_backend: app.program_options.storage_backend,
+        save_cipher: app.get_save_cipher(),
+        save_file_watch: app.save_file_watch.clone(),
+    };
+    app.persistence
+        .queue(ctx, PersistenceJob::CheckSaveFile(job));
+}
+
+/// Offers the day list read from disk to the user, if it differs from the day list in this window
+#[tracing::instrument(skip(app, days))]
+pub fn offer_external_change(app: &mut HappyChartState, days: Vec<ImprovedDayStat>) {
+    let preview = RestorePreview::new(&app.days, &days);
+    if preview.added.is_empty() && preview.removed.is_empty() && preview.changed.is_empty() {
+        info!("Save file was changed by another program, but its days are the same");
+        return;
+    }
+    info!(
+        "Save file was changed by another program: {} added, {} removed, {} changed",
+        preview.added.len(),
+        preview.removed.len(),
+        preview.changed.len()
+    );
+    app.external_change = Some(ExternalChange {
+        days,
+        preview,
+        use_disk_version: BTreeSet::new(),
+    });
+}
+
+/// Keeps the day list in this window, it is saved over the save file on disk by the next save
+#[tracing::instrument(skip(app))]
+pub fn keep_local_version(app: &mut HappyChartState) {
+    if app.external_change.take().is_some() {
+        app.mark_changed();
+    }
+}
+
+/// Replaces the day list in this window with the day list on disk, days the entry edit policy has made read-only are kept as they are
+#[tracing::instrument(skip(app))]
+pub fn keep_disk_version(app: &mut HappyChartState) {
+    if let Some(external_change) = app.external_change.take() {
+        let mut days = external_change.days;
+        let kept_count = app.keep_locked_days(&mut days);
+        app.replace_days(days);
+        if kept_count > 0 {
+            // the day list now differs from the one on disk
+            app.mark_changed();
+        }
+    }
+}
+
+/// Keeps every day from both versions, days in both use the version chosen for them in `use_disk_version`.
+/// Days the entry edit policy has made read-only keep the version in this window.
+#[tracing::instrument(skip(app))]
+pub fn combine_versions(app: &mut HappyChartState) {
+    let Some(external_change) = app.external_change.take() else {
+        return;
+    };
+    let local_ids = app
+        .days
+        .iter()
+        .map(|day| *day.get_id())
+        .collect::<BTreeSet<Uuid>>();
+
+    let mut days = app
+        .days
+        .iter()
+        .filter(|day| !external_change.use_disk_version.contains(day.get_id()))
+        .cloned()
+        .collect::<Vec<ImprovedDayStat>>();
+    days.extend(external_change.days.into_iter().filter(|day| {
+        !local_ids.contains(day.get_id()) || external_change.use_disk_version.contains(day.get_id())
+    }));
+    app.keep_locked_days(&mut days);
+
+    app.replace_days(days);
+    // the combined day list is in neither version, so it is saved over the one on disk
+    app.mark_changed();
+}
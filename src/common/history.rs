@@ -0,0 +1,339 @@
+use crate::day_stats::improved_daystat::ImprovedDayStat;
+use crate::day_stats::trashed_day::TrashedDay;
+use crate::prelude::HappyChartState;
+use crate::state::activities::Activity;
+use crate::state::error_states::HappyChartError;
+use crate::{EDIT_HISTORY_LIMIT, EDIT_HISTORY_MERGE_SECONDS};
+use chrono::{DateTime, Local};
+use egui::{Context, Key, Modifiers};
+use std::collections::VecDeque;
+use tracing::{error, info};
+
+/// Which part of a day an edit changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DayEditKind {
+    Rating,
+    Moods,
+    Activities,
+    Date,
+    Note,
+    /// Reverted to an earlier revision of the day
+    Revert,
+}
+
+/// A change to the journal that can be undone and redone
+#[derive(Debug, Clone)]
+pub enum JournalCommand {
+    AddDay(ImprovedDayStat),
+    RemoveDay(ImprovedDayStat),
+    /// Replaces a day with an edited copy, the whole day is kept from before and after the edit so any edit can be undone
+    EditDay {
+        kind: DayEditKind,
+        before: ImprovedDayStat,
+        after: ImprovedDayStat,
+    },
+    /// Moves a day from the journal into the trash
+    TrashDay(ImprovedDayStat),
+    /// Moves a day from the trash back into the journal
+    RestoreDay(ImprovedDayStat),
+    AddActivity(Activity),
+    RemoveActivity(Activity),
+}
+
+impl JournalCommand {
+    /// Returns the command that undoes this command
+    fn inverse(&self) -> Self {
+        match self {
+            Self::AddDay(day) => Self::RemoveDay(day.clone()),
+            Self::RemoveDay(day) => Self::AddDay(day.clone()),
+            Self::EditDay {
+                kind,
+                before,
+                after,
+            } => Self::EditDay {
+                kind: *kind,
+                before: after.clone(),
+                after: before.clone(),
+            },
+            Self::TrashDay(day) => Self::RestoreDay(day.clone()),
+            Self::RestoreDay(day) => Self::TrashDay(day.clone()),
+            Self::AddActivity(activity) => Self::RemoveActivity(activity.clone()),
+            Self::RemoveActivity(activity) => Self::AddActivity(activity.clone()),
+        }
+    }
+
+    /// Applies the command to the journal, returns false if it no longer applies, such as when the day it changes has since been removed
+    fn apply(&self, app: &mut HappyChartState) -> bool {
+        match self {
+            Self::AddDay(day) => {
+                if app.get_day(day.get_id()).is_some() {
+                    return false;
+                }
+                app.days.push(day.clone());
+                app.days.sort_by_key(|day| *day.get_date());
+                true
+            }
+            Self::RemoveDay(day) => app.remove_day(day.get_id()).is_some(),
+            Self::EditDay { after, .. } => match app.get_day_mut(after.get_id()) {
+                Some(day) => {
+                    // undoing or redoing an edit is an edit of its own, so it is added to the revisions rather than replacing them
+                    day.edit_to_match(after);
+                    // the edit may have moved the day to another date
+                    app.days.sort_by_key(|day| *day.get_date());
+                    true
+                }
+                None => false,
+            },
+            Self::TrashDay(day) => match app.remove_day(day.get_id()) {
+                Some(day) => {
+                    app.trash.push(TrashedDay::new(day));
+                    true
+                }
+                None => false,
+            },
+            Self::RestoreDay(day) => {
+                // a day purged from the trash can not be restored
+                if app.get_day(day.get_id()).is_some() {
+                    return false;
+                }
+                let Some(trashed) = app.take_from_trash(day.get_id()) else {
+                    return false;
+                };
+                app.days.push(trashed.day);
+                app.days.sort_by_key(|day| *day.get_date());
+                true
+            }
+            Self::AddActivity(activity) => {
+                let activity_list = &mut app.program_options.activity_list;
+                if activity_list.get_activity_list().contains(activity) {
+                    return false;
+                }
+                activity_list.add_new_activity(activity.clone());
+                true
+            }
+            Self::RemoveActivity(activity) => {
+                let activity_list = &mut app.program_options.activity_list;
+                if !activity_list.get_activity_list().contains(activity) {
+                    return false;
+                }
+                activity_list.remove_activity(activity);
+                true
+            }
+        }
+    }
+
+    /// Returns the day in the journal the command would change or remove, if it changes one
+    fn get_changed_day<'a>(&self, app: &'a HappyChartState) -> Option<&'a ImprovedDayStat> {
+        match self {
+            Self::RemoveDay(day) | Self::TrashDay(day) | Self::EditDay { after: day, .. } => {
+                app.get_day(day.get_id())
+            }
+            Self::AddDay(_)
+            | Self::RestoreDay(_)
+            | Self::AddActivity(_)
+            | Self::RemoveActivity(_) => None,
+        }
+    }
+
+    /// Returns an error if the command would change or remove a day the entry edit policy has made read-only, or add a day at a date it has made read-only
+    fn check_entry_lock(&self, app: &HappyChartState) -> Result<(), HappyChartError> {
+        if let Self::AddDay(day) = self {
+            if app.is_entry_locked(day) {
+                return Err(HappyChartError::EntryLocked(*day.get_date()));
+            }
+        }
+        match self.get_changed_day(app) {
+            Some(day) if app.is_entry_locked(day) => {
+                Err(HappyChartError::EntryLocked(*day.get_date()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// A short description of the command, shown in the history panel
+    pub fn get_description(&self) -> String {
+        let format_date =
+            |day: &ImprovedDayStat| day.get_date().format("%Y-%m-%d %H:%M").to_string();
+        match self {
+            Self::AddDay(day) => format!("Added day {}", format_date(day)),
+            Self::RemoveDay(day) => format!("Removed day {}", format_date(day)),
+            Self::EditDay { kind, after, .. } => {
+                let edited = match kind {
+                    DayEditKind::Rating => "rating",
+                    DayEditKind::Moods => "moods",
+                    DayEditKind::Activities => "activities",
+                    DayEditKind::Date => "date",
+                    DayEditKind::Note => "note",
+                    DayEditKind::Revert => {
+                        return format!(
+                            "Reverted day {} to an earlier revision",
+                            format_date(after)
+                        );
+                    }
+                };
+                format!("Changed {} of day {}", edited, format_date(after))
+            }
+            Self::TrashDay(day) => format!("Moved day {} to the trash", format_date(day)),
+            Self::RestoreDay(day) => format!("Restored day {} from the trash", format_date(day)),
+            Self::AddActivity(activity) => format!("Added activity {}", activity),
+            Self::RemoveActivity(activity) => format!("Removed activity {}", activity),
+        }
+    }
+}
+
+/// A command in the edit history, and when it was made
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub command: JournalCommand,
+    pub time: DateTime<Local>,
+}
+
+/// Commands that can be undone, newest last, and commands that were undone and can be redone, most recently undone last
+#[derive(Debug, Default)]
+pub struct EditHistory {
+    undo_stack: VecDeque<HistoryEntry>,
+    redo_stack: Vec<HistoryEntry>,
+}
+
+impl EditHistory {
+    /// Records a command that was just applied, which clears the commands that could be redone.
+    /// Repeated edits of the same part of the same day in quick succession, such as dragging a slider, are recorded as a single edit.
+    fn push(&mut self, command: JournalCommand) {
+        let now = Local::now();
+        self.redo_stack.clear();
+
+        if let (
+            Some(HistoryEntry {
+                command:
+                    JournalCommand::EditDay {
+                        kind: last_kind,
+                        after: last_after,
+                        ..
+                    },
+                time,
+            }),
+            JournalCommand::EditDay { kind, after, .. },
+        ) = (self.undo_stack.back_mut(), &command)
+        {
+            if last_kind == kind
+                && last_after.get_id() == after.get_id()
+                && now.signed_duration_since(*time).num_seconds() < EDIT_HISTORY_MERGE_SECONDS
+            {
+                *last_after = after.clone();
+                *time = now;
+                return;
+            }
+        }
+
+        self.undo_stack
+            .push_back(HistoryEntry { command, time: now });
+        if self.undo_stack.len() > EDIT_HISTORY_LIMIT {
+            self.undo_stack.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Commands that can be undone, most recent first
+    pub fn get_undo_entries(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.undo_stack.iter().rev()
+    }
+
+    /// Commands that can be redone, next to be redone first
+    pub fn get_redo_entries(&self) -> impl DoubleEndedIterator<Item = &HistoryEntry> {
+        self.redo_stack.iter().rev()
+    }
+}
+
+/// Applies a change to the journal and records it in the edit history, so it can be undone
+#[tracing::instrument(skip(app))]
+pub fn execute_command(app: &mut HappyChartState, command: JournalCommand) {
+    if let Err(err) = command.check_entry_lock(app) {
+        error!("Command blocked by the entry edit policy: {}", err);
+        app.error_states.push(err);
+        return;
+    }
+    if !command.apply(app) {
+        info!("Command no longer applies: {}", command.get_description());
+        return;
+    }
+    info!("{}", command.get_description());
+    app.edit_history.push(command);
+    finish_history_change(app);
+}
+
+/// Undoes the most recent command, returns false if there was nothing to undo or it no longer applies
+#[tracing::instrument(skip(app))]
+pub fn undo(app: &mut HappyChartState) -> bool {
+    let Some(entry) = app.edit_history.undo_stack.pop_back() else {
+        return false;
+    };
+    let inverse = entry.command.inverse();
+    if let Err(err) = inverse.check_entry_lock(app) {
+        error!("Undo blocked by the entry edit policy: {}", err);
+        app.error_states.push(err);
+        app.edit_history.undo_stack.push_back(entry);
+        return false;
+    }
+    if !inverse.apply(app) {
+        info!("Unable to undo: {}", entry.command.get_description());
+        return false;
+    }
+    info!("Undid: {}", entry.command.get_description());
+    app.edit_history.redo_stack.push(entry);
+    finish_history_change(app);
+    true
+}
+
+/// Redoes the most recently undone command, returns false if there was nothing to redo or it no longer applies
+#[tracing::instrument(skip(app))]
+pub fn redo(app: &mut HappyChartState) -> bool {
+    let Some(entry) = app.edit_history.redo_stack.pop() else {
+        return false;
+    };
+    if let Err(err) = entry.command.check_entry_lock(app) {
+        error!("Redo blocked by the entry edit policy: {}", err);
+        app.error_states.push(err);
+        app.edit_history.redo_stack.push(entry);
+        return false;
+    }
+    if !entry.command.apply(app) {
+        info!("Unable to redo: {}", entry.command.get_description());
+        return false;
+    }
+    info!("Redid: {}", entry.command.get_description());
+    app.edit_history.undo_stack.push_back(entry);
+    finish_history_change(app);
+    true
+}
+
+fn finish_history_change(app: &mut HappyChartState) {
+    app.mark_changed();
+    app.stats
+        .calc_all_stats(&app.days, app.program_options.streak_leniency);
+}
+
+/// Undoes on Ctrl+Z and redoes on Ctrl+Shift+Z, unless a text field has focus, as text fields have their own undo
+#[tracing::instrument(skip_all)]
+pub fn handle_history_shortcuts(ctx: &Context, app: &mut HappyChartState) {
+    if ctx.wants_keyboard_input() {
+        return;
+    }
+    // the redo shortcut is checked first, as consuming Ctrl+Z would also match Ctrl+Shift+Z
+    if ctx.input_mut(|i| i.consume_key(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z)) {
+        redo(app);
+    } else if ctx.input_mut(|i| i.consume_key(Modifiers::COMMAND, Key::Z)) {
+        undo(app);
+    }
+}
@@ -0,0 +1,211 @@
+use crate::common::data_paths::DataPaths;
+use crate::common::save::sibling_file_path;
+use crate::state::error_states::HappyChartError;
+use crate::{STALE_LOCK_SECONDS, TEMP_FILE_EXTENSION};
+use chrono::{DateTime, Local};
+use gethostname::gethostname;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::time::SystemTime;
+use sysinfo::{Pid, ProcessRefreshKind, System, IS_SUPPORTED_SYSTEM};
+use tracing::{info, warn};
+
+/// The running copy of happy chart holding the lock on a profiles data folder, stored in the lock file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LockOwner {
+    pub pid: u32,
+    pub hostname: String,
+    /// When the lock was taken
+    pub acquired: DateTime<Local>,
+    /// Rewritten regularly while the owner is running, so a lock left behind by a crash can be told apart from one in use
+    pub heartbeat: DateTime<Local>,
+}
+
+impl LockOwner {
+    fn current() -> Self {
+        let now = Local::now();
+        Self {
+            pid: std::process::id(),
+            hostname: get_hostname(),
+            acquired: now,
+            heartbeat: now,
+        }
+    }
+
+    fn is_current_process(&self) -> bool {
+        self.pid == std::process::id() && self.hostname == get_hostname()
+    }
+
+    /// A lock is stale if its owner has not refreshed it recently, or if its owner ran on this machine and is no longer running
+    pub fn is_stale(&self, now: DateTime<Local>) -> bool {
+        if now.signed_duration_since(self.heartbeat).num_seconds() > STALE_LOCK_SECONDS {
+            return true;
+        }
+        self.hostname == get_hostname() && is_process_running(self.pid) == Some(false)
+    }
+}
+
+/// Returns the name of this machine
+fn get_hostname() -> String {
+    gethostname().to_string_lossy().into_owned()
+}
+
+/// Returns None if it can not be known on this platform whether a process is running
+fn is_process_running(pid: u32) -> Option<bool> {
+    if !IS_SUPPORTED_SYSTEM {
+        return None;
+    }
+    Some(System::new().refresh_process_specifics(Pid::from_u32(pid), ProcessRefreshKind::new()))
+}
+
+/// Returns true if the lock file was written within the last `STALE_LOCK_SECONDS`, or if its age can not be told.
+/// A lock file that can not be read is only replaced once this is false, as it may belong to a copy of happy chart that is still writing it.
+fn lock_file_is_recent(lock_path: &Path) -> bool {
+    let Ok(modified) = fs::metadata(lock_path).and_then(|metadata| metadata.modified()) else {
+        return !matches!(fs::metadata(lock_path), Err(err) if err.kind() == ErrorKind::NotFound);
+    };
+    SystemTime::now()
+        .duration_since(modified)
+        .map_or(true, |age| age.as_secs() <= STALE_LOCK_SECONDS as u64)
+}
+
+/// Takes the lock on the data folder of the profile in use, so a second running copy of happy chart does not overwrite the first.
+/// A stale lock left behind by a crash is replaced, a lock held by another running copy returns `HappyChartError::InstanceLocked`.
+/// A lock file that can not be read counts as held until it has not been written for `STALE_LOCK_SECONDS`.
+#[tracing::instrument]
+pub fn acquire_instance_lock(data_paths: &DataPaths) -> Result<(), HappyChartError> {
+    let lock_path = data_paths.lock_file();
+    let owner = LockOwner::current();
+    let ser = serde_json::to_string(&owner).map_err(HappyChartError::Serialization)?;
+
+    // the lock is retried once after removing a stale lock, in case another copy of happy chart took the lock in between
+    for _ in 0..2 {
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(mut file) => {
+                file.write_all(ser.as_bytes())
+                    .and_then(|()| file.sync_all())
+                    .map_err(|err| HappyChartError::WriteSaveFileIO(err, lock_path.clone()))?;
+                info!("Acquired instance lock {:?}", lock_path);
+                return Ok(());
+            }
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                match read_lock_owner(&lock_path) {
+                    Some(existing) if existing.is_current_process() => return Ok(()),
+                    Some(existing) if !existing.is_stale(Local::now()) => {
+                        return Err(HappyChartError::InstanceLocked {
+                            path: lock_path,
+                            owner: Some(existing),
+                        });
+                    }
+                    None if lock_file_is_recent(&lock_path) => {
+                        warn!("Instance lock {:?} can not be read", lock_path);
+                        return Err(HappyChartError::InstanceLocked {
+                            path: lock_path,
+                            owner: None,
+                        });
+                    }
+                    existing => {
+                        warn!(
+                            "Removing stale instance lock {:?}: {:?}",
+                            lock_path, existing
+                        );
+                        match fs::remove_file(&lock_path) {
+                            Err(err) if err.kind() != ErrorKind::NotFound => {
+                                return Err(HappyChartError::WriteSaveFileIO(err, lock_path));
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            Err(err) => return Err(HappyChartError::WriteSaveFileIO(err, lock_path)),
+        }
+    }
+
+    Err(HappyChartError::InstanceLocked {
+        owner: read_lock_owner(&lock_path),
+        path: lock_path,
+    })
+}
+
+/// Rewrites the heartbeat of the lock held by this copy of happy chart.
+/// Returns `HappyChartError::InstanceLocked` if another copy took the lock over in the meantime.
+#[tracing::instrument]
+pub fn refresh_instance_lock(data_paths: &DataPaths) -> Result<(), HappyChartError> {
+    let lock_path = data_paths.lock_file();
+    let mut owner = match read_lock_owner(&lock_path) {
+        Some(owner) if owner.is_current_process() => owner,
+        Some(owner) => {
+            return Err(HappyChartError::InstanceLocked {
+                path: lock_path,
+                owner: Some(owner),
+            })
+        }
+        // the lock file was removed or damaged from under us, so it is taken again if no other copy could be writing it
+        None => return acquire_instance_lock(data_paths),
+    };
+    owner.heartbeat = Local::now();
+    write_lock_owner(&lock_path, &owner)
+}
+
+/// Replaces the lock held by another copy of happy chart with a lock held by this copy
+#[tracing::instrument]
+pub fn take_over_instance_lock(data_paths: &DataPaths) -> Result<(), HappyChartError> {
+    let lock_path = data_paths.lock_file();
+    warn!("Taking over instance lock {:?}", lock_path);
+    write_lock_owner(&lock_path, &LockOwner::current())
+}
+
+/// Removes the lock if it is held by this copy of happy chart
+#[tracing::instrument]
+pub fn release_instance_lock(data_paths: &DataPaths) {
+    let lock_path = data_paths.lock_file();
+    if read_lock_owner(&lock_path).is_some_and(|owner| owner.is_current_process()) {
+        match fs::remove_file(&lock_path) {
+            Ok(()) => info!("Released instance lock {:?}", lock_path),
+            Err(err) => warn!("Unable to remove instance lock {:?}: {}", lock_path, err),
+        }
+    }
+}
+
+fn read_lock_owner(lock_path: &Path) -> Option<LockOwner> {
+    serde_json::from_slice(&fs::read(lock_path).ok()?).ok()
+}
+
+/// Rewrites the lock file by renaming a temporary file over it, so another copy of happy chart never reads a half written lock and takes it as stale.
+/// No previous generation is kept, the lock file is never missing while it is rewritten.
+fn write_lock_owner(lock_path: &Path, owner: &LockOwner) -> Result<(), HappyChartError> {
+    let ser = serde_json::to_string(owner).map_err(HappyChartError::Serialization)?;
+    let temp_path = sibling_file_path(lock_path, TEMP_FILE_EXTENSION);
+    fs::write(&temp_path, ser.as_bytes())
+        .map_err(|err| HappyChartError::WriteSaveFileIO(err, temp_path.clone()))?;
+    fs::rename(&temp_path, lock_path)
+        .map_err(|err| HappyChartError::WriteSaveFileIO(err, lock_path.to_path_buf()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_of_a_stopped_process_on_this_machine_is_stale() {
+        let now = Local::now();
+        let running = LockOwner::current();
+        assert!(!running.is_stale(now));
+
+        // process ids wrap well below this on every supported platform
+        let stopped = LockOwner {
+            pid: u32::MAX - 1,
+            ..LockOwner::current()
+        };
+        assert_eq!(is_process_running(stopped.pid), Some(false));
+        assert!(stopped.is_stale(now));
+    }
+}
@@ -1,4 +1,6 @@
+use crate::common::encryption::SaveCipher;
 use crate::options::program_options::ProgramOptions;
+use crate::state::error_states::HappyChartError;
 use crate::state::tutorial_state::TutorialGoal;
 use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
@@ -19,6 +21,10 @@ pub struct LastSession {
     pub last_backup_date: DateTime<Local>,
 
     pub tutorial_state: TutorialGoal,
+
+    /// The full program options encrypted with the save file key, when encryption is enabled `program_options` only holds the unprotected options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypted_program_options: Option<Vec<u8>>,
 }
 
 impl Default for LastSession {
@@ -32,6 +38,34 @@ impl Default for LastSession {
             last_version_checked: None,
             last_backup_date: Local::now(),
             tutorial_state: TutorialGoal::default(),
+            encrypted_program_options: None,
         }
     }
 }
+
+impl LastSession {
+    /// Encrypts the program options, leaving only the options needed to unlock the save file in plain text
+    #[tracing::instrument(skip_all)]
+    pub fn protect(&mut self, cipher: &SaveCipher) -> Result<(), HappyChartError> {
+        let options_ser =
+            serde_json::to_vec(&self.program_options).map_err(HappyChartError::Serialization)?;
+        self.encrypted_program_options = Some(cipher.encrypt(&options_ser)?);
+        self.program_options = self.program_options.get_unprotected_options();
+        Ok(())
+    }
+
+    /// Decrypts the full program options, returns None if they were not encrypted
+    #[tracing::instrument(skip_all)]
+    pub fn decrypt_program_options(
+        &self,
+        cipher: &SaveCipher,
+    ) -> Result<Option<ProgramOptions>, HappyChartError> {
+        let Some(encrypted_program_options) = &self.encrypted_program_options else {
+            return Ok(None);
+        };
+        let options_ser = cipher.decrypt(encrypted_program_options)?;
+        serde_json::from_slice(&options_ser)
+            .map(Some)
+            .map_err(|err| HappyChartError::Deserialization(err, None))
+    }
+}
@@ -0,0 +1,358 @@
+use crate::common::encryption::{is_encrypted_container, SaveCipher};
+use crate::common::persistence::PersistenceJob;
+use crate::common::restore::{read_backup_archive, read_sqlite_database_copy};
+use crate::common::save_format::deserialize_save_data;
+use crate::day_stats::improved_daystat::assign_unique_day_ids;
+use crate::prelude::{HappyChartState, ImprovedDayStat};
+use crate::state::error_states::HappyChartError;
+use crate::MERGE_NEAR_DUPLICATE_MINUTES;
+use egui::Context;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// The first bytes of every SQLite database file
+const SQLITE_FILE_HEADER: &[u8] = b"SQLite format 3\0";
+/// The first bytes of every zip archive, which backups are stored as
+const ZIP_FILE_HEADER: &[u8] = b"PK\x03\x04";
+
+/// Why two days could not be merged automatically
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeConflictKind {
+    /// Both journals have the same day, with different contents, such as when it was edited or moved to another time in one of them
+    Edited,
+    /// The journals have different days close together in time with the same note, likely the same day logged twice
+    NearDuplicate,
+}
+
+/// Which day a merge conflict is resolved to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeResolution {
+    Current,
+    Incoming,
+    /// Keeps both days, only possible for near duplicates as an edited day is one day in both journals
+    Both,
+}
+
+/// A day from each journal that the user has to choose between
+#[derive(Debug, Clone)]
+pub struct MergeConflict {
+    pub kind: MergeConflictKind,
+    pub current: ImprovedDayStat,
+    pub incoming: ImprovedDayStat,
+    pub resolution: MergeResolution,
+}
+
+/// The result of merging an incoming journal into the current journal, before conflicts are resolved
+#[derive(Debug, Clone)]
+pub struct JournalMerge {
+    /// Every day that did not conflict, from either journal
+    pub merged: Vec<ImprovedDayStat>,
+    /// Days in both journals with identical contents, kept once
+    pub identical_count: usize,
+    /// Days only in the incoming journal
+    pub added_count: usize,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+impl JournalMerge {
+    /// Returns the merged day list, with every conflict resolved as chosen, sorted by date
+    pub fn resolve(&self) -> Vec<ImprovedDayStat> {
+        let mut days = self.merged.clone();
+        for conflict in &self.conflicts {
+            match conflict.resolution {
+                MergeResolution::Current => days.push(conflict.current.clone()),
+                MergeResolution::Incoming => days.push(conflict.incoming.clone()),
+                MergeResolution::Both => {
+                    days.push(conflict.current.clone());
+                    days.push(conflict.incoming.clone());
+                }
+            }
+        }
+        days.sort_by_key(|day| *day.get_date());
+        days
+    }
+}
+
+/// Merges the incoming day list into the current one.
+/// Days are matched by their id, days with identical contents are kept once and days only in one journal are kept as they are.
+/// Days with the same id and different contents, and different days close together in time with the same note, are left as conflicts for the user to resolve.
+#[tracing::instrument(skip_all)]
+pub fn merge_journals(current: &[ImprovedDayStat], incoming: &[ImprovedDayStat]) -> JournalMerge {
+    let serialize = |day: &ImprovedDayStat| serde_json::to_string(day).unwrap_or_default();
+
+    let mut unmatched_current = current
+        .iter()
+        .map(|day| (*day.get_id(), day))
+        .collect::<BTreeMap<Uuid, &ImprovedDayStat>>();
+
+    let mut merge = JournalMerge {
+        merged: vec![],
+        identical_count: 0,
+        added_count: 0,
+        conflicts: vec![],
+    };
+
+    let mut unmatched_incoming = vec![];
+    for incoming_day in incoming {
+        match unmatched_current.remove(incoming_day.get_id()) {
+            Some(current_day) if serialize(current_day) == serialize(incoming_day) => {
+                merge.identical_count += 1;
+                merge.merged.push(current_day.clone());
+            }
+            Some(current_day) => merge.conflicts.push(MergeConflict {
+                kind: MergeConflictKind::Edited,
+                current: current_day.clone(),
+                incoming: incoming_day.clone(),
+                resolution: MergeResolution::Current,
+            }),
+            None => unmatched_incoming.push(incoming_day),
+        }
+    }
+
+    for incoming_day in unmatched_incoming {
+        let near_duplicate = unmatched_current
+            .iter()
+            .find(|(_, current_day)| is_near_duplicate(current_day, incoming_day))
+            .map(|(id, _)| *id);
+        match near_duplicate.and_then(|id| unmatched_current.remove(&id)) {
+            Some(current_day) => merge.conflicts.push(MergeConflict {
+                kind: MergeConflictKind::NearDuplicate,
+                current: current_day.clone(),
+                incoming: incoming_day.clone(),
+                resolution: MergeResolution::Current,
+            }),
+            None => {
+                merge.added_count += 1;
+                merge.merged.push(incoming_day.clone());
+            }
+        }
+    }
+
+    merge
+        .merged
+        .extend(unmatched_current.into_values().cloned());
+    merge.merged.sort_by_key(|day| *day.get_date());
+
+    info!(
+        "Merged journals: {} identical, {} added, {} conflicts",
+        merge.identical_count,
+        merge.added_count,
+        merge.conflicts.len()
+    );
+    merge
+}
+
+/// Two days are near duplicates if they are close together in time and have the same note, or the same rating if neither has a note
+fn is_near_duplicate(day: &ImprovedDayStat, other: &ImprovedDayStat) -> bool {
+    let minutes_apart = day
+        .get_date()
+        .signed_duration_since(other.get_date())
+        .num_minutes()
+        .abs();
+    if minutes_apart > MERGE_NEAR_DUPLICATE_MINUTES {
+        return false;
+    }
+    let (note, other_note) = (day.get_note().trim(), other.get_note().trim());
+    if note.is_empty() && other_note.is_empty() {
+        day.get_rating() == other.get_rating()
+    } else {
+        note == other_note
+    }
+}
+
+/// Everything needed to read a journal chosen to merge on the persistence worker
+pub struct JournalReadJob {
+    pub path: PathBuf,
+    pub cipher: Option<SaveCipher>,
+}
+
+/// Queues reading a journal chosen to merge, the merge screen shows the merge once it has been read
+#[tracing::instrument(skip(ctx, app, cipher))]
+pub fn queue_journal_read(
+    ctx: &Context,
+    app: &mut HappyChartState,
+    path: PathBuf,
+    cipher: Option<SaveCipher>,
+) {
+    app.ui_states.merge_ui_state.reading = true;
+    app.persistence.queue(
+        ctx,
+        PersistenceJob::ReadJournal(JournalReadJob { path, cipher }),
+    );
+}
+
+/// Opens the merge screen with the given day list merged into the current journal
+#[tracing::instrument(skip(app, incoming_days))]
+pub fn start_merge(app: &mut HappyChartState, source: String, incoming_days: &[ImprovedDayStat]) {
+    let merge = merge_journals(&app.days, incoming_days);
+    app.ui_states.merge_ui_state.merge = Some(merge);
+    app.ui_states.merge_ui_state.source = Some(source);
+    app.ui_states.merge_ui_state.status = None;
+    app.ui_states.showing_merge_screen = true;
+}
+
+/// Starts merging the journal read by the persistence worker
+#[tracing::instrument(skip(app, days))]
+pub fn finish_journal_read(
+    app: &mut HappyChartState,
+    path: &Path,
+    days: Result<Vec<ImprovedDayStat>, HappyChartError>,
+) {
+    app.ui_states.merge_ui_state.reading = false;
+    match days {
+        Ok(incoming_days) => {
+            info!("Read {} days to merge from {:?}", incoming_days.len(), path);
+            start_merge(app, path.display().to_string(), &incoming_days);
+        }
+        Err(err) => {
+            error!("Error reading journal to merge: {}", err);
+            app.error_states.push(err);
+        }
+    }
+}
+
+/// Reads the day list from a journal chosen to merge, either a save file of any format version, plain or encrypted, a SQLite database, or a backup archive
+#[tracing::instrument(skip(cipher))]
+pub fn read_journal_file(
+    path: &Path,
+    cipher: Option<&SaveCipher>,
+) -> Result<Vec<ImprovedDayStat>, HappyChartError> {
+    let data =
+        fs::read(path).map_err(|err| HappyChartError::ReadSaveFileIO(err, path.to_path_buf()))?;
+
+    if data.starts_with(ZIP_FILE_HEADER) {
+        return read_backup_archive(path, cipher).map(|contents| contents.days);
+    }
+
+    if data.starts_with(SQLITE_FILE_HEADER) {
+        return read_sqlite_database_copy(&data, cipher).map_err(|err| match err {
+            HappyChartError::EncryptedSaveFile(_) => {
+                HappyChartError::EncryptedJournal(path.to_path_buf())
+            }
+            err => err,
+        });
+    }
+
+    let data = match (is_encrypted_container(&data), cipher) {
+        (false, _) => data,
+        (true, Some(cipher)) => cipher.decrypt(&data)?,
+        (true, None) => return Err(HappyChartError::EncryptedJournal(path.to_path_buf())),
+    };
+
+    deserialize_save_data(&data)
+        .map(|(_, mut days)| {
+            assign_unique_day_ids(&mut days);
+            days
+        })
+        .map_err(|err| match err {
+            HappyChartError::Deserialization(..) => {
+                HappyChartError::UnrecognizedJournal(path.to_path_buf())
+            }
+            err => err,
+        })
+}
+
+/// Replaces the day list with the resolved merge.
+/// Returns an error without changing anything if a conflict is resolved by replacing a day the entry edit policy has made read-only.
+#[tracing::instrument(skip(app, merge))]
+pub fn apply_merge(app: &mut HappyChartState, merge: &JournalMerge) -> Result<(), HappyChartError> {
+    if let Some(conflict) = merge.conflicts.iter().find(|conflict| {
+        conflict.resolution == MergeResolution::Incoming && app.is_entry_locked(&conflict.current)
+    }) {
+        return Err(HappyChartError::EntryLocked(*conflict.current.get_date()));
+    }
+    let mut days = merge.resolve();
+    app.keep_locked_days(&mut days);
+    app.replace_days(days);
+    app.mark_changed();
+    info!("Applied merge, journal now has {} days", app.days.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Duration, Local, TimeZone};
+
+    fn day_at(date: DateTime<Local>, rating: f32, note: &str) -> ImprovedDayStat {
+        ImprovedDayStat::new(rating, date, note, vec![], vec![])
+    }
+
+    fn base_time() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn days_are_matched_by_id() {
+        let shared = day_at(base_time(), 50.0, "shared");
+        let edited = day_at(base_time() + Duration::days(1), 50.0, "before");
+        let only_current = day_at(base_time() + Duration::days(2), 50.0, "current");
+        let only_incoming = day_at(base_time() + Duration::days(3), 50.0, "incoming");
+
+        let mut edited_incoming = edited.clone();
+        edited_incoming.edit_note("after");
+
+        let merge = merge_journals(
+            &[shared.clone(), edited.clone(), only_current.clone()],
+            &[shared, edited_incoming, only_incoming],
+        );
+        assert_eq!(merge.identical_count, 1);
+        assert_eq!(merge.added_count, 1);
+        assert_eq!(merge.merged.len(), 3);
+        assert_eq!(merge.conflicts.len(), 1);
+        assert_eq!(merge.conflicts[0].kind, MergeConflictKind::Edited);
+        assert_eq!(merge.conflicts[0].current.get_note(), "before");
+        assert_eq!(merge.conflicts[0].incoming.get_note(), "after");
+
+        // unresolved conflicts keep the current day
+        let resolved = merge.resolve();
+        assert_eq!(resolved.len(), 4);
+        assert!(resolved.iter().any(|day| day.get_note() == "before"));
+    }
+
+    #[test]
+    fn near_duplicates_are_close_in_time_with_the_same_note() {
+        let current = [
+            day_at(base_time(), 50.0, "walked the dog"),
+            day_at(base_time() + Duration::days(1), 40.0, ""),
+            day_at(base_time() + Duration::days(2), 40.0, ""),
+        ];
+        let incoming = [
+            // logged twice, half an hour apart
+            day_at(
+                base_time() + Duration::minutes(30),
+                60.0,
+                " walked the dog ",
+            ),
+            // no notes, so the ratings are compared
+            day_at(
+                base_time() + Duration::days(1) + Duration::minutes(5),
+                40.0,
+                "",
+            ),
+            day_at(
+                base_time() + Duration::days(2) + Duration::minutes(5),
+                45.0,
+                "",
+            ),
+            // the same note, too far apart
+            day_at(base_time() + Duration::hours(3), 50.0, "walked the dog"),
+        ];
+
+        let mut merge = merge_journals(&current, &incoming);
+        assert_eq!(merge.conflicts.len(), 2);
+        assert!(merge
+            .conflicts
+            .iter()
+            .all(|conflict| conflict.kind == MergeConflictKind::NearDuplicate));
+        assert_eq!(merge.added_count, 2);
+
+        for conflict in &mut merge.conflicts {
+            conflict.resolution = MergeResolution::Both;
+        }
+        assert_eq!(merge.resolve().len(), current.len() + incoming.len());
+    }
+}
@@ -0,0 +1,488 @@
+use crate::common::backup::{
+    prepare_backup_job, run_backup_job, run_restore_backup_job, BackupJob, BackupOutcome,
+    RestoreBackupJob,
+};
+use crate::common::backup_manifest::{verify_all_backups, BackupVerification};
+use crate::common::data_paths::DataPaths;
+use crate::common::external_change::{
+    offer_external_change, queue_save_file_check, run_save_file_check_job, SaveFileCheckJob,
+    SaveFileWatch,
+};
+use crate::common::finish_first_load;
+use crate::common::last_session::LastSession;
+use crate::common::merge::{finish_journal_read, read_journal_file, JournalReadJob};
+use crate::common::rekey::{
+    finish_password_change, run_password_change_job, PasswordChangeJob, PasswordChangeOutcome,
+};
+use crate::common::restore::{finish_restore, list_backup_archives, BackupArchive, BackupListJob};
+use crate::common::save::{prepare_save_job, read_last_session_save_file, run_save_job, SaveJob};
+use crate::common::sync::{apply_sync_outcome, run_sync_job, SyncJob, SyncOutcome};
+use crate::common::unlock::{finish_unlock, run_unlock_job, UnlockJob, UnlockOutcome};
+use crate::day_stats::trashed_day::TrashedDay;
+use crate::prelude::{HappyChartState, ImprovedDayStat};
+use crate::state::error_states::HappyChartError;
+use crate::storage::{
+    finish_storage_migration, open_storage, run_storage_migration_job, StorageBackendKind,
+    StorageMigrationJob,
+};
+use chrono::Local;
+use egui::Context;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use tracing::{error, info};
+
+/// Work done by the persistence worker, each job holds a copy of the program state it needs so the UI can carry on while it runs
+pub enum PersistenceJob {
+    /// Reads the last session file and day list of the profile the data paths point to
+    Load(DataPaths, SaveFileWatch),
+    Save(SaveJob),
+    Backup(BackupJob),
+    /// Reads the day list again after another program changed the save file
+    CheckSaveFile(SaveFileCheckJob),
+    /// Exchanges changes with other devices through the sync folder
+    Sync(SyncJob),
+    /// Reads every backup archive for the restore screen
+    ListBackups(BackupListJob),
+    /// Verifies every backup archive in each of the given backup folders
+    VerifyBackups(Vec<PathBuf>),
+    /// Re-encrypts the save files and backups with a new password
+    ChangePassword(PasswordChangeJob),
+    /// Reads a journal chosen to merge
+    ReadJournal(JournalReadJob),
+    /// Decrypts the save file with the encryption key entered on launch
+    Unlock(UnlockJob),
+    /// Backs up the program state before a backup is restored over it
+    BackupBeforeRestore(RestoreBackupJob),
+    /// Copies the day list and the trash into another kind of storage
+    MigrateStorage(StorageMigrationJob),
+}
+
+/// The result of a finished job, handled on the UI thread by `handle_persistence_events`
+pub enum PersistenceResult {
+    Loaded {
+        last_session: Box<LastSession>,
+        days: Result<Vec<ImprovedDayStat>, HappyChartError>,
+        /// Empty if the day list could not be read
+        trash: Vec<TrashedDay>,
+    },
+    Saved(Result<(), HappyChartError>),
+    BackedUp(BackupOutcome),
+    SaveFileChecked(Result<Vec<ImprovedDayStat>, HappyChartError>),
+    Synced(Result<SyncOutcome, HappyChartError>),
+    BackupsListed(Vec<BackupArchive>),
+    BackupsVerified(Vec<BackupVerification>),
+    PasswordChanged(Result<PasswordChangeOutcome, HappyChartError>),
+    JournalRead {
+        path: PathBuf,
+        days: Result<Vec<ImprovedDayStat>, HappyChartError>,
+    },
+    Unlocked(Result<Box<UnlockOutcome>, HappyChartError>),
+    /// The path of the backup taken before restoring
+    BackedUpBeforeRestore(Result<PathBuf, HappyChartError>),
+    StorageMigrated {
+        to: StorageBackendKind,
+        result: Result<(), HappyChartError>,
+    },
+}
+
+/// What the running job is doing, shown in the UI
+#[derive(Debug, Clone)]
+pub struct JobProgress {
+    pub description: String,
+    /// How far through the job it is, ranged from 0.0..=1.0
+    pub fraction: f32,
+}
+
+enum PersistenceEvent {
+    Progress(JobProgress),
+    Finished(PersistenceResult),
+}
+
+/// Number of jobs queued or running, and a condvar notified whenever a job finishes
+type PendingJobs = Arc<(Mutex<usize>, Condvar)>;
+
+/// Runs save file reads and writes, encryption and backup zipping on a background thread, so the window does not freeze while they run.
+/// Jobs are run one at a time in the order they were queued, so a later save never finishes before an earlier one.
+pub struct PersistenceWorker {
+    job_sender: Option<Sender<(Context, PersistenceJob)>>,
+    event_receiver: Receiver<PersistenceEvent>,
+    pending_jobs: PendingJobs,
+    progress: Option<JobProgress>,
+    /// Results of jobs run on the UI thread as the worker thread was not running
+    inline_results: Vec<PersistenceResult>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PersistenceWorker {
+    #[tracing::instrument]
+    pub fn new() -> Self {
+        let (job_sender, job_receiver) = channel::<(Context, PersistenceJob)>();
+        let (event_sender, event_receiver) = channel();
+        let pending_jobs: PendingJobs = Arc::new((Mutex::new(0), Condvar::new()));
+
+        let worker_pending_jobs = Arc::clone(&pending_jobs);
+        let thread = thread::Builder::new()
+            .name("happy_chart_persistence".to_string())
+            .spawn(move || {
+                for (ctx, job) in job_receiver {
+                    // the job counts as pending until its result is sent, even if it panics
+                    let _pending_job = PendingJobGuard(Arc::clone(&worker_pending_jobs));
+                    let result = run_job(job, |description, fraction| {
+                        let _ = event_sender.send(PersistenceEvent::Progress(JobProgress {
+                            description,
+                            fraction,
+                        }));
+                        ctx.request_repaint();
+                    });
+                    let _ = event_sender.send(PersistenceEvent::Finished(result));
+                    ctx.request_repaint();
+                }
+                info!("Persistence worker stopped");
+            })
+            .inspect_err(|err| error!("Unable to start persistence worker: {}", err))
+            .ok();
+
+        Self {
+            job_sender: thread.as_ref().map(|_| job_sender),
+            event_receiver,
+            pending_jobs,
+            progress: None,
+            inline_results: vec![],
+            thread,
+        }
+    }
+
+    /// Queues a job, if the worker thread is not running the job is run right away instead
+    #[tracing::instrument(skip_all)]
+    pub fn queue(&mut self, ctx: &Context, mut job: PersistenceJob) {
+        if let Some(job_sender) = &self.job_sender {
+            let (pending_jobs, _) = &*self.pending_jobs;
+            if let Ok(mut pending_jobs) = pending_jobs.lock() {
+                *pending_jobs += 1;
+            }
+            match job_sender.send((ctx.clone(), job)) {
+                Ok(()) => return,
+                Err(err) => {
+                    drop(PendingJobGuard(Arc::clone(&self.pending_jobs)));
+                    (_, job) = err.0;
+                }
+            }
+        }
+        error!("Persistence worker is not running, running job on the UI thread");
+        self.inline_results.push(run_job(job, |_, _| {}));
+    }
+
+    pub fn is_idle(&self) -> bool {
+        let (pending_jobs, _) = &*self.pending_jobs;
+        pending_jobs
+            .lock()
+            .map(|pending_jobs| *pending_jobs == 0)
+            .unwrap_or(true)
+    }
+
+    /// Blocks until every queued job has finished, their results are still handled by `handle_persistence_events`
+    #[tracing::instrument(skip(self))]
+    pub fn wait_until_idle(&self) {
+        let (pending_jobs, job_finished) = &*self.pending_jobs;
+        let Ok(mut pending) = pending_jobs.lock() else {
+            return;
+        };
+        while *pending > 0 {
+            info!("Waiting for {} persistence jobs to finish", *pending);
+            pending = match job_finished.wait(pending) {
+                Ok(pending) => pending,
+                Err(_) => return,
+            };
+        }
+    }
+
+    /// What the running job is doing, None if no job is running
+    pub fn get_progress(&self) -> Option<&JobProgress> {
+        self.progress.as_ref()
+    }
+
+    /// Returns the results of every job that finished since this was last called
+    fn receive_results(&mut self) -> Vec<PersistenceResult> {
+        let mut results = std::mem::take(&mut self.inline_results);
+        for event in self.event_receiver.try_iter() {
+            match event {
+                PersistenceEvent::Progress(progress) => self.progress = Some(progress),
+                PersistenceEvent::Finished(result) => {
+                    self.progress = None;
+                    results.push(result);
+                }
+            }
+        }
+        results
+    }
+}
+
+impl Default for PersistenceWorker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Waits for every queued job to finish, so no write is cut short when the program state is replaced or the program exits
+impl Drop for PersistenceWorker {
+    fn drop(&mut self) {
+        drop(self.job_sender.take());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Persistence worker panicked");
+            }
+        }
+    }
+}
+
+/// Decrements the pending job count when dropped
+struct PendingJobGuard(PendingJobs);
+
+impl Drop for PendingJobGuard {
+    fn drop(&mut self) {
+        let (pending_jobs, job_finished) = &*self.0;
+        if let Ok(mut pending_jobs) = pending_jobs.lock() {
+            *pending_jobs = pending_jobs.saturating_sub(1);
+        }
+        job_finished.notify_all();
+    }
+}
+
+#[tracing::instrument(skip_all)]
+fn run_job(job: PersistenceJob, report_progress: impl Fn(String, f32)) -> PersistenceResult {
+    match job {
+        PersistenceJob::Load(data_paths, save_file_watch) => {
+            report_progress("Reading save file".to_string(), 0.0);
+            // the last session is read first, as the program options decide which storage the save is read from
+            let last_session = read_last_session_save_file(&data_paths);
+            let storage_backend = last_session.program_options.storage_backend;
+            let save_file_path = storage_backend.get_save_file_path(&data_paths);
+            // recorded before reading, so a change made while it is read is noticed afterwards
+            let watched = save_file_watch.record(&save_file_path);
+            let loaded =
+                open_storage(&data_paths, storage_backend, None).and_then(|mut storage| {
+                    let days = storage.load()?;
+                    Ok((days, storage.load_trash()?))
+                });
+            let (days, trash) = match loaded {
+                Ok((days, trash)) => (Ok(days), trash),
+                Err(err) => (Err(err), vec![]),
+            };
+            if !watched {
+                // reading a missing save file creates it
+                save_file_watch.record(&save_file_path);
+            }
+            PersistenceResult::Loaded {
+                last_session: Box::new(last_session),
+                days,
+                trash,
+            }
+        }
+        PersistenceJob::Save(save) => {
+            report_progress("Saving".to_string(), 0.0);
+            PersistenceResult::Saved(run_save_job(save))
+        }
+        PersistenceJob::Backup(backup) => {
+            PersistenceResult::BackedUp(run_backup_job(backup, report_progress))
+        }
+        PersistenceJob::CheckSaveFile(check) => {
+            report_progress("Reading changed save file".to_string(), 0.0);
+            PersistenceResult::SaveFileChecked(run_save_file_check_job(check))
+        }
+        PersistenceJob::Sync(sync) => {
+            report_progress("Syncing".to_string(), 0.0);
+            PersistenceResult::Synced(run_sync_job(sync))
+        }
+        PersistenceJob::ListBackups(list) => {
+            PersistenceResult::BackupsListed(list_backup_archives(&list, report_progress))
+        }
+        PersistenceJob::VerifyBackups(backup_dirs) => {
+            PersistenceResult::BackupsVerified(verify_all_backups(&backup_dirs, report_progress))
+        }
+        PersistenceJob::ChangePassword(password_change) => PersistenceResult::PasswordChanged(
+            run_password_change_job(password_change, report_progress),
+        ),
+        PersistenceJob::ReadJournal(read) => {
+            report_progress("Reading journal to merge".to_string(), 0.0);
+            PersistenceResult::JournalRead {
+                days: read_journal_file(&read.path, read.cipher.as_ref()),
+                path: read.path,
+            }
+        }
+        PersistenceJob::Unlock(unlock) => {
+            report_progress("Deriving encryption key".to_string(), 0.0);
+            PersistenceResult::Unlocked(run_unlock_job(unlock, report_progress).map(Box::new))
+        }
+        PersistenceJob::BackupBeforeRestore(backup) => {
+            report_progress("Backing up before restoring".to_string(), 0.0);
+            PersistenceResult::BackedUpBeforeRestore(run_restore_backup_job(backup))
+        }
+        PersistenceJob::MigrateStorage(migration) => {
+            report_progress(format!("Migrating storage to {}", migration.to), 0.0);
+            PersistenceResult::StorageMigrated {
+                to: migration.to,
+                result: run_storage_migration_job(migration),
+            }
+        }
+    }
+}
+
+/// Queues reading the save file of the profile in use, the rest of program startup runs once it has been read
+#[tracing::instrument(skip(ctx, app))]
+pub fn queue_load(ctx: &Context, app: &mut HappyChartState) {
+    app.loading = true;
+    let data_paths = app.data_paths.clone();
+    let save_file_watch = app.save_file_watch.clone();
+    app.persistence
+        .queue(ctx, PersistenceJob::Load(data_paths, save_file_watch));
+}
+
+/// Queues saving the program state, which counts as saved from here on unless the save fails
+#[tracing::instrument(skip(ctx, app))]
+pub fn queue_save(ctx: &Context, app: &mut HappyChartState) -> Result<(), HappyChartError> {
+    let job = prepare_save_job(ctx, app)?;
+    app.mark_unchanged();
+    app.persistence.queue(ctx, PersistenceJob::Save(job));
+    Ok(())
+}
+
+/// Queues a backup to every destination if `is_manual`, otherwise only to the destinations an automatic backup is due for
+#[tracing::instrument(skip(ctx, app))]
+pub fn queue_backup(
+    ctx: &Context,
+    app: &mut HappyChartState,
+    is_manual: bool,
+) -> Result<(), HappyChartError> {
+    if let Some(job) = prepare_backup_job(ctx, app, is_manual)? {
+        // the program state is saved as part of the backup
+        app.mark_unchanged();
+        app.persistence.queue(ctx, PersistenceJob::Backup(job));
+    }
+    Ok(())
+}
+
+/// Applies the results of finished jobs to the program state, errors are added to the error states
+#[tracing::instrument(skip(ctx, app))]
+pub fn handle_persistence_events(ctx: &Context, app: &mut HappyChartState) {
+    for result in app.persistence.receive_results() {
+        match result {
+            PersistenceResult::Loaded {
+                last_session,
+                days,
+                trash,
+            } => {
+                app.loading = false;
+                app.program_options = last_session.program_options.clone();
+                app.trash = trash;
+                match days {
+                    Ok(days) => app.days = days,
+                    Err(err) => {
+                        error!("Error reading save file: {}", err);
+                        if let HappyChartError::CorruptSaveFile {
+                            recovered_days: Some(recovered_days),
+                            recovered_trash,
+                            ..
+                        } = &err
+                        {
+                            app.days = recovered_days.clone();
+                            app.trash = recovered_trash.clone();
+                        }
+                        app.error_states.push(err);
+                    }
+                }
+                finish_first_load(app, ctx, Some(*last_session));
+            }
+            PersistenceResult::Saved(Ok(())) => app.record_save(),
+            PersistenceResult::Saved(Err(err)) => {
+                error!("Error saving program state: {}", err);
+                app.mark_changed();
+                app.quit_requested = false;
+                app.profile_switch_requested = None;
+                push_persistence_error(ctx, app, err);
+            }
+            PersistenceResult::BackedUp(outcome) => {
+                if !outcome.written.is_empty() {
+                    app.last_backup_date = Local::now();
+                    app.record_save();
+                } else if !outcome.errors.is_empty() {
+                    // the save the backup started with may not have been written
+                    app.mark_changed();
+                }
+                if !outcome.errors.is_empty() {
+                    app.quit_requested = false;
+                }
+                for err in outcome.errors {
+                    error!("Error backing up program state: {:?}", err);
+                    push_persistence_error(ctx, app, err);
+                }
+            }
+            PersistenceResult::SaveFileChecked(Ok(days)) => offer_external_change(app, days),
+            PersistenceResult::SaveFileChecked(Err(err)) => {
+                error!("Error reading changed save file: {}", err);
+                app.error_states.push(err);
+            }
+            PersistenceResult::Synced(Ok(outcome)) => apply_sync_outcome(app, outcome),
+            PersistenceResult::Synced(Err(err)) => {
+                error!("Error syncing journal: {}", err);
+                app.ui_states.sync_status = Some(format!("Sync failed: {}", err));
+                app.error_states.push(err);
+            }
+            PersistenceResult::BackupsListed(archives) => {
+                info!("Read {} backup archives", archives.len());
+                let restore_ui_state = &mut app.ui_states.restore_ui_state;
+                restore_ui_state.listing = false;
+                // the screen may have been closed while the backups were read
+                if app.ui_states.showing_restore_screen {
+                    restore_ui_state.archives = Some(Rc::new(archives));
+                    restore_ui_state.selected_archive = None;
+                    restore_ui_state.preview = None;
+                }
+            }
+            PersistenceResult::BackupsVerified(results) => {
+                let backup_count = results.len();
+                let mut failed_count = 0;
+                for (path, result) in results {
+                    match result {
+                        Ok(manifest) => info!(
+                            "Verified backup {:?}, {} files, {:?} days",
+                            path, manifest.file_count, manifest.day_count
+                        ),
+                        Err(err) => {
+                            failed_count += 1;
+                            app.error_states.push(err);
+                        }
+                    }
+                }
+                app.ui_states.backup_verification_status = Some(format!(
+                    "{} of {} backups verified",
+                    backup_count - failed_count,
+                    backup_count
+                ));
+            }
+            PersistenceResult::PasswordChanged(result) => {
+                finish_password_change(ctx, app, result);
+            }
+            PersistenceResult::JournalRead { path, days } => {
+                finish_journal_read(app, &path, days);
+            }
+            PersistenceResult::Unlocked(result) => finish_unlock(ctx, app, result),
+            PersistenceResult::BackedUpBeforeRestore(result) => finish_restore(ctx, app, result),
+            PersistenceResult::StorageMigrated { to, result } => {
+                finish_storage_migration(ctx, app, to, result);
+            }
+        }
+    }
+}
+
+/// A save refused as another program changed the save file is not shown as an error, the user is asked which version to keep instead
+fn push_persistence_error(ctx: &Context, app: &mut HappyChartState, err: HappyChartError) {
+    if matches!(err, HappyChartError::SaveFileChangedExternally(_)) {
+        queue_save_file_check(ctx, app);
+    } else {
+        app.error_states.push(err);
+    }
+}
@@ -0,0 +1,165 @@
+use crate::common::data_paths::DataPaths;
+use crate::common::instance_lock::release_instance_lock;
+use crate::common::persistence::queue_save;
+use crate::common::save::write_file_atomically;
+use crate::prelude::HappyChartState;
+use crate::state::error_states::HappyChartError;
+use crate::DEFAULT_PROFILE_NAME;
+use egui::Context;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use tracing::{error, info, warn};
+
+/// Settings shared between every profile, stored in the config folder of the default profile
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ProfileSettings {
+    /// The profile opened on launch, None for the default profile
+    pub last_profile: Option<String>,
+    /// Ask which profile to open on launch, instead of opening the last used profile
+    pub choose_profile_at_startup: bool,
+}
+
+/// Reads the profile settings, returns the default settings if they do not exist or can not be read
+#[tracing::instrument]
+pub fn read_profile_settings(data_paths: &DataPaths) -> ProfileSettings {
+    let path = data_paths.profile_settings_file();
+    match fs::read(&path) {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|err| {
+            warn!("Unable to read profile settings at {:?}: {}", path, err);
+            ProfileSettings::default()
+        }),
+        Err(_) => ProfileSettings::default(),
+    }
+}
+
+#[tracing::instrument]
+pub fn write_profile_settings(
+    data_paths: &DataPaths,
+    settings: &ProfileSettings,
+) -> Result<(), HappyChartError> {
+    let ser = serde_json::to_string(settings).map_err(HappyChartError::Serialization)?;
+    write_file_atomically(&data_paths.profile_settings_file(), ser.as_bytes())
+}
+
+/// Returns the name of every named profile, sorted alphabetically, the default profile is not included
+#[tracing::instrument]
+pub fn list_profiles(data_paths: &DataPaths) -> Vec<String> {
+    let mut profiles = fs::read_dir(data_paths.profiles_dir())
+        .map(|dir_list| {
+            dir_list
+                .filter_map(Result::ok)
+                .filter(|entry| entry.path().is_dir())
+                .filter_map(|entry| entry.file_name().to_str().map(ToString::to_string))
+                .filter(|name| is_valid_profile_name(name))
+                .collect::<Vec<String>>()
+        })
+        .unwrap_or_default();
+    profiles.sort();
+    profiles
+}
+
+/// Profile names are used as folder names, so only characters that are safe in a folder name on every platform are allowed
+fn is_valid_profile_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.trim() == name
+        && !name.eq_ignore_ascii_case(DEFAULT_PROFILE_NAME)
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+}
+
+/// Returns the folders of the profile opened on launch, the default profile is used if the last used profile no longer exists
+#[tracing::instrument]
+pub fn get_startup_data_paths(data_paths: &DataPaths) -> DataPaths {
+    let last_profile = read_profile_settings(data_paths)
+        .last_profile
+        .filter(|profile| list_profiles(data_paths).contains(profile));
+    info!("Opening profile: {:?}", last_profile);
+    data_paths.with_profile(last_profile.as_deref())
+}
+
+/// Creates the folders of a new named profile, returns the name it was created with
+#[tracing::instrument]
+pub fn create_profile(data_paths: &DataPaths, name: &str) -> Result<String, HappyChartError> {
+    let name = name.trim();
+    if !is_valid_profile_name(name)
+        || list_profiles(data_paths)
+            .iter()
+            .any(|profile| profile.eq_ignore_ascii_case(name))
+    {
+        return Err(HappyChartError::InvalidProfileName(name.to_string()));
+    }
+
+    let profile_paths = data_paths.with_profile(Some(name));
+    for dir in [profile_paths.data_dir(), profile_paths.config_dir()] {
+        fs::create_dir_all(dir)
+            .map_err(|err| HappyChartError::WriteSaveFileIO(err, dir.to_path_buf()))?;
+    }
+    info!("Created profile {:?}", name);
+    Ok(name.to_string())
+}
+
+/// Returns true if the profile in use can be saved before switching away from it.
+/// It is not saved if it was never loaded, or is still waiting for its encryption key, as that would overwrite it with an empty journal, nor if it is open read-only.
+fn can_save_before_switch(app: &HappyChartState) -> bool {
+    let save_locked = app
+        .error_states
+        .iter()
+        .any(|err| matches!(err, HappyChartError::EncryptedSaveFile(_)));
+    !app.first_load && !app.loading && !save_locked && !app.read_only
+}
+
+/// Queues saving the current profile and switches to another profile once the save has been written, see `switch_profile_when_saved`
+#[tracing::instrument(skip(ctx, app))]
+pub fn switch_profile(
+    ctx: &Context,
+    app: &mut HappyChartState,
+    profile: Option<&str>,
+) -> Result<(), HappyChartError> {
+    if can_save_before_switch(app) {
+        queue_save(ctx, app)?;
+    }
+    app.profile_switch_requested = Some(profile.map(ToString::to_string));
+    switch_profile_when_saved(ctx, app);
+    Ok(())
+}
+
+/// Replaces the program state with that of the requested profile once no writes are left pending, the profile is loaded on the next frame.
+/// A failed save cancels the switch, so its error is shown. Returns true if the program state was replaced.
+#[tracing::instrument(skip(ctx, app))]
+pub fn switch_profile_when_saved(ctx: &Context, app: &mut HappyChartState) -> bool {
+    if app.profile_switch_requested.is_none() || !app.persistence.is_idle() {
+        return false;
+    }
+    // changes made while the save was written are saved before switching too
+    if app.has_unsaved_changes() && can_save_before_switch(app) {
+        if let Err(err) = queue_save(ctx, app) {
+            error!("Error saving before switching profile: {}", err);
+            app.profile_switch_requested = None;
+            app.error_states.push(err);
+        }
+        return false;
+    }
+    let Some(profile) = app.profile_switch_requested.take() else {
+        return false;
+    };
+
+    if app.instance_lock_held {
+        release_instance_lock(&app.data_paths);
+    }
+
+    app.profile_settings.last_profile.clone_from(&profile);
+    if let Err(err) = write_profile_settings(&app.data_paths, &app.profile_settings) {
+        error!("Error switching to profile {:?}: {}", profile, err);
+        app.error_states.push(err);
+        return false;
+    }
+
+    let data_paths = app.data_paths.with_profile(profile.as_deref());
+    data_paths.create_dirs();
+    info!("Switching to profile {:?}", profile);
+    *app = HappyChartState::from_data_paths(data_paths, vec![]);
+    ctx.request_repaint();
+    true
+}
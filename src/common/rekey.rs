@@ -0,0 +1,495 @@
+use crate::common::backup_destination::list_backup_archive_paths;
+use crate::common::backup_manifest::{
+    read_backup_zip, write_backup_zip, BackupEntry, BackupManifest,
+};
+use crate::common::encryption::{encryption_key_checks, is_encrypted_container, SaveCipher};
+use crate::common::external_change::check_save_file_unchanged;
+use crate::common::external_change::SaveFileWatch;
+use crate::common::last_session::LastSession;
+use crate::common::persistence::{queue_save, PersistenceJob};
+use crate::common::save::sibling_file_path;
+use crate::prelude::HappyChartState;
+use crate::state::error_states::HappyChartError;
+use crate::state::happy_chart_state::PasswordChangeState;
+use crate::storage::sqlite_storage::SqliteStorage;
+use crate::storage::StorageBackend;
+use crate::{
+    BACKUP_FILE_EXTENSION, LAST_SESSION_FILE_NAME, PREVIOUS_GENERATION_EXTENSION,
+    REKEY_ROLLBACK_EXTENSION, REKEY_STAGED_EXTENSION, SQLITE_SAVE_FILE_NAME,
+};
+use chrono::Local;
+use egui::Context;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{error, info, warn};
+
+/// A file whose re-encrypted contents have been written next to it, waiting to be moved into place
+struct StagedFile {
+    path: PathBuf,
+    staged_path: PathBuf,
+    rollback_path: PathBuf,
+}
+
+/// Everything needed to re-encrypt the save files and backups on the persistence worker
+pub struct PasswordChangeJob {
+    paths: Vec<PathBuf>,
+    old_cipher: SaveCipher,
+    new_cipher: Option<SaveCipher>,
+    /// The new password, None if the files are decrypted permanently
+    new_password: Option<String>,
+    save_file_path: PathBuf,
+    save_file_watch: SaveFileWatch,
+}
+
+/// The result of re-encrypting the save files and backups
+#[derive(Debug)]
+pub struct PasswordChangeOutcome {
+    /// Number of files that were re-encrypted
+    pub rekeyed_count: usize,
+    /// The new password, None if the files were decrypted permanently
+    pub new_password: Option<String>,
+}
+
+/// Queues re-encrypting the save files and every backup with a new password, or decrypting them permanently if no new password is given.
+/// Nothing is saved until it has finished, as a save with the old password would be left unreadable.
+#[tracing::instrument(skip_all)]
+pub fn queue_password_change(
+    ctx: &Context,
+    app: &mut HappyChartState,
+    old_password: &str,
+    new_password: Option<(&str, &str)>,
+) -> Result<(), HappyChartError> {
+    if old_password != app.encryption_key {
+        return Err(HappyChartError::IncorrectEncryptionKey);
+    }
+    if let Some((new_password, new_password_check)) = new_password {
+        encryption_key_checks(new_password, new_password_check)?;
+    }
+
+    let settings = app.program_options.key_derivation_settings;
+    let job = PasswordChangeJob {
+        paths: get_encrypted_file_paths(app),
+        old_cipher: SaveCipher::new(old_password, settings),
+        new_cipher: new_password.map(|(new_password, _)| SaveCipher::new(new_password, settings)),
+        new_password: new_password.map(|(new_password, _)| new_password.to_string()),
+        save_file_path: app
+            .program_options
+            .storage_backend
+            .get_save_file_path(&app.data_paths),
+        save_file_watch: app.save_file_watch.clone(),
+    };
+    // the program state is saved with the new password once the files are re-encrypted
+    app.encryption_change_running = true;
+    app.persistence
+        .queue(ctx, PersistenceJob::ChangePassword(job));
+    Ok(())
+}
+
+/// Re-encrypts every file of the job.
+/// Every file is re-encrypted next to the original before any original is replaced, so a failure part way through leaves every file as it was.
+#[tracing::instrument(skip_all)]
+pub fn run_password_change_job(
+    job: PasswordChangeJob,
+    report_progress: impl Fn(String, f32),
+) -> Result<PasswordChangeOutcome, HappyChartError> {
+    // saves queued before this job have been written, so the save file on disk is the one last written by this copy of happy chart
+    check_save_file_unchanged(&job.save_file_watch, &job.save_file_path)?;
+    verify_old_password(&job.save_file_path, &job.old_cipher)?;
+
+    let staged_files = stage_files(
+        &job.paths,
+        &job.old_cipher,
+        job.new_cipher.as_ref(),
+        report_progress,
+    )?;
+    let rekeyed_count = staged_files.len();
+    commit_staged_files(&staged_files)?;
+    // the save file was rewritten by us, not by another program
+    job.save_file_watch.record(&job.save_file_path);
+
+    info!("Re-encrypted {} files", rekeyed_count);
+    Ok(PasswordChangeOutcome {
+        rekeyed_count,
+        new_password: job.new_password,
+    })
+}
+
+/// Switches to the new password once the files are re-encrypted, and saves the program state with it
+#[tracing::instrument(skip_all)]
+pub fn finish_password_change(
+    ctx: &Context,
+    app: &mut HappyChartState,
+    result: Result<PasswordChangeOutcome, HappyChartError>,
+) {
+    app.encryption_change_running = false;
+    let outcome = match result {
+        Ok(outcome) => outcome,
+        Err(err) => {
+            error!("Error changing encryption password: {}", err);
+            app.ui_states.password_change.status = None;
+            app.error_states.push(err);
+            return;
+        }
+    };
+
+    let action = match &outcome.new_password {
+        Some(new_password) => {
+            app.encryption_key.clone_from(new_password);
+            app.encryption_key_second_check.clone_from(new_password);
+            app.program_options.encrypt_save_file = true;
+            "Re-encrypted"
+        }
+        None => {
+            app.encryption_key.clear();
+            app.encryption_key_second_check.clear();
+            app.program_options.encrypt_save_file = false;
+            "Decrypted"
+        }
+    };
+    info!(
+        "Encryption password changed, {} files re-encrypted",
+        outcome.rekeyed_count
+    );
+    app.ui_states.password_change = PasswordChangeState {
+        status: Some(format!("{} {} files", action, outcome.rekeyed_count)),
+        ..PasswordChangeState::default()
+    };
+    if let Err(err) = queue_save(ctx, app) {
+        error!("Error saving after changing encryption password: {}", err);
+        app.error_states.push(err);
+    }
+}
+
+/// Returns an error if the save file is encrypted with a key other than the old password, so a mistyped password never re-encrypts anything
+fn verify_old_password(
+    save_file_path: &Path,
+    old_cipher: &SaveCipher,
+) -> Result<(), HappyChartError> {
+    let incorrect_key = |err| match err {
+        HappyChartError::DecryptionError(_) => HappyChartError::IncorrectEncryptionKey,
+        err => err,
+    };
+    if !save_file_path.is_file() {
+        return Ok(());
+    }
+    if save_file_path
+        .file_name()
+        .is_some_and(|name| name == SQLITE_SAVE_FILE_NAME)
+    {
+        SqliteStorage::open(save_file_path, Some(old_cipher.clone()))
+            .and_then(|mut storage| storage.load())
+            .map_err(incorrect_key)?;
+        return Ok(());
+    }
+    let data = fs::read(save_file_path)
+        .map_err(|err| HappyChartError::ReadSaveFileIO(err, save_file_path.into()))?;
+    if is_encrypted_container(&data) {
+        old_cipher.decrypt(&data).map_err(incorrect_key)?;
+    }
+    Ok(())
+}
+
+/// Returns every save file and backup that could contain encrypted data
+fn get_encrypted_file_paths(app: &HappyChartState) -> Vec<PathBuf> {
+    let save_file = app.data_paths.save_file();
+    let last_session_file = app.data_paths.last_session_file();
+    let sync_state_file = app.data_paths.sync_state_file();
+    // files written by `write_file_atomically` keep their previous generation next to them
+    let mut paths = vec![
+        sibling_file_path(&save_file, PREVIOUS_GENERATION_EXTENSION),
+        save_file,
+        sibling_file_path(&last_session_file, PREVIOUS_GENERATION_EXTENSION),
+        last_session_file,
+        sibling_file_path(&sync_state_file, PREVIOUS_GENERATION_EXTENSION),
+        sync_state_file,
+        app.data_paths.legacy_save_file(),
+        app.data_paths.sqlite_save_file(),
+    ];
+
+    // backups encrypted with a separate key are left as they are
+    if app.program_options.separate_backup_key {
+        return paths.into_iter().filter(|path| path.is_file()).collect();
+    }
+
+    for backup_dir in app.get_backup_dirs() {
+        paths.extend(list_backup_archive_paths(&backup_dir));
+    }
+
+    paths.into_iter().filter(|path| path.is_file()).collect()
+}
+
+/// Writes the re-encrypted contents of every file that contains encrypted data next to it.
+/// If any file can not be re-encrypted, every staged file is removed again.
+#[tracing::instrument(skip_all)]
+fn stage_files(
+    paths: &[PathBuf],
+    old_cipher: &SaveCipher,
+    new_cipher: Option<&SaveCipher>,
+    report_progress: impl Fn(String, f32),
+) -> Result<Vec<StagedFile>, HappyChartError> {
+    let mut staged_files = vec![];
+    for (index, path) in paths.iter().enumerate() {
+        report_progress(
+            format!(
+                "Re-encrypting {}",
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .unwrap_or_default()
+            ),
+            index as f32 / paths.len() as f32,
+        );
+        let staged = rekey_file(path, old_cipher, new_cipher).and_then(|rekeyed| {
+            let Some(rekeyed) = rekeyed else {
+                return Ok(None);
+            };
+            let staged_path = sibling_file_path(path, REKEY_STAGED_EXTENSION);
+            write_synced(&staged_path, &rekeyed)?;
+            Ok(Some(StagedFile {
+                path: path.clone(),
+                rollback_path: sibling_file_path(path, REKEY_ROLLBACK_EXTENSION),
+                staged_path,
+            }))
+        });
+
+        match staged {
+            Ok(Some(staged_file)) => {
+                info!("Staged re-encrypted file {:?}", staged_file.staged_path);
+                staged_files.push(staged_file);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                error!("Unable to re-encrypt {:?}: {}", path, err);
+                for staged_file in &staged_files {
+                    let _ = fs::remove_file(&staged_file.staged_path);
+                }
+                return Err(HappyChartError::EncryptionChange {
+                    path: path.clone(),
+                    error: Box::new(err),
+                });
+            }
+        }
+    }
+    Ok(staged_files)
+}
+
+/// Moves every staged file into place, keeping the original until every file has been replaced.
+/// If any file can not be moved into place, every original is moved back.
+#[tracing::instrument(skip_all)]
+fn commit_staged_files(staged_files: &[StagedFile]) -> Result<(), HappyChartError> {
+    let mut committed: Vec<&StagedFile> = vec![];
+    for staged_file in staged_files {
+        let result = fs::rename(&staged_file.path, &staged_file.rollback_path).and_then(|()| {
+            fs::rename(&staged_file.staged_path, &staged_file.path).inspect_err(|_| {
+                let _ = fs::rename(&staged_file.rollback_path, &staged_file.path);
+            })
+        });
+
+        if let Err(io_error) = result {
+            error!(
+                "Unable to replace {:?}, rolling back: {}",
+                staged_file.path, io_error
+            );
+            for committed_file in committed {
+                if let Err(err) = fs::rename(&committed_file.rollback_path, &committed_file.path) {
+                    error!(
+                        "Unable to roll back {:?}, the original is still at {:?}: {}",
+                        committed_file.path, committed_file.rollback_path, err
+                    );
+                }
+            }
+            for staged_file in staged_files {
+                let _ = fs::remove_file(&staged_file.staged_path);
+            }
+            return Err(HappyChartError::EncryptionChange {
+                path: staged_file.path.clone(),
+                error: Box::new(HappyChartError::WriteSaveFileIO(
+                    io_error,
+                    staged_file.path.clone(),
+                )),
+            });
+        }
+        committed.push(staged_file);
+    }
+
+    for staged_file in staged_files {
+        if let Err(err) = fs::remove_file(&staged_file.rollback_path) {
+            warn!(
+                "Unable to remove original file {:?}: {}",
+                staged_file.rollback_path, err
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Returns the re-encrypted contents of a file, or None if the file contains nothing encrypted
+fn rekey_file(
+    path: &Path,
+    old_cipher: &SaveCipher,
+    new_cipher: Option<&SaveCipher>,
+) -> Result<Option<Vec<u8>>, HappyChartError> {
+    let data = fs::read(path).map_err(|err| HappyChartError::ReadSaveFileIO(err, path.into()))?;
+    let staging_dir = path.parent().unwrap_or(Path::new("."));
+    if path
+        .extension()
+        .is_some_and(|extension| extension == BACKUP_FILE_EXTENSION)
+    {
+        return rekey_backup_archive(&data, old_cipher, new_cipher, staging_dir);
+    }
+    // the previous generation of a file is read the same way as the file itself
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let file_name = file_name
+        .strip_suffix(PREVIOUS_GENERATION_EXTENSION)
+        .and_then(|file_name| file_name.strip_suffix('.'))
+        .unwrap_or(file_name);
+    if file_name == LAST_SESSION_FILE_NAME {
+        return rekey_last_session(&data, old_cipher, new_cipher);
+    }
+    rekey_save_data(file_name, &data, old_cipher, new_cipher, staging_dir)
+}
+
+/// Returns the re-encrypted contents of a last session file, or None if it contains nothing encrypted.
+/// Its program options are encrypted on their own, and the whole file is encrypted as well when it is stored in a backup.
+fn rekey_last_session(
+    data: &[u8],
+    old_cipher: &SaveCipher,
+    new_cipher: Option<&SaveCipher>,
+) -> Result<Option<Vec<u8>>, HappyChartError> {
+    if is_encrypted_container(data) {
+        let decrypted = old_cipher.decrypt(data)?;
+        let rekeyed = rekey_last_session(&decrypted, old_cipher, new_cipher)?.unwrap_or(decrypted);
+        return match new_cipher {
+            Some(new_cipher) => new_cipher.encrypt(&rekeyed).map(Some),
+            None => Ok(Some(rekeyed)),
+        };
+    }
+
+    let mut last_session = serde_json::from_slice::<LastSession>(data)
+        .map_err(|err| HappyChartError::Deserialization(err, None))?;
+    let Some(program_options) = last_session.decrypt_program_options(old_cipher)? else {
+        return Ok(None);
+    };
+    last_session.program_options = program_options;
+    last_session.encrypted_program_options = None;
+    if let Some(new_cipher) = new_cipher {
+        last_session.protect(new_cipher)?;
+    }
+    serde_json::to_vec(&last_session)
+        .map(Some)
+        .map_err(HappyChartError::Serialization)
+}
+
+/// Returns the re-encrypted contents of a save file with the given name, or None if it contains nothing encrypted.
+/// A SQLite database is re-encrypted in a copy staged in `staging_dir`.
+pub fn rekey_save_data(
+    file_name: &str,
+    data: &[u8],
+    old_cipher: &SaveCipher,
+    new_cipher: Option<&SaveCipher>,
+    staging_dir: &Path,
+) -> Result<Option<Vec<u8>>, HappyChartError> {
+    if file_name == SQLITE_SAVE_FILE_NAME {
+        return rekey_sqlite_database(data, old_cipher, new_cipher, staging_dir);
+    }
+    if !is_encrypted_container(data) {
+        return Ok(None);
+    }
+    let decrypted = old_cipher.decrypt(data)?;
+    match new_cipher {
+        Some(new_cipher) => new_cipher.encrypt(&decrypted).map(Some),
+        None => Ok(Some(decrypted)),
+    }
+}
+
+/// Re-encrypts every entry of a backup archive and rewrites its manifest, returns None if no entry contains anything encrypted
+fn rekey_backup_archive(
+    data: &[u8],
+    old_cipher: &SaveCipher,
+    new_cipher: Option<&SaveCipher>,
+    staging_dir: &Path,
+) -> Result<Option<Vec<u8>>, HappyChartError> {
+    let (manifest, entries) = read_backup_zip(data).map_err(HappyChartError::SaveBackupIO)?;
+    let mut changed = false;
+
+    let entries = entries
+        .into_iter()
+        .map(|(name, entry_data)| {
+            let rekeyed = if name == LAST_SESSION_FILE_NAME {
+                rekey_last_session(&entry_data, old_cipher, new_cipher)?
+            } else {
+                rekey_save_data(&name, &entry_data, old_cipher, new_cipher, staging_dir)?
+            };
+            Ok(match rekeyed {
+                Some(rekeyed) => {
+                    changed = true;
+                    (name, rekeyed)
+                }
+                None => (name, entry_data),
+            })
+        })
+        .collect::<Result<Vec<BackupEntry>, HappyChartError>>()?;
+
+    if !changed {
+        return Ok(None);
+    }
+    // the backup keeps the date it was made and its day count, only the checksums change
+    let manifest =
+        manifest.and_then(|manifest| serde_json::from_slice::<BackupManifest>(&manifest).ok());
+    let created = manifest
+        .as_ref()
+        .map_or_else(Local::now, |manifest| manifest.created);
+    let day_count = manifest.and_then(|manifest| manifest.day_count);
+    write_backup_zip(&entries, created, day_count).map(Some)
+}
+
+/// Re-encrypts every row of a SQLite database, using a copy of it staged in `staging_dir`, returns None if no row is encrypted.
+/// The copy is staged next to the database rather than in the system temporary folder, so a decrypted copy of the journal is never written outside the folder it is kept in.
+fn rekey_sqlite_database(
+    data: &[u8],
+    old_cipher: &SaveCipher,
+    new_cipher: Option<&SaveCipher>,
+    staging_dir: &Path,
+) -> Result<Option<Vec<u8>>, HappyChartError> {
+    let temp_path = staging_dir.join(format!(
+        "{}.{}.{}",
+        SQLITE_SAVE_FILE_NAME,
+        rand::random::<u64>(),
+        REKEY_STAGED_EXTENSION
+    ));
+    write_synced(&temp_path, data)?;
+
+    let rekeyed = (|| {
+        let mut storage = SqliteStorage::open(&temp_path, Some(old_cipher.clone()))?;
+        if !storage.has_encrypted_rows()? {
+            return Ok(None);
+        }
+        let days = storage.load()?;
+        let trash = storage.load_trash()?;
+        drop(storage);
+
+        let mut storage = SqliteStorage::open(&temp_path, new_cipher.cloned())?;
+        storage.store_all(&days, &trash)?;
+        // rows encrypted with the old key are left in free pages until the database is rebuilt
+        storage.vacuum()?;
+        drop(storage);
+        fs::read(&temp_path)
+            .map(Some)
+            .map_err(|err| HappyChartError::ReadSaveFileIO(err, temp_path.clone()))
+    })();
+
+    if let Err(err) = fs::remove_file(&temp_path) {
+        warn!("Unable to remove temporary file {:?}: {}", temp_path, err);
+    }
+    rekeyed
+}
+
+fn write_synced(path: &Path, data: &[u8]) -> Result<(), HappyChartError> {
+    File::create(path)
+        .and_then(|mut file| file.write_all(data).and_then(|()| file.sync_all()))
+        .map_err(|err| HappyChartError::WriteSaveFileIO(err, path.into()))
+}
@@ -0,0 +1,370 @@
+use crate::common::backup::prepare_restore_backup_job;
+use crate::common::backup_destination::list_backup_archive_paths;
+use crate::common::backup_manifest::read_backup_zip;
+use crate::common::encryption::{is_encrypted_container, SaveCipher};
+use crate::common::last_session::LastSession;
+use crate::common::persistence::{queue_save, PersistenceJob};
+use crate::common::save_format::deserialize_save_data;
+use crate::day_stats::improved_daystat::{assign_unique_day_ids, ImprovedDayStat};
+use crate::options::program_options::ProgramOptions;
+use crate::prelude::HappyChartState;
+use crate::state::error_states::HappyChartError;
+use crate::storage::sqlite_storage::SqliteStorage;
+use crate::storage::StorageBackend;
+use crate::{LAST_SESSION_FILE_NAME, NEW_SAVE_FILE_NAME, SAVE_FILE_NAME, SQLITE_SAVE_FILE_NAME};
+use chrono::{DateTime, Local};
+use egui::Context;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// A backup archive found in the backup folder, along with what could be read from it
+#[derive(Debug)]
+pub struct BackupArchive {
+    pub path: PathBuf,
+    pub modified: Option<DateTime<Local>>,
+    pub contents: Result<BackupContents, HappyChartError>,
+}
+
+/// The journal and options stored in a backup archive
+#[derive(Debug, Clone)]
+pub struct BackupContents {
+    pub days: Vec<ImprovedDayStat>,
+    /// The program options at the time of the backup, None if the archive has none, or they are encrypted with a different key
+    pub program_options: Option<ProgramOptions>,
+}
+
+impl BackupContents {
+    /// Returns the dates of the first and last day in the backup
+    pub fn get_date_range(&self) -> Option<(DateTime<Local>, DateTime<Local>)> {
+        let first = self.days.iter().map(|day| *day.get_date()).min()?;
+        let last = self.days.iter().map(|day| *day.get_date()).max()?;
+        Some((first, last))
+    }
+}
+
+/// A day listed in a restore preview
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreviewDay {
+    pub id: Uuid,
+    /// The time of the day, as it is in the backup if the day is in both journals
+    pub date: DateTime<Local>,
+}
+
+/// Differences between the current journal and the journal stored in a backup, days are matched by their id
+#[derive(Debug, Default)]
+pub struct RestorePreview {
+    /// Days in the backup that are not in the current journal
+    pub added: Vec<PreviewDay>,
+    /// Days in the current journal that are not in the backup, these are lost when restoring
+    pub removed: Vec<PreviewDay>,
+    /// Days in both, whose contents differ
+    pub changed: Vec<PreviewDay>,
+    pub unchanged_count: usize,
+}
+
+impl RestorePreview {
+    #[tracing::instrument(skip_all)]
+    pub fn new(current_days: &[ImprovedDayStat], backup_days: &[ImprovedDayStat]) -> Self {
+        let key_days = |days: &[ImprovedDayStat]| {
+            days.iter()
+                .map(|day| {
+                    (
+                        *day.get_id(),
+                        (
+                            *day.get_date(),
+                            serde_json::to_string(day).unwrap_or_default(),
+                        ),
+                    )
+                })
+                .collect::<BTreeMap<Uuid, (DateTime<Local>, String)>>()
+        };
+        let current_days = key_days(current_days);
+        let backup_days = key_days(backup_days);
+
+        let mut preview = Self::default();
+        for (id, (date, backup_day)) in &backup_days {
+            let preview_day = PreviewDay {
+                id: *id,
+                date: *date,
+            };
+            match current_days.get(id) {
+                None => preview.added.push(preview_day),
+                Some((_, current_day)) if current_day != backup_day => {
+                    preview.changed.push(preview_day);
+                }
+                Some(_) => preview.unchanged_count += 1,
+            }
+        }
+        preview.removed = current_days
+            .iter()
+            .filter(|(id, _)| !backup_days.contains_key(id))
+            .map(|(id, (date, _))| PreviewDay {
+                id: *id,
+                date: *date,
+            })
+            .collect();
+        for list in [
+            &mut preview.added,
+            &mut preview.removed,
+            &mut preview.changed,
+        ] {
+            list.sort_by_key(|preview_day| preview_day.date);
+        }
+        preview
+    }
+}
+
+/// Everything needed to read the backup archives on the persistence worker
+pub struct BackupListJob {
+    pub backup_dirs: Vec<PathBuf>,
+    /// Used to read encrypted backups
+    pub cipher: Option<SaveCipher>,
+}
+
+/// Queues reading every backup archive for the restore screen, which lists them once they have been read
+#[tracing::instrument(skip(ctx, app, cipher))]
+pub fn queue_backup_listing(ctx: &Context, app: &mut HappyChartState, cipher: Option<SaveCipher>) {
+    app.ui_states.restore_ui_state.listing = true;
+    let job = BackupListJob {
+        backup_dirs: app.get_backup_dirs(),
+        cipher,
+    };
+    app.persistence.queue(ctx, PersistenceJob::ListBackups(job));
+}
+
+/// Lists every backup archive in each of the backup folders of the job, newest first, reading each with the cipher of the job if it is encrypted
+#[tracing::instrument(skip_all)]
+pub fn list_backup_archives(
+    job: &BackupListJob,
+    report_progress: impl Fn(String, f32),
+) -> Vec<BackupArchive> {
+    let paths = job
+        .backup_dirs
+        .iter()
+        .flat_map(|backup_dir| list_backup_archive_paths(backup_dir))
+        .collect::<Vec<PathBuf>>();
+    let path_count = paths.len();
+    let mut archives = paths
+        .into_iter()
+        .enumerate()
+        .map(|(index, path)| {
+            report_progress(
+                format!("Reading backup {} of {}", index + 1, path_count),
+                index as f32 / path_count as f32,
+            );
+            BackupArchive {
+                modified: fs::metadata(&path)
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+                    .map(DateTime::<Local>::from),
+                contents: read_backup_archive(&path, job.cipher.as_ref()),
+                path,
+            }
+        })
+        .collect::<Vec<BackupArchive>>();
+
+    archives.sort_by_key(|archive| Reverse(archive.modified));
+    archives
+}
+
+/// Reads the journal and options from a backup archive, encrypted backups return `EncryptedBackup` if no cipher is given
+#[tracing::instrument(skip(cipher))]
+pub fn read_backup_archive(
+    path: &Path,
+    cipher: Option<&SaveCipher>,
+) -> Result<BackupContents, HappyChartError> {
+    let data = fs::read(path).map_err(HappyChartError::SaveBackupIO)?;
+    let entries = read_backup_zip(&data)
+        .map_err(HappyChartError::SaveBackupIO)?
+        .1
+        .into_iter()
+        .collect::<HashMap<String, Vec<u8>>>();
+
+    let decrypt = |data: &[u8]| match (is_encrypted_container(data), cipher) {
+        (false, _) => Ok(data.to_vec()),
+        (true, Some(cipher)) => cipher.decrypt(data),
+        (true, None) => Err(HappyChartError::EncryptedBackup(path.to_path_buf())),
+    };
+
+    let mut days = if let Some(data) = entries.get(NEW_SAVE_FILE_NAME) {
+        deserialize_save_data(&decrypt(data)?)?.1
+    } else if let Some(data) = entries.get(SQLITE_SAVE_FILE_NAME) {
+        read_backup_sqlite_database(path, data, cipher)?
+    } else if let Some(data) = entries.get(SAVE_FILE_NAME) {
+        deserialize_save_data(&decrypt(data)?)?.1
+    } else {
+        return Err(HappyChartError::BackupMissingSaveFile(path.to_path_buf()));
+    };
+    assign_unique_day_ids(&mut days);
+
+    let program_options = match entries.get(LAST_SESSION_FILE_NAME) {
+        Some(data) => {
+            let last_session = serde_json::from_slice::<LastSession>(&decrypt(data)?)
+                .map_err(|err| HappyChartError::Deserialization(err, None))?;
+            match (&last_session.encrypted_program_options, cipher) {
+                (None, _) => Some(last_session.program_options),
+                (Some(_), Some(cipher)) => last_session
+                    .decrypt_program_options(cipher)
+                    .inspect_err(|err| {
+                        warn!("Unable to decrypt backup program options: {}", err);
+                    })
+                    .ok()
+                    .flatten(),
+                (Some(_), None) => None,
+            }
+        }
+        None => None,
+    };
+
+    Ok(BackupContents {
+        days,
+        program_options,
+    })
+}
+
+/// Reads the days from a SQLite database stored in a backup
+fn read_backup_sqlite_database(
+    path: &Path,
+    data: &[u8],
+    cipher: Option<&SaveCipher>,
+) -> Result<Vec<ImprovedDayStat>, HappyChartError> {
+    read_sqlite_database_copy(data, cipher).map_err(|err| match err {
+        HappyChartError::EncryptedSaveFile(_) => {
+            HappyChartError::EncryptedBackup(path.to_path_buf())
+        }
+        err => err,
+    })
+}
+
+/// Reads the days from the contents of a SQLite database, using a temporary copy of it so the database it was read from is never written to
+pub fn read_sqlite_database_copy(
+    data: &[u8],
+    cipher: Option<&SaveCipher>,
+) -> Result<Vec<ImprovedDayStat>, HappyChartError> {
+    let temp_path = env::temp_dir().join(format!(
+        "happy_chart_read_{}_{}",
+        rand::random::<u64>(),
+        SQLITE_SAVE_FILE_NAME
+    ));
+    fs::write(&temp_path, data)
+        .map_err(|err| HappyChartError::WriteSaveFileIO(err, temp_path.clone()))?;
+
+    let days =
+        SqliteStorage::open(&temp_path, cipher.cloned()).and_then(|mut storage| storage.load());
+
+    if let Err(err) = fs::remove_file(&temp_path) {
+        warn!("Unable to remove temporary file {:?}: {}", temp_path, err);
+    }
+    days
+}
+
+/// A restore waiting for the backup of the current state to be taken first
+#[derive(Debug)]
+pub struct PendingRestore {
+    pub contents: BackupContents,
+    pub restore_program_options: bool,
+}
+
+/// Queues restoring the day list from a backup, and the program options too if `restore_program_options` is set.
+/// A backup of the current state is always taken first on the persistence worker, the restore is applied by `finish_restore` once it has been taken.
+#[tracing::instrument(skip(ctx, app, contents))]
+pub fn queue_restore(
+    ctx: &Context,
+    app: &mut HappyChartState,
+    contents: &BackupContents,
+    restore_program_options: bool,
+) -> Result<(), HappyChartError> {
+    let job = prepare_restore_backup_job(ctx, app)?;
+    app.mark_unchanged();
+    app.ui_states.restore_ui_state.pending_restore = Some(Rc::new(PendingRestore {
+        contents: contents.clone(),
+        restore_program_options,
+    }));
+    app.persistence
+        .queue(ctx, PersistenceJob::BackupBeforeRestore(job));
+    Ok(())
+}
+
+/// Applies the pending restore once the backup of the current state has been taken, nothing is restored if it could not be taken
+#[tracing::instrument(skip(ctx, app, result))]
+pub fn finish_restore(
+    ctx: &Context,
+    app: &mut HappyChartState,
+    result: Result<PathBuf, HappyChartError>,
+) {
+    let Some(pending) = app.ui_states.restore_ui_state.pending_restore.take() else {
+        return;
+    };
+    let safety_backup_path = match result {
+        Ok(safety_backup_path) => safety_backup_path,
+        Err(err) => {
+            error!("Unable to take a backup before restoring: {}", err);
+            app.mark_changed();
+            app.ui_states.restore_ui_state.status = None;
+            app.error_states.push(err);
+            return;
+        }
+    };
+    app.record_save();
+    info!("Took a backup before restoring at {:?}", safety_backup_path);
+
+    let kept_count = restore_backup(app, &pending.contents, pending.restore_program_options);
+    if let Err(err) = queue_save(ctx, app) {
+        error!("Error saving restored backup: {}", err);
+        app.error_states.push(err);
+    }
+
+    let mut status = format!(
+        "Restored {} days, the previous state was backed up to {}",
+        app.days.len(),
+        safety_backup_path.display()
+    );
+    if kept_count > 0 {
+        status.push_str(&format!(
+            ", {} read-only entries were kept as they were",
+            kept_count
+        ));
+    }
+    app.ui_states.restore_ui_state.status = Some(status);
+    // the safety backup is now in the backup folder, and the preview is of the old journal
+    app.ui_states.restore_ui_state.archives = None;
+}
+
+/// Replaces the day list with that of a backup, and the program options too if `restore_program_options` is set, encryption and storage options are never restored.
+/// Days the entry edit policy has made read-only are kept as they are, returns the number of read-only days kept.
+#[tracing::instrument(skip(app, contents))]
+fn restore_backup(
+    app: &mut HappyChartState,
+    contents: &BackupContents,
+    restore_program_options: bool,
+) -> usize {
+    let mut days = contents.days.clone();
+    let kept_count = app.keep_locked_days(&mut days);
+    app.days = days;
+    app.days.sort_by_key(|day| *day.get_date());
+    app.edit_history.clear();
+
+    if let (true, Some(program_options)) = (restore_program_options, &contents.program_options) {
+        let current_options = app.program_options.clone();
+        app.program_options = ProgramOptions {
+            encrypt_save_file: current_options.encrypt_save_file,
+            storage_backend: current_options.storage_backend,
+            key_derivation_settings: current_options.key_derivation_settings,
+            separate_backup_key: current_options.separate_backup_key,
+            ..program_options.clone()
+        };
+    }
+
+    app.mark_changed();
+    app.stats
+        .calc_all_stats(&app.days, app.program_options.streak_leniency);
+
+    info!("Restored {} days from backup", app.days.len());
+    kept_count
+}
@@ -1,23 +1,58 @@
-use crate::common::encryption::encryption_save_file_checks;
+use crate::common::data_paths::DataPaths;
+use crate::common::encryption::{encryption_save_file_checks, is_encrypted_container, SaveCipher};
+use crate::common::external_change::{check_save_file_unchanged, SaveFileWatch};
 use crate::common::last_session::LastSession;
-#[allow(deprecated)]
-use crate::day_stats::daystat::DayStat;
+use crate::common::save_format::{
+    deserialize_save_data, read_save_metadata, read_save_trash, serialize_save_data,
+};
+use crate::day_stats::trashed_day::TrashedDay;
 use crate::prelude::{HappyChartState, ImprovedDayStat};
 use crate::state::error_states::HappyChartError;
-use crate::{LAST_SESSION_FILE_NAME, NEW_SAVE_FILE_NAME, SAVE_FILE_NAME};
+use crate::storage::{open_storage, StorageBackendKind};
+use crate::{
+    CORRUPT_FILE_EXTENSION, PREVIOUS_GENERATION_EXTENSION, SAVE_FORMAT_VERSION, TEMP_FILE_EXTENSION,
+};
 use chrono::Local;
-use cocoon::MiniCocoon;
 use eframe::emath::{Pos2, Rect};
 use egui::Context;
+use std::ffi::OsString;
+use std::fs;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use tracing::{error, info, warn};
 
+/// A copy of everything written when the program state is saved, so it can be written on the persistence worker while the UI carries on
+pub struct SaveJob {
+    pub data_paths: DataPaths,
+    pub last_session: LastSession,
+    pub days: Vec<ImprovedDayStat>,
+    pub trash: Vec<TrashedDay>,
+    pub storage_backend: StorageBackendKind,
+    pub save_cipher: Option<SaveCipher>,
+    pub save_file_watch: SaveFileWatch,
+    /// Result of checking the encryption keys, None if encryption is disabled
+    encryption_check: Option<Result<(), HappyChartError>>,
+}
+
+/// Copies the program state into a save job, this is cheap enough to do on the UI thread, all encryption and IO is left to `run_save_job`
 #[tracing::instrument(skip(ctx, app))]
-pub fn save_program_state(ctx: &Context, app: &HappyChartState) -> Result<(), HappyChartError> {
-    info!("Saving program state...");
-    let days = &app.days;
+pub fn prepare_save_job(ctx: &Context, app: &HappyChartState) -> Result<SaveJob, HappyChartError> {
+    if app.read_only {
+        return Err(HappyChartError::ReadOnlySession);
+    }
+    // a save with the old password would be left unreadable once the files are re-encrypted
+    if app.encryption_change_running {
+        return Err(HappyChartError::EncryptionChangeRunning);
+    }
+    // nothing is saved over a save file changed by another program until the user has chosen which version to keep
+    if app.external_change.is_some() {
+        return Err(HappyChartError::SaveFileChangedExternally(
+            app.program_options
+                .storage_backend
+                .get_save_file_path(&app.data_paths),
+        ));
+    }
 
     let window_size = ctx.input(|i| {
         i.viewport().inner_rect.unwrap_or(Rect::from_two_pos(
@@ -38,65 +73,212 @@ pub fn save_program_state(ctx: &Context, app: &HappyChartState) -> Result<(), Ha
         },
         last_backup_date: app.last_backup_date,
         tutorial_state: app.tutorial_state,
+        encrypted_program_options: None,
     };
 
+    Ok(SaveJob {
+        data_paths: app.data_paths.clone(),
+        last_session,
+        days: app.days.clone(),
+        trash: app.trash.clone(),
+        storage_backend: app.program_options.storage_backend,
+        save_cipher: app.get_save_cipher(),
+        save_file_watch: app.save_file_watch.clone(),
+        // only check for save file encryption issues if the user has encryption enabled
+        encryption_check: app
+            .program_options
+            .encrypt_save_file
+            .then(|| encryption_save_file_checks(app)),
+    })
+}
+
+/// Writes the last session file, the day list, and the trash of a save job
+#[tracing::instrument(skip(job))]
+pub fn run_save_job(job: SaveJob) -> Result<(), HappyChartError> {
+    info!("Saving program state...");
+    let save_file_path = job.storage_backend.get_save_file_path(&job.data_paths);
+    check_save_file_unchanged(&job.save_file_watch, &save_file_path)?;
+
+    let mut last_session = job.last_session;
+
+    // the last session is still written unprotected if the keys are invalid, so the options are not lost
+    if let (Some(Ok(())), Some(cipher)) = (&job.encryption_check, &job.save_cipher) {
+        last_session.protect(cipher)?;
+    }
+
     let session_ser =
         serde_json::to_string(&last_session).map_err(HappyChartError::Serialization)?;
-    let last_session_path = Path::new(LAST_SESSION_FILE_NAME);
+    let last_session_path = job.data_paths.last_session_file();
 
-    let mut last_session_save_file = File::create(last_session_path).map_err(|io_error| {
-        HappyChartError::WriteSaveFileIO(io_error, PathBuf::from(last_session_path))
-    })?;
-
-    last_session_save_file
-        .write_all(session_ser.as_bytes())
-        .map_err(|err| HappyChartError::WriteSaveFileIO(err, PathBuf::from(last_session_path)))?;
+    write_file_atomically(&last_session_path, session_ser.as_bytes())?;
 
     info!("Last session save file written to: {:?}", last_session_path);
 
-    // only check for save file encryption issues if the user has encryption enabled, and we have already written the last session file
-    if app.program_options.encrypt_save_file {
-        encryption_save_file_checks(app)?;
+    if let Some(encryption_check) = job.encryption_check {
+        encryption_check?;
     }
 
-    let ser = serde_json::to_string(days).map_err(HappyChartError::Serialization)?;
-    let save_path = Path::new(NEW_SAVE_FILE_NAME);
+    open_storage(&job.data_paths, job.storage_backend, job.save_cipher)?
+        .store_changes(&job.days, &job.trash)?;
+    job.save_file_watch.record(&save_file_path);
+
+    Ok(())
+}
+
+/// Serializes the day list and the trash into the save file at `path`, encrypting it if a cipher is given.
+/// The creation date of the save file being replaced is kept, and a save file written by a newer version of happy chart is never overwritten.
+#[tracing::instrument(skip(days, trash, cipher))]
+pub fn write_save_file(
+    path: &Path,
+    days: &[ImprovedDayStat],
+    trash: &[TrashedDay],
+    cipher: Option<&SaveCipher>,
+) -> Result<(), HappyChartError> {
+    let existing_metadata =
+        fs::read(path)
+            .ok()
+            .and_then(|data| match (is_encrypted_container(&data), cipher) {
+                (true, Some(cipher)) => read_save_metadata(&cipher.decrypt(&data).ok()?),
+                _ => read_save_metadata(&data),
+            });
+
+    if let Some(metadata) = &existing_metadata {
+        if metadata.format_version > SAVE_FORMAT_VERSION {
+            error!(
+                "Refusing to overwrite save file of format version {} at path: {:?}",
+                metadata.format_version, path
+            );
+            return Err(HappyChartError::UnsupportedSaveFormatVersion {
+                found: metadata.format_version,
+                supported: SAVE_FORMAT_VERSION,
+                app_version: metadata.app_version.clone(),
+            });
+        }
+    }
 
-    let mut save_file = File::create(save_path)
-        .map_err(|io_error| HappyChartError::WriteSaveFileIO(io_error, PathBuf::from(save_path)))?;
+    let ser = serialize_save_data(
+        days,
+        trash,
+        existing_metadata.map(|metadata| metadata.created),
+    )?;
 
-    info!("Creating save file at path: {:?}", save_path);
+    info!("Creating save file at path: {:?}", path);
 
-    if app.program_options.encrypt_save_file {
-        info!("Save file encryption enabled, encrypting...");
-        let mut key = app.encryption_key.to_string();
-        if key.len() < 32 {
-            key.push_str("00000000000000000000000000000000");
+    match cipher {
+        Some(cipher) => {
+            info!("Save file encryption enabled, encrypting...");
+            write_file_atomically(path, &cipher.encrypt(&ser)?)
+        }
+        None => {
+            info!("Save file encryption disabled, saving...");
+            write_file_atomically(path, &ser)
         }
+    }
+}
 
-        let mut cocoon = MiniCocoon::from_key(&key.as_bytes()[0..32], &[0; 32]);
-        let decrypt = cocoon
-            .wrap(ser.as_ref())
-            .map_err(HappyChartError::EncryptionError)?;
-        save_file
-            .write_all(&decrypt)
-            .map_err(|err| HappyChartError::WriteSaveFileIO(err, PathBuf::from(save_path)))?;
-    } else {
-        info!("Save file encryption disabled, saving...");
-        save_file
-            .write_all(ser.as_bytes())
-            .map_err(|err| HappyChartError::WriteSaveFileIO(err, PathBuf::from(save_path)))?;
+/// Returns the path of a file stored next to `path`, with `extension` appended to its file name.
+pub fn sibling_file_path(path: &Path, extension: &str) -> PathBuf {
+    let mut file_name = path.file_name().map(OsString::from).unwrap_or_default();
+    file_name.push(".");
+    file_name.push(extension);
+    path.with_file_name(file_name)
+}
+
+/// Writes `data` to `path` such that a crash part way through never leaves a truncated file behind.
+/// The data is written to a temporary file and synced to disk, the current file is kept as the previous generation, and the temporary file is renamed into place.
+#[tracing::instrument(skip(data))]
+pub fn write_file_atomically(path: &Path, data: &[u8]) -> Result<(), HappyChartError> {
+    let temp_path = sibling_file_path(path, TEMP_FILE_EXTENSION);
+    let previous_generation_path = sibling_file_path(path, PREVIOUS_GENERATION_EXTENSION);
+
+    let mut temp_file = File::create(&temp_path)
+        .map_err(|io_error| HappyChartError::WriteSaveFileIO(io_error, temp_path.clone()))?;
+    temp_file
+        .write_all(data)
+        .map_err(|io_error| HappyChartError::WriteSaveFileIO(io_error, temp_path.clone()))?;
+    temp_file
+        .sync_all()
+        .map_err(|io_error| HappyChartError::WriteSaveFileIO(io_error, temp_path.clone()))?;
+    drop(temp_file);
+
+    if path.exists() {
+        fs::rename(path, &previous_generation_path).map_err(|io_error| {
+            HappyChartError::WriteSaveFileIO(io_error, previous_generation_path.clone())
+        })?;
     }
 
+    fs::rename(&temp_path, path)
+        .map_err(|io_error| HappyChartError::WriteSaveFileIO(io_error, PathBuf::from(path)))?;
+
+    sync_parent_directory(path);
+
+    info!("Atomically wrote {} bytes to {:?}", data.len(), path);
+
     Ok(())
 }
 
+/// Syncs the directory containing `path`, so the renames done by `write_file_atomically` are on disk as well.
+/// Directories can not be opened as files on windows, so this only does anything on unix like systems.
+#[tracing::instrument]
+fn sync_parent_directory(path: &Path) {
+    #[cfg(unix)]
+    {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        if let Err(err) = File::open(parent).and_then(|dir| dir.sync_all()) {
+            warn!("Unable to sync directory {:?}: {}", parent, err);
+        }
+    }
+}
+
 /// Reads the last session file, if exists, returns the deserialized contents, if it doesn't exist, returns a default `LastSession` struct.
+/// If the last session file can not be read, the previous generation of it is used instead.
 #[tracing::instrument]
-pub fn read_last_session_save_file() -> LastSession {
+pub fn read_last_session_save_file(data_paths: &DataPaths) -> LastSession {
     info!("Reading last session save file");
-    let path = Path::new(LAST_SESSION_FILE_NAME);
+    let last_session_path = data_paths.last_session_file();
+    let path = last_session_path.as_path();
+    let previous_generation_path = sibling_file_path(path, PREVIOUS_GENERATION_EXTENSION);
 
+    if let Some(last_session) = read_last_session_from_path(path) {
+        return last_session;
+    }
+
+    if let Some(last_session) = read_last_session_from_path(&previous_generation_path) {
+        warn!(
+            "Last session save file unreadable, using previous generation at path: {:?}",
+            previous_generation_path
+        );
+        return last_session;
+    }
+
+    if !path.exists() {
+        match File::create(path) {
+            // save file wasn't found, make one
+            Ok(_) => {
+                info!(
+                    "Last session save file not found, creating one at path: {:?}",
+                    path
+                );
+            }
+            Err(_) => {
+                error!(
+                    "Error creating save file at path: {:?}, using a default session save file",
+                    path
+                );
+            }
+        }
+    }
+
+    // cant read either generation of the last session, return a default last session just encase
+    LastSession::default()
+}
+
+/// Returns the last session stored at the given path, or None if the file is missing or unreadable
+#[tracing::instrument]
+fn read_last_session_from_path(path: &Path) -> Option<LastSession> {
     let mut file = match File::open(path) {
         // try to open save file
         Ok(f) => {
@@ -107,24 +289,7 @@ pub fn read_last_session_save_file() -> LastSession {
             f
         }
         Err(_) => {
-            match File::create(path) {
-                // save file wasn't found, make one
-                Ok(f) => {
-                    info!(
-                        "Last session save file not found, creating one at path: {:?}",
-                        path
-                    );
-                    f
-                }
-                Err(_) => {
-                    error!(
-                        "Error creating save file at path: {:?}, using a default session save file",
-                        path
-                    );
-                    // cant make save file, return a default last session just encase
-                    return LastSession::default();
-                }
-            }
+            return None;
         }
     };
 
@@ -135,40 +300,65 @@ pub fn read_last_session_save_file() -> LastSession {
             info!("Read last session save file successfully");
         }
         Err(_) => {
-            error!("Failed to read file for last session, using a default session");
-            // fail to read file as string, return a default last session just encase, this should only happen if invalid utf-8 exists in the save file.
-            return LastSession::default();
+            error!("Failed to read file for last session at path: {:?}", path);
+            // fail to read file as string, this should only happen if invalid utf-8 exists in the save file.
+            return None;
+        }
+    }
+
+    match serde_json::from_str(&s) {
+        Ok(last_session) => Some(last_session),
+        Err(err) => {
+            error!(
+                "Failed to deserialize last session at path: {:?} {}",
+                path, err
+            );
+            None
         }
     }
-    serde_json::from_str(&s).unwrap_or_default() // return the deserialized struct
 }
 
 /// Reads the save file, if found, returns the vector full of all the `DayStats`
+/// If the save file is corrupt, it is copied aside and the previous generation of the save file is loaded in its place.
 #[tracing::instrument]
-pub fn read_save_file() -> Result<Vec<ImprovedDayStat>, HappyChartError> {
-    let new_path = PathBuf::from(NEW_SAVE_FILE_NAME);
-    let path = Path::new(SAVE_FILE_NAME);
+pub fn read_save_file(data_paths: &DataPaths) -> Result<Vec<ImprovedDayStat>, HappyChartError> {
+    let new_path = data_paths.save_file();
+    let previous_generation_path = sibling_file_path(&new_path, PREVIOUS_GENERATION_EXTENSION);
+    let legacy_path = data_paths.legacy_save_file();
+    let path = legacy_path.as_path();
 
     info!(
         "Reading save file at path: {:?} with a fallback path of {:?}",
         new_path, path
     );
 
-    let mut file = match File::open(&new_path) {
+    let (mut file, opened_path) = match File::open(&new_path) {
         Ok(f) => {
             info!("Successfully opening save file at new path");
-            f
+            (f, new_path.clone())
         }
         Err(e) => {
-            match File::open(path) {
-                Ok(f) => {
-                    info!("Save file not found at new path, instead found at fallback path successfully");
-                    f
-                }
-                Err(e1) => {
-                    error!("Error finding save file in {:?} or fallback path of {:?}, errors in reading: {:?} and {:?}", new_path, path,e,e1);
-                    File::create(new_path.clone())
-                        .map_err(|io_error| HappyChartError::ReadSaveFileIO(io_error, new_path))?
+            if let Ok(f) = File::open(&previous_generation_path) {
+                // a save was interrupted after the save file was moved aside, but before the new one was moved into place
+                warn!(
+                    "Save file not found at new path, instead found the previous generation of it"
+                );
+                (f, previous_generation_path.clone())
+            } else {
+                match File::open(path) {
+                    Ok(f) => {
+                        info!("Save file not found at new path, instead found at fallback path successfully");
+                        (f, PathBuf::from(path))
+                    }
+                    Err(e1) => {
+                        error!("Error finding save file in {:?} or fallback path of {:?}, errors in reading: {:?} and {:?}", new_path, path,e,e1);
+                        (
+                            File::create(new_path.clone()).map_err(|io_error| {
+                                HappyChartError::ReadSaveFileIO(io_error, new_path.clone())
+                            })?,
+                            new_path.clone(),
+                        )
+                    }
                 }
             }
         }
@@ -185,37 +375,107 @@ pub fn read_save_file() -> Result<Vec<ImprovedDayStat>, HappyChartError> {
             return Ok(vec![]);
         }
     };
+    let data = &s[0..read_len];
 
-    // attempt to read old save file format
-    match serde_json::from_slice::<Vec<ImprovedDayStat>>(&s[0..read_len]) {
-        Ok(vec) => {
-            info!("Found modern save file");
-            // new save file format found, return it
-            Ok(vec)
-        }
-        Err(_err_improved) => {
-            // not old save file format, attempt to read it as new save file format
-            #[allow(deprecated)]
-            match serde_json::from_slice::<Vec<DayStat>>(&s[0..read_len]) {
-                Ok(v) => {
-                    info!("Found legacy save file, converting now");
-                    // old save file format found, convert it into new save file format
-                    Ok(v.into_iter()
-                        .map(|old_day_stat| old_day_stat.into())
-                        .collect::<Vec<ImprovedDayStat>>())
-                }
-                Err(e) => {
-                    warn!(
-                        "Error deserializing save file, most likely it is encrypted? {:?}",
-                        e
-                    );
-                    // cant read old or new save file format, so empty vec.
-
-                    return Err(HappyChartError::EncryptedSaveFile(
-                        (s[0..read_len]).to_vec(),
-                    ));
+    match deserialize_save_data(data) {
+        Ok((_, days)) => Ok(days),
+        Err(HappyChartError::Deserialization(..)) if is_encrypted_container(data) => {
+            warn!("Save file is not plain text, it is encrypted");
+            Err(HappyChartError::EncryptedSaveFile(data.to_vec()))
+        }
+        Err(HappyChartError::Deserialization(..))
+            if data.iter().all(u8::is_ascii_whitespace) && !previous_generation_path.exists() =>
+        {
+            info!("Save file is empty, starting a new save file");
+            Ok(vec![])
+        }
+        Err(HappyChartError::Deserialization(deserialize_error, _)) => {
+            error!(
+                "Save file at path {:?} is corrupt: {}",
+                opened_path, deserialize_error
+            );
+            let can_recover = opened_path == new_path;
+            Err(recover_corrupt_save_file(
+                &opened_path,
+                data,
+                deserialize_error,
+                can_recover.then_some(previous_generation_path.as_path()),
+            ))
+        }
+        Err(err) => {
+            error!(
+                "Unable to read save file at path {:?}: {}",
+                opened_path, err
+            );
+            Err(err)
+        }
+    }
+}
+
+/// Copies a corrupt save file aside so it is never overwritten, and attempts to read the previous generation of the save file
+#[tracing::instrument(skip(data, deserialize_error))]
+fn recover_corrupt_save_file(
+    path: &Path,
+    data: &[u8],
+    deserialize_error: serde_json::Error,
+    previous_generation_path: Option<&Path>,
+) -> HappyChartError {
+    let corrupt_copy_path = sibling_file_path(path, CORRUPT_FILE_EXTENSION);
+    match fs::write(&corrupt_copy_path, data) {
+        Ok(()) => {
+            info!("Copied corrupt save file to {:?}", corrupt_copy_path);
+        }
+        Err(err) => {
+            error!(
+                "Unable to copy corrupt save file to {:?}: {}",
+                corrupt_copy_path, err
+            );
+        }
+    }
+
+    let previous_generation = previous_generation_path
+        .and_then(|previous_generation_path| fs::read(previous_generation_path).ok());
+
+    let mut recovered_trash = vec![];
+    let recovered_days = match previous_generation {
+        Some(previous_data) => match deserialize_save_data(&previous_data) {
+            Ok((_, days)) => {
+                info!(
+                    "Recovered {} days from the previous save generation",
+                    days.len()
+                );
+                match read_save_trash(&previous_data) {
+                    Ok(trash) => recovered_trash = trash,
+                    Err(err) => {
+                        error!(
+                            "Unable to read the trash of the previous save generation: {}",
+                            err
+                        );
+                    }
                 }
+                recovered_trash.sort_by_key(|trashed| trashed.deleted);
+                Some(days)
+            }
+            Err(HappyChartError::Deserialization(..)) if is_encrypted_container(&previous_data) => {
+                warn!("Previous save generation is encrypted, prompting for its key");
+                return HappyChartError::EncryptedSaveFile(previous_data);
             }
+            Err(err) => {
+                error!("Previous save generation is also unreadable: {}", err);
+                None
+            }
+        },
+        None => {
+            warn!("No previous save generation to recover from");
+            None
         }
+    };
+
+    HappyChartError::CorruptSaveFile {
+        path: PathBuf::from(path),
+        corrupt_copy_path,
+        deserialize_error,
+        recovered_days,
+        recovered_trash,
     }
 }
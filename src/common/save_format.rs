@@ -0,0 +1,249 @@
+#[allow(deprecated)]
+use crate::day_stats::daystat::DayStat;
+use crate::day_stats::improved_daystat::ImprovedDayStat;
+use crate::day_stats::trashed_day::TrashedDay;
+use crate::state::error_states::HappyChartError;
+use crate::SAVE_FORMAT_VERSION;
+use chrono::{DateTime, Local};
+use self_update::cargo_crate_version;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::info;
+
+/// A migration upgrades the day list of a save file by a single format version
+type Migration = fn(Value) -> Result<Value, serde_json::Error>;
+
+/// Every migration in order, the migration at index `n` upgrades a day list from format version `n` to `n + 1`.
+/// Version 0 is the legacy `DayStat` list, version 1 is a bare `ImprovedDayStat` list, and version 2 wraps the day list in a `SaveEnvelope`.
+const MIGRATIONS: [Migration; SAVE_FORMAT_VERSION as usize] =
+    [migrate_legacy_day_stats, migrate_bare_day_list];
+
+/// Information stored alongside the day list in a save file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SaveMetadata {
+    pub format_version: u32,
+    /// The version of happy chart that last wrote the save file
+    pub app_version: String,
+    pub created: DateTime<Local>,
+    pub modified: DateTime<Local>,
+}
+
+impl SaveMetadata {
+    /// Metadata for a save file written by this version of happy chart
+    pub fn current(created: DateTime<Local>) -> Self {
+        Self {
+            format_version: SAVE_FORMAT_VERSION,
+            app_version: cargo_crate_version!().to_string(),
+            created,
+            modified: Local::now(),
+        }
+    }
+}
+
+/// The save file format since version 2, the day list is stored as the `days` field next to the metadata.
+/// Deleted days are stored as the `trash` field, save files written before the trash existed have none.
+#[derive(Serialize, Deserialize, Debug)]
+struct SaveEnvelope<T, U> {
+    #[serde(flatten)]
+    metadata: SaveMetadata,
+    days: T,
+    #[serde(default)]
+    trash: U,
+}
+
+/// Serializes the day list and the trash into the current save file format.
+/// `created` should be the creation date of the save file being replaced, if there is one.
+#[tracing::instrument(skip(days, trash))]
+pub fn serialize_save_data(
+    days: &[ImprovedDayStat],
+    trash: &[TrashedDay],
+    created: Option<DateTime<Local>>,
+) -> Result<Vec<u8>, HappyChartError> {
+    let envelope = SaveEnvelope {
+        metadata: SaveMetadata::current(created.unwrap_or_else(Local::now)),
+        days,
+        trash,
+    };
+    serde_json::to_vec(&envelope).map_err(HappyChartError::Serialization)
+}
+
+/// Deserializes save data of any known format version, migrating it to the current format.
+/// Save files written by a newer version of happy chart return `UnsupportedSaveFormatVersion`, anything unreadable returns `Deserialization`.
+#[tracing::instrument(skip_all)]
+pub fn deserialize_save_data(
+    data: &[u8],
+) -> Result<(SaveMetadata, Vec<ImprovedDayStat>), HappyChartError> {
+    let value = serde_json::from_slice::<Value>(data)
+        .map_err(|err| HappyChartError::Deserialization(err, None))?;
+
+    let (metadata, days) = match value {
+        Value::Object(_) => {
+            let envelope = serde_json::from_value::<SaveEnvelope<Value, Value>>(value)
+                .map_err(|err| HappyChartError::Deserialization(err, None))?;
+            (envelope.metadata, envelope.days)
+        }
+        Value::Array(_) => {
+            // save files from before the envelope existed have no metadata, so it is made up on the spot
+            let now = Local::now();
+            let metadata = SaveMetadata {
+                format_version: get_bare_day_list_version(&value),
+                app_version: String::new(),
+                created: now,
+                modified: now,
+            };
+            (metadata, value)
+        }
+        _ => {
+            // neither an envelope nor a day list, let serde describe what was found instead
+            let err = serde_json::from_value::<Vec<ImprovedDayStat>>(value)
+                .err()
+                .unwrap_or_else(|| serde::de::Error::custom("save data is not a day list"));
+            return Err(HappyChartError::Deserialization(err, None));
+        }
+    };
+
+    if metadata.format_version > SAVE_FORMAT_VERSION {
+        return Err(HappyChartError::UnsupportedSaveFormatVersion {
+            found: metadata.format_version,
+            supported: SAVE_FORMAT_VERSION,
+            app_version: metadata.app_version,
+        });
+    }
+
+    let days = MIGRATIONS
+        .iter()
+        .enumerate()
+        .skip(metadata.format_version as usize)
+        .try_fold(days, |days, (version, migration)| {
+            info!(
+                "Migrating save data from format version {} to {}",
+                version,
+                version + 1
+            );
+            migration(days)
+        })
+        .and_then(serde_json::from_value::<Vec<ImprovedDayStat>>)
+        .map_err(|err| HappyChartError::Deserialization(err, None))?;
+
+    info!(
+        "Read save data of format version {}, written by version {:?}",
+        metadata.format_version, metadata.app_version
+    );
+
+    Ok((metadata, days))
+}
+
+/// Returns the metadata of save data without migrating or keeping its day list
+#[tracing::instrument(skip_all)]
+pub fn read_save_metadata(data: &[u8]) -> Option<SaveMetadata> {
+    serde_json::from_slice::<SaveMetadata>(data).ok()
+}
+
+/// Returns the trash of save data, save data without a trash, such as a day list saved without an envelope, has an empty trash
+#[tracing::instrument(skip_all)]
+pub fn read_save_trash(data: &[u8]) -> Result<Vec<TrashedDay>, HappyChartError> {
+    let value = serde_json::from_slice::<Value>(data)
+        .map_err(|err| HappyChartError::Deserialization(err, None))?;
+    match value {
+        Value::Object(mut envelope) => match envelope.remove("trash") {
+            Some(trash) => serde_json::from_value::<Vec<TrashedDay>>(trash)
+                .map_err(|err| HappyChartError::Deserialization(err, None)),
+            None => Ok(vec![]),
+        },
+        _ => Ok(vec![]),
+    }
+}
+
+/// Returns the format version of a day list saved without an envelope, legacy `DayStat` entries store their date as a number
+fn get_bare_day_list_version(days: &Value) -> u32 {
+    let is_legacy = days
+        .as_array()
+        .and_then(|days| days.first())
+        .and_then(|day| day.get("date"))
+        .is_some_and(Value::is_number);
+    u32::from(!is_legacy)
+}
+
+/// Version 0 to 1, converts the legacy `DayStat` list into `ImprovedDayStat`
+#[allow(deprecated)]
+fn migrate_legacy_day_stats(days: Value) -> Result<Value, serde_json::Error> {
+    let days = serde_json::from_value::<Vec<DayStat>>(days)?
+        .into_iter()
+        .map(ImprovedDayStat::from)
+        .collect::<Vec<ImprovedDayStat>>();
+    serde_json::to_value(days)
+}
+
+/// Version 1 to 2, only added the envelope around the day list, so the days themselves are unchanged
+fn migrate_bare_day_list(days: Value) -> Result<Value, serde_json::Error> {
+    Ok(days)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEGACY_DAYS: &[u8] =
+        br#"[{"rating":10.0,"date":1700000000,"note":"first"},{"rating":20.0,"date":1700086400,"note":"second"}]"#;
+
+    fn test_days() -> Vec<ImprovedDayStat> {
+        deserialize_save_data(LEGACY_DAYS).unwrap().1
+    }
+
+    #[test]
+    fn legacy_day_stats_migrate_to_improved_day_stats() {
+        let migrated = migrate_legacy_day_stats(serde_json::from_slice(LEGACY_DAYS).unwrap())
+            .and_then(serde_json::from_value::<Vec<ImprovedDayStat>>)
+            .unwrap();
+        assert_eq!(migrated.len(), 2);
+        assert_eq!(migrated[0].get_date().timestamp(), 1_700_000_000);
+        assert_eq!(migrated[0].get_rating(), 10.0);
+        assert_eq!(migrated[1].get_note(), "second");
+
+        let (metadata, days) = deserialize_save_data(LEGACY_DAYS).unwrap();
+        assert_eq!(metadata.format_version, 0);
+        assert_eq!(days.len(), 2);
+    }
+
+    #[test]
+    fn bare_day_list_migrates_unchanged() {
+        let bare = serde_json::to_vec(&test_days()).unwrap();
+        let value = serde_json::from_slice::<Value>(&bare).unwrap();
+        assert_eq!(migrate_bare_day_list(value.clone()).unwrap(), value);
+
+        let (metadata, days) = deserialize_save_data(&bare).unwrap();
+        assert_eq!(metadata.format_version, 1);
+        assert_eq!(days[0].get_id(), test_days()[0].get_id());
+    }
+
+    #[test]
+    fn envelope_round_trips_with_its_trash() {
+        let days = test_days();
+        let trash = vec![TrashedDay::new(days[1].clone())];
+        let data = serialize_save_data(&days[..1], &trash, None).unwrap();
+
+        let (metadata, read_days) = deserialize_save_data(&data).unwrap();
+        assert_eq!(metadata.format_version, SAVE_FORMAT_VERSION);
+        assert_eq!(read_days.len(), 1);
+        assert_eq!(read_days[0].get_id(), days[0].get_id());
+
+        let read_trash = read_save_trash(&data).unwrap();
+        assert_eq!(read_trash.len(), 1);
+        assert_eq!(read_trash[0].day.get_id(), days[1].get_id());
+        assert!(read_save_trash(LEGACY_DAYS).unwrap().is_empty());
+    }
+
+    #[test]
+    fn newer_format_version_is_rejected() {
+        let data = serialize_save_data(&test_days(), &[], None).unwrap();
+        let mut envelope = serde_json::from_slice::<Value>(&data).unwrap();
+        envelope["format_version"] = Value::from(SAVE_FORMAT_VERSION + 1);
+
+        let result = deserialize_save_data(&serde_json::to_vec(&envelope).unwrap());
+        assert!(matches!(
+            result,
+            Err(HappyChartError::UnsupportedSaveFormatVersion { found, .. })
+                if found == SAVE_FORMAT_VERSION + 1
+        ));
+    }
+}
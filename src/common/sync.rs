@@ -0,0 +1,648 @@
+use crate::common::encryption::{is_encrypted_container, SaveCipher};
+use crate::common::persistence::PersistenceJob;
+use crate::common::save::write_file_atomically;
+use crate::prelude::{HappyChartState, ImprovedDayStat};
+use crate::state::error_states::HappyChartError;
+use crate::{SYNC_ENCRYPTED_LINE_PREFIX, SYNC_LOG_FILE_EXTENSION, SYNC_LOG_FILE_PREFIX};
+use chrono::{DateTime, Local};
+use egui::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// A single change to the journal, days are identified by their id
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum SyncOperation {
+    Upsert(ImprovedDayStat),
+    DeleteDay(Uuid),
+    /// Deletes the day at the given time, only read from change logs written before days were identified by their id
+    Delete(DateTime<Local>),
+}
+
+impl SyncOperation {
+    /// Returns the id of the day changed by the operation, None if it deletes a time no day is at
+    fn get_day_id(&self, days: &BTreeMap<Uuid, ImprovedDayStat>) -> Option<Uuid> {
+        match self {
+            Self::Upsert(day) => Some(*day.get_id()),
+            Self::DeleteDay(id) => Some(*id),
+            Self::Delete(date) => days
+                .values()
+                .find(|day| day.get_date() == date)
+                .map(|day| *day.get_id()),
+        }
+    }
+}
+
+/// A line of a device's change log
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncChange {
+    /// Lamport clock of the change, higher than every change its device had read when it was made.
+    /// Of two changes to the same day, the one with the higher clock wins, ties are broken by device id, so every device ends up with the same journal.
+    pub clock: u64,
+    pub device_id: String,
+    pub operation: SyncOperation,
+}
+
+impl SyncChange {
+    fn version(&self) -> (u64, String) {
+        (self.clock, self.device_id.clone())
+    }
+}
+
+/// What this device knows about the sync folder, kept in the data folder of the profile
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct SyncState {
+    /// The folder this state belongs to, the state is started over if a different sync folder is chosen
+    sync_folder: PathBuf,
+    /// Highest clock of any change read or written
+    clock: u64,
+    /// The journal as of the last sync, changes made on this device are found by comparing against it
+    days: Vec<ImprovedDayStat>,
+    /// Number of lines of each other device's change log that have been applied
+    applied_lines: BTreeMap<String, usize>,
+    /// Clock and device of the change that last set each day, by the id of the day, a change is only applied if it is newer.
+    /// States written before days were identified by their id kept these by time under another name, which is ignored as every change in the logs up to then has already been applied.
+    #[serde(default)]
+    day_versions: BTreeMap<Uuid, (u64, String)>,
+}
+
+/// Everything needed to sync the journal on the persistence worker
+pub struct SyncJob {
+    pub sync_folder: PathBuf,
+    pub state_path: PathBuf,
+    pub device_id: String,
+    pub days: Vec<ImprovedDayStat>,
+    /// Encrypts this device's change log and sync state, every device syncing the journal must use the same encryption key
+    pub cipher: Option<SaveCipher>,
+}
+
+/// The result of a sync
+#[derive(Debug)]
+pub struct SyncOutcome {
+    /// The day list the sync started from, so changes made while it ran can be applied on top of its result
+    pub synced_from: Vec<ImprovedDayStat>,
+    pub days: Vec<ImprovedDayStat>,
+    /// Changes made on this device written to its change log
+    pub sent: usize,
+    /// Changes made on other devices applied to the journal
+    pub received: usize,
+    /// Lines of other devices' change logs that could not be read, such as lines still being copied by a sync tool, they are read again on the next sync
+    pub unreadable: usize,
+}
+
+/// Writes the changes made on this device since the last sync to its change log, and applies the changes made on every other device since then
+#[tracing::instrument(skip(job), fields(sync_folder = ?job.sync_folder))]
+pub fn run_sync_job(job: SyncJob) -> Result<SyncOutcome, HappyChartError> {
+    fs::create_dir_all(&job.sync_folder)
+        .map_err(|err| HappyChartError::WriteSaveFileIO(err, job.sync_folder.clone()))?;
+
+    let mut state = match read_sync_state(&job.state_path, job.cipher.as_ref())? {
+        Some(state) if state.sync_folder == job.sync_folder => state,
+        _ => {
+            info!("Starting a new sync state for {:?}", job.sync_folder);
+            SyncState {
+                sync_folder: job.sync_folder.clone(),
+                ..SyncState::default()
+            }
+        }
+    };
+
+    let (remote_changes, unreadable) = read_remote_changes(&job, &mut state)?;
+    state.clock = remote_changes
+        .iter()
+        .map(|change| change.clock)
+        .fold(state.clock, u64::max);
+
+    let local_changes = diff_days(&state.days, &job.days)
+        .into_iter()
+        .map(|operation| SyncChange {
+            clock: state.clock + 1,
+            device_id: job.device_id.clone(),
+            operation,
+        })
+        .collect::<Vec<SyncChange>>();
+    if !local_changes.is_empty() {
+        state.clock += 1;
+        append_changes(
+            &get_change_log_path(&job.sync_folder, &job.device_id),
+            &local_changes,
+            job.cipher.as_ref(),
+        )?;
+    }
+    let mut days = job
+        .days
+        .iter()
+        .map(|day| (*day.get_id(), day.clone()))
+        .collect::<BTreeMap<Uuid, ImprovedDayStat>>();
+    for change in &local_changes {
+        if let Some(id) = change.operation.get_day_id(&days) {
+            state.day_versions.insert(id, change.version());
+        }
+    }
+
+    let mut received = 0;
+    for change in remote_changes {
+        let Some(id) = change.operation.get_day_id(&days) else {
+            continue;
+        };
+        let version = change.version();
+        if state
+            .day_versions
+            .get(&id)
+            .is_some_and(|applied_version| *applied_version >= version)
+        {
+            continue;
+        }
+        state.day_versions.insert(id, version);
+        apply_operation(&mut days, change.operation);
+        received += 1;
+    }
+
+    state.days = days.into_values().collect();
+    state.days.sort_by_key(|day| *day.get_date());
+    write_sync_state(&job.state_path, &state, job.cipher.as_ref())?;
+
+    info!(
+        "Synced journal: {} sent, {} received, {} unreadable",
+        local_changes.len(),
+        received,
+        unreadable
+    );
+    Ok(SyncOutcome {
+        days: state.days,
+        synced_from: job.days,
+        sent: local_changes.len(),
+        received,
+        unreadable,
+    })
+}
+
+/// Returns the operations that turn the `from` day list into the `to` day list
+pub fn diff_days(from: &[ImprovedDayStat], to: &[ImprovedDayStat]) -> Vec<SyncOperation> {
+    let serialize = |day: &ImprovedDayStat| serde_json::to_string(day).unwrap_or_default();
+    let from_days = from
+        .iter()
+        .map(|day| (*day.get_id(), serialize(day)))
+        .collect::<BTreeMap<Uuid, String>>();
+    let to_ids = to
+        .iter()
+        .map(|day| *day.get_id())
+        .collect::<BTreeSet<Uuid>>();
+
+    to.iter()
+        .filter(|day| from_days.get(day.get_id()) != Some(&serialize(day)))
+        .map(|day| SyncOperation::Upsert(day.clone()))
+        .chain(
+            from_days
+                .keys()
+                .filter(|id| !to_ids.contains(id))
+                .map(|id| SyncOperation::DeleteDay(*id)),
+        )
+        .collect()
+}
+
+fn apply_operation(days: &mut BTreeMap<Uuid, ImprovedDayStat>, operation: SyncOperation) {
+    match operation {
+        SyncOperation::Upsert(day) => {
+            days.insert(*day.get_id(), day);
+        }
+        SyncOperation::DeleteDay(id) => {
+            days.remove(&id);
+        }
+        SyncOperation::Delete(date) => {
+            days.retain(|_, day| *day.get_date() != date);
+        }
+    }
+}
+
+/// Applies the operations to the day list, returning it sorted by date
+pub fn apply_operations(
+    days: Vec<ImprovedDayStat>,
+    operations: Vec<SyncOperation>,
+) -> Vec<ImprovedDayStat> {
+    let mut days = days
+        .into_iter()
+        .map(|day| (*day.get_id(), day))
+        .collect::<BTreeMap<Uuid, ImprovedDayStat>>();
+    for operation in operations {
+        apply_operation(&mut days, operation);
+    }
+    let mut days = days.into_values().collect::<Vec<ImprovedDayStat>>();
+    days.sort_by_key(|day| *day.get_date());
+    days
+}
+
+fn get_change_log_path(sync_folder: &Path, device_id: &str) -> PathBuf {
+    sync_folder.join(format!(
+        "{}{}.{}",
+        SYNC_LOG_FILE_PREFIX, device_id, SYNC_LOG_FILE_EXTENSION
+    ))
+}
+
+/// Returns the device id of a change log, None if the file is not a change log
+fn get_change_log_device_id(path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_str()?;
+    file_name
+        .strip_prefix(SYNC_LOG_FILE_PREFIX)?
+        .strip_suffix(SYNC_LOG_FILE_EXTENSION)?
+        .strip_suffix('.')
+        .map(ToString::to_string)
+}
+
+/// Reads the lines of every other device's change log that have not been applied yet.
+/// A log is read up to its first unreadable line, so a line still being copied is read again on the next sync instead of skipped.
+fn read_remote_changes(
+    job: &SyncJob,
+    state: &mut SyncState,
+) -> Result<(Vec<SyncChange>, usize), HappyChartError> {
+    let entries = fs::read_dir(&job.sync_folder)
+        .map_err(|err| HappyChartError::ReadSaveFileIO(err, job.sync_folder.clone()))?;
+
+    let mut changes = vec![];
+    let mut unreadable = 0;
+    for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        let Some(device_id) = get_change_log_device_id(&path) else {
+            continue;
+        };
+        if device_id == job.device_id {
+            continue;
+        }
+        let log = match fs::read_to_string(&path) {
+            Ok(log) => log,
+            Err(err) => {
+                warn!("Unable to read change log {:?}: {}", path, err);
+                unreadable += 1;
+                continue;
+            }
+        };
+
+        let applied_lines = state.applied_lines.entry(device_id).or_default();
+        let lines = log.lines().skip(*applied_lines).collect::<Vec<&str>>();
+        for (index, line) in lines.iter().enumerate() {
+            match parse_change(line, job.cipher.as_ref()) {
+                Some(change) => {
+                    changes.push(change);
+                    *applied_lines += 1;
+                }
+                None => {
+                    warn!(
+                        "Unable to read line {} of change log {:?}",
+                        *applied_lines + 1,
+                        path
+                    );
+                    unreadable += lines.len() - index;
+                    break;
+                }
+            }
+        }
+    }
+    Ok((changes, unreadable))
+}
+
+fn parse_change(line: &str, cipher: Option<&SaveCipher>) -> Option<SyncChange> {
+    match line.strip_prefix(SYNC_ENCRYPTED_LINE_PREFIX) {
+        Some(encrypted_line) => {
+            let data = cipher?.decrypt(&decode_hex(encrypted_line)?).ok()?;
+            serde_json::from_slice(&data).ok()
+        }
+        None => serde_json::from_str(line).ok(),
+    }
+}
+
+/// Appends the changes to the end of a change log, encrypting each line if a cipher is given
+fn append_changes(
+    path: &Path,
+    changes: &[SyncChange],
+    cipher: Option<&SaveCipher>,
+) -> Result<(), HappyChartError> {
+    let mut lines = String::new();
+    // a line left unfinished by a crash must not swallow the first new line
+    if fs::read(path).is_ok_and(|log| log.last().is_some_and(|last| *last != b'\n')) {
+        lines.push('\n');
+    }
+    for change in changes {
+        let ser = serde_json::to_string(change).map_err(HappyChartError::Serialization)?;
+        match cipher {
+            Some(cipher) => {
+                lines.push_str(SYNC_ENCRYPTED_LINE_PREFIX);
+                lines.push_str(&encode_hex(&cipher.encrypt(ser.as_bytes())?));
+            }
+            None => lines.push_str(&ser),
+        }
+        lines.push('\n');
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| HappyChartError::WriteSaveFileIO(err, path.to_path_buf()))?;
+    file.write_all(lines.as_bytes())
+        .and_then(|()| file.sync_all())
+        .map_err(|err| HappyChartError::WriteSaveFileIO(err, path.to_path_buf()))?;
+    info!("Appended {} changes to {:?}", changes.len(), path);
+    Ok(())
+}
+
+fn read_sync_state(
+    path: &Path,
+    cipher: Option<&SaveCipher>,
+) -> Result<Option<SyncState>, HappyChartError> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(HappyChartError::ReadSaveFileIO(err, path.to_path_buf())),
+    };
+    let data = match (is_encrypted_container(&data), cipher) {
+        (true, Some(cipher)) => cipher.decrypt(&data)?,
+        (true, None) => {
+            warn!("Sync state is encrypted but encryption is disabled, starting it over");
+            return Ok(None);
+        }
+        (false, _) => data,
+    };
+    serde_json::from_slice(&data)
+        .map(Some)
+        .map_err(|err| HappyChartError::Deserialization(err, None))
+}
+
+fn write_sync_state(
+    path: &Path,
+    state: &SyncState,
+    cipher: Option<&SaveCipher>,
+) -> Result<(), HappyChartError> {
+    let ser = serde_json::to_vec(state).map_err(HappyChartError::Serialization)?;
+    match cipher {
+        Some(cipher) => write_file_atomically(path, &cipher.encrypt(&ser)?),
+        None => write_file_atomically(path, &ser),
+    }
+}
+
+fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// Queues a sync with the sync folder, if one is chosen and the journal can be changed
+#[tracing::instrument(skip(ctx, app))]
+pub fn queue_sync(ctx: &Context, app: &mut HappyChartState) -> Result<(), HappyChartError> {
+    let Some(sync_folder) = &app.program_options.sync_folder else {
+        return Ok(());
+    };
+    if app.read_only {
+        return Err(HappyChartError::ReadOnlySession);
+    }
+    if app.encryption_change_running {
+        return Err(HappyChartError::EncryptionChangeRunning);
+    }
+    let cipher = app.get_save_cipher();
+    if app.program_options.encrypt_save_file && cipher.is_none() {
+        // the journal is still locked, so there is nothing to sync yet
+        return Ok(());
+    }
+
+    app.last_sync_time = SystemTime::now();
+    let job = SyncJob {
+        sync_folder: app.data_paths.resolve(sync_folder),
+        state_path: app.data_paths.sync_state_file(),
+        device_id: app.program_options.sync_device_id.clone(),
+        days: app.days.clone(),
+        cipher,
+    };
+    app.persistence.queue(ctx, PersistenceJob::Sync(job));
+    Ok(())
+}
+
+/// Syncs with the sync folder every sync interval
+#[tracing::instrument(skip(ctx, app))]
+pub fn sync_when_due(ctx: &Context, app: &mut HappyChartState) {
+    if app.program_options.sync_folder.is_none()
+        || app.first_load
+        || app.loading
+        || app.read_only
+        || app.external_change.is_some()
+        || app.encryption_change_running
+        || app.error_states.iter().any(HappyChartError::blocks_saving)
+    {
+        return;
+    }
+    let Ok(interval_minutes) = u64::try_from(app.program_options.sync_interval_minutes) else {
+        return;
+    };
+
+    let interval = Duration::from_secs(interval_minutes * 60);
+    let elapsed = app.last_sync_time.elapsed().unwrap_or_default();
+    if elapsed < interval {
+        ctx.request_repaint_after(interval - elapsed);
+        return;
+    }
+
+    if let Err(err) = queue_sync(ctx, app) {
+        warn!("Unable to sync: {}", err);
+        app.last_sync_time = SystemTime::now();
+        app.error_states.push(err);
+    }
+}
+
+/// Applies the result of a sync to the journal, keeping any change made while the sync ran.
+/// Days the entry edit policy has made read-only are kept as they are, the next sync sends them to the other devices again.
+#[tracing::instrument(skip(app, outcome))]
+pub fn apply_sync_outcome(app: &mut HappyChartState, outcome: SyncOutcome) {
+    let changes_during_sync = diff_days(&outcome.synced_from, &app.days);
+    let mut days = apply_operations(outcome.days, changes_during_sync);
+    let kept_count = app.keep_locked_days(&mut days);
+    if !diff_days(&app.days, &days).is_empty() {
+        app.replace_days(days);
+        app.mark_changed();
+    }
+
+    let mut status = format!(
+        "Synced at {}, {} changes sent, {} received",
+        Local::now().format("%H:%M:%S"),
+        outcome.sent,
+        outcome.received
+    );
+    if outcome.unreadable > 0 {
+        status.push_str(&format!(
+            ", {} changes from other devices could not be read yet, they may still be being copied, or were encrypted with a different key",
+            outcome.unreadable
+        ));
+    }
+    if kept_count > 0 {
+        status.push_str(&format!(
+            ", {} changes to read-only entries were not applied",
+            kept_count
+        ));
+    }
+    app.ui_states.sync_status = Some(status);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    /// A device syncing through the shared sync folder, with its own data folder holding its sync state
+    struct TestDevice {
+        device_id: String,
+        data_dir: PathBuf,
+    }
+
+    /// Temporary folders for two devices and the sync folder they share, removed when dropped
+    struct TestFolders {
+        root: PathBuf,
+        sync_folder: PathBuf,
+        first: TestDevice,
+        second: TestDevice,
+    }
+
+    impl TestFolders {
+        fn new() -> Self {
+            let root =
+                env::temp_dir().join(format!("happy_chart_sync_test_{}", rand::random::<u64>()));
+            let device = |device_id: &str| TestDevice {
+                device_id: device_id.to_string(),
+                data_dir: root.join(device_id),
+            };
+            let folders = Self {
+                sync_folder: root.join("sync"),
+                first: device("first"),
+                second: device("second"),
+                root,
+            };
+            fs::create_dir_all(&folders.first.data_dir).unwrap();
+            fs::create_dir_all(&folders.second.data_dir).unwrap();
+            folders
+        }
+
+        fn sync(&self, device: &TestDevice, days: &[ImprovedDayStat]) -> Vec<ImprovedDayStat> {
+            let outcome = run_sync_job(SyncJob {
+                sync_folder: self.sync_folder.clone(),
+                state_path: device.data_dir.join("sync_state.json"),
+                device_id: device.device_id.clone(),
+                days: days.to_vec(),
+                cipher: None,
+            })
+            .unwrap();
+            assert_eq!(outcome.unreadable, 0);
+            outcome.days
+        }
+    }
+
+    impl Drop for TestFolders {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn serialize(days: &[ImprovedDayStat]) -> String {
+        serde_json::to_string(days).unwrap()
+    }
+
+    fn new_day(note: &str) -> ImprovedDayStat {
+        ImprovedDayStat::new(50.0, Local::now(), note, vec![], vec![])
+    }
+
+    /// Both devices start out with the same synced journal
+    fn start_synced(folders: &TestFolders, days: &[ImprovedDayStat]) -> Vec<ImprovedDayStat> {
+        let first_days = folders.sync(&folders.first, days);
+        let second_days = folders.sync(&folders.second, &[]);
+        assert_eq!(serialize(&first_days), serialize(&second_days));
+        second_days
+    }
+
+    /// Syncs both devices until neither has anything left to exchange
+    fn sync_both(
+        folders: &TestFolders,
+        first_days: &[ImprovedDayStat],
+        second_days: &[ImprovedDayStat],
+    ) -> (Vec<ImprovedDayStat>, Vec<ImprovedDayStat>) {
+        let first_days = folders.sync(&folders.first, first_days);
+        let second_days = folders.sync(&folders.second, second_days);
+        let first_days = folders.sync(&folders.first, &first_days);
+        let second_days = folders.sync(&folders.second, &second_days);
+        (first_days, second_days)
+    }
+
+    #[test]
+    fn concurrent_edits_of_the_same_day_converge() {
+        let folders = TestFolders::new();
+        let days = start_synced(&folders, &[new_day("morning"), new_day("evening")]);
+
+        let mut first_days = days.clone();
+        first_days[0].edit_rating(10.0);
+        let mut second_days = days;
+        second_days[0].edit_note("edited on the second device");
+
+        let (first_days, second_days) = sync_both(&folders, &first_days, &second_days);
+        assert_eq!(serialize(&first_days), serialize(&second_days));
+        assert_eq!(first_days.len(), 2);
+        // the second device synced its edit last, so its version wins
+        assert_eq!(first_days[0].get_note(), "edited on the second device");
+        assert_eq!(first_days[0].get_rating(), 50.0);
+    }
+
+    #[test]
+    fn delete_racing_an_edit_converges() {
+        let folders = TestFolders::new();
+        let days = start_synced(&folders, &[new_day("morning"), new_day("evening")]);
+        let raced_id = *days[0].get_id();
+
+        let first_days = days[1..].to_vec();
+        let mut second_days = days;
+        second_days[0].edit_note("edited while deleted elsewhere");
+
+        let (first_days, second_days) = sync_both(&folders, &first_days, &second_days);
+        assert_eq!(serialize(&first_days), serialize(&second_days));
+        // the edit was synced after the delete, so the edited day is kept
+        assert!(first_days
+            .iter()
+            .any(|day| *day.get_id() == raced_id
+                && day.get_note() == "edited while deleted elsewhere"));
+    }
+
+    #[test]
+    fn edit_racing_a_delete_converges() {
+        let folders = TestFolders::new();
+        let days = start_synced(&folders, &[new_day("morning"), new_day("evening")]);
+        let raced_id = *days[0].get_id();
+
+        let mut first_days = days.clone();
+        first_days[0].edit_note("edited while deleted elsewhere");
+        let second_days = days[1..].to_vec();
+
+        let (first_days, second_days) = sync_both(&folders, &first_days, &second_days);
+        assert_eq!(serialize(&first_days), serialize(&second_days));
+        // the delete was synced after the edit, so the day is deleted
+        assert!(first_days.iter().all(|day| *day.get_id() != raced_id));
+    }
+
+    #[test]
+    fn day_moved_to_another_time_is_not_duplicated() {
+        let folders = TestFolders::new();
+        let days = start_synced(&folders, &[new_day("morning")]);
+
+        let mut second_days = days.clone();
+        let moved_date = *days[0].get_date() - chrono::Duration::hours(3);
+        second_days[0].edit_date(moved_date);
+        let (first_days, second_days) = sync_both(&folders, &days, &second_days);
+        assert_eq!(serialize(&first_days), serialize(&second_days));
+        assert_eq!(first_days.len(), 1);
+        assert_eq!(*first_days[0].get_date(), moved_date);
+    }
+}
@@ -0,0 +1,108 @@
+/// A line of a line by line comparison of two texts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffLine<T> {
+    Unchanged(T),
+    /// Only in the old text
+    Removed(T),
+    /// Only in the new text
+    Added(T),
+}
+
+impl DiffLine<&str> {
+    /// Copies the line out of the texts it was compared from, so the diff can be kept after they change
+    pub fn to_owned_line(self) -> DiffLine<String> {
+        match self {
+            Self::Unchanged(line) => DiffLine::Unchanged(line.to_string()),
+            Self::Removed(line) => DiffLine::Removed(line.to_string()),
+            Self::Added(line) => DiffLine::Added(line.to_string()),
+        }
+    }
+}
+
+/// Compares two texts line by line, keeping the longest common subsequence of lines as unchanged.
+/// Notes are short, so the quadratic table of common subsequence lengths is cheap enough.
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<&'a str>> {
+    let old_lines = old.lines().collect::<Vec<&str>>();
+    let new_lines = new.lines().collect::<Vec<&str>>();
+
+    // common[i][j] is the length of the longest common subsequence of old_lines[i..] and new_lines[j..]
+    let mut common = vec![vec![0usize; new_lines.len() + 1]; old_lines.len() + 1];
+    for i in (0..old_lines.len()).rev() {
+        for j in (0..new_lines.len()).rev() {
+            common[i][j] = if old_lines[i] == new_lines[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old_lines.len() && j < new_lines.len() {
+        if old_lines[i] == new_lines[j] {
+            diff.push(DiffLine::Unchanged(old_lines[i]));
+            i += 1;
+            j += 1;
+        } else if common[i + 1][j] >= common[i][j + 1] {
+            diff.push(DiffLine::Removed(old_lines[i]));
+            i += 1;
+        } else {
+            diff.push(DiffLine::Added(new_lines[j]));
+            j += 1;
+        }
+    }
+    diff.extend(old_lines[i..].iter().copied().map(DiffLine::Removed));
+    diff.extend(new_lines[j..].iter().copied().map(DiffLine::Added));
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DiffLine::{Added, Removed, Unchanged};
+
+    #[test]
+    fn identical_texts_are_unchanged() {
+        assert_eq!(
+            diff_lines("first\nsecond", "first\nsecond"),
+            vec![Unchanged("first"), Unchanged("second")]
+        );
+    }
+
+    #[test]
+    fn insertion_is_added() {
+        assert_eq!(
+            diff_lines("first\nthird", "first\nsecond\nthird"),
+            vec![Unchanged("first"), Added("second"), Unchanged("third")]
+        );
+    }
+
+    #[test]
+    fn deletion_is_removed() {
+        assert_eq!(
+            diff_lines("first\nsecond\nthird", "first\nthird"),
+            vec![Unchanged("first"), Removed("second"), Unchanged("third")]
+        );
+    }
+
+    #[test]
+    fn changed_middle_line_is_removed_then_added() {
+        assert_eq!(
+            diff_lines("first\nsecond\nthird", "first\nchanged\nthird"),
+            vec![
+                Unchanged("first"),
+                Removed("second"),
+                Added("changed"),
+                Unchanged("third")
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_texts() {
+        assert_eq!(diff_lines("", ""), vec![]);
+        assert_eq!(diff_lines("", "new"), vec![Added("new")]);
+        assert_eq!(diff_lines("old", ""), vec![Removed("old")]);
+    }
+}
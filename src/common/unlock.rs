@@ -0,0 +1,101 @@
+use crate::common::data_paths::DataPaths;
+use crate::common::encryption::{KeyDerivationSettings, SaveCipher};
+use crate::common::first_load;
+use crate::common::persistence::PersistenceJob;
+use crate::common::save::read_last_session_save_file;
+use crate::day_stats::trashed_day::TrashedDay;
+use crate::options::program_options::ProgramOptions;
+use crate::prelude::{HappyChartState, ImprovedDayStat};
+use crate::state::error_states::HappyChartError;
+use crate::storage::{open_storage, StorageBackendKind};
+use egui::Context;
+use tracing::{error, info};
+
+/// Everything needed to unlock an encrypted save file on the persistence worker
+pub struct UnlockJob {
+    data_paths: DataPaths,
+    storage_backend: StorageBackendKind,
+    password: String,
+    settings: KeyDerivationSettings,
+}
+
+/// The day list, trash and program options read from an unlocked save file
+pub struct UnlockOutcome {
+    days: Vec<ImprovedDayStat>,
+    trash: Vec<TrashedDay>,
+    /// None if the last session file has no encrypted program options
+    program_options: Option<ProgramOptions>,
+}
+
+/// Queues unlocking the save file with the encryption key entered in the decryption screen.
+/// Deriving the key and decrypting every day can take a while, so it is left to the persistence worker.
+#[tracing::instrument(skip_all)]
+pub fn queue_unlock(ctx: &Context, app: &mut HappyChartState) {
+    let job = UnlockJob {
+        data_paths: app.data_paths.clone(),
+        storage_backend: app.program_options.storage_backend,
+        password: app.encryption_key.clone(),
+        settings: app.program_options.key_derivation_settings,
+    };
+    app.ui_states.unlocking = true;
+    app.persistence.queue(ctx, PersistenceJob::Unlock(job));
+}
+
+/// Decrypts the day list, the trash and the program options with the password of the job
+#[tracing::instrument(skip_all)]
+pub fn run_unlock_job(
+    job: UnlockJob,
+    report_progress: impl Fn(String, f32),
+) -> Result<UnlockOutcome, HappyChartError> {
+    let cipher = SaveCipher::new(&job.password, job.settings);
+    let mut storage = open_storage(&job.data_paths, job.storage_backend, Some(cipher.clone()))?;
+
+    report_progress("Decrypting save file".to_string(), 0.0);
+    let days = storage.load()?;
+    report_progress("Decrypting trash".to_string(), 0.6);
+    let trash = storage.load_trash()?;
+
+    // the full program options are encrypted with the same key as the save file
+    report_progress("Decrypting program options".to_string(), 0.8);
+    let program_options =
+        read_last_session_save_file(&job.data_paths).decrypt_program_options(&cipher)?;
+
+    Ok(UnlockOutcome {
+        days,
+        trash,
+        program_options,
+    })
+}
+
+/// Applies an unlocked save file to the program state and runs the rest of program startup, or shows why it could not be unlocked
+#[tracing::instrument(skip_all)]
+pub fn finish_unlock(
+    ctx: &Context,
+    app: &mut HappyChartState,
+    result: Result<Box<UnlockOutcome>, HappyChartError>,
+) {
+    app.ui_states.unlocking = false;
+    let outcome = match result {
+        Ok(outcome) => outcome,
+        Err(err) => {
+            error!("Error decrypting save file: {:?}", err);
+            app.error_states.push(err);
+            return;
+        }
+    };
+
+    info!("Unlocked save file with {} days", outcome.days.len());
+    if let Some(program_options) = outcome.program_options {
+        app.program_options = program_options;
+    }
+    // set the second key equal to the first key so after the user unlocks the save file, they don't have to re-type their password
+    app.encryption_key_second_check = app.encryption_key.to_string();
+    app.days = outcome.days;
+    app.trash = outcome.trash;
+
+    app.error_states.retain(|err| {
+        !matches!(err, HappyChartError::DecryptionError(_))
+            && !matches!(err, HappyChartError::EncryptedSaveFile(_))
+    });
+    first_load(app, ctx, false);
+}
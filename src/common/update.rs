@@ -35,7 +35,7 @@ pub fn update_program() -> JoinHandle<Result<Status, String>> {
 }
 
 #[tracing::instrument]
-pub fn get_release_list() -> Result<Vec<Release>, Box<dyn Error>> {
+pub fn get_release_list() -> Result<Vec<Release>, Box<dyn Error + Send + Sync>> {
     info!("Getting release list");
     let list = self_update::backends::github::ReleaseList::configure()
         .repo_owner("CoryRobertson")
@@ -0,0 +1,129 @@
+use crate::prelude::MoodTag;
+use crate::state::activities::Activity;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::mem::discriminant;
+
+/// The value of a part of a day before and after it was edited
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum RevisionChange {
+    Rating {
+        old: f32,
+        new: f32,
+    },
+    Moods {
+        old: Vec<MoodTag>,
+        new: Vec<MoodTag>,
+    },
+    Activities {
+        old: Vec<Activity>,
+        new: Vec<Activity>,
+    },
+    Date {
+        old: DateTime<Local>,
+        new: DateTime<Local>,
+    },
+    Note {
+        old: String,
+        new: String,
+    },
+    /// The entry edit policy was overridden so the read-only day could be changed, kept with the day as a record of the override
+    LockOverridden {
+        reason: String,
+    },
+}
+
+/// An edit made to a day after it was added, kept with the day so its original values are never lost
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct DayRevision {
+    pub time: DateTime<Local>,
+    pub change: RevisionChange,
+}
+
+impl RevisionChange {
+    /// Returns true if both changes are to the same part of a day
+    pub fn is_same_field(&self, other: &Self) -> bool {
+        discriminant(self) == discriminant(other)
+    }
+
+    /// Returns true if the change leaves the value as it was
+    pub fn is_unchanged(&self) -> bool {
+        match self {
+            Self::Rating { old, new } => old == new,
+            Self::Moods { old, new } => old == new,
+            Self::Activities { old, new } => old == new,
+            Self::Date { old, new } => old == new,
+            Self::Note { old, new } => old == new,
+            Self::LockOverridden { .. } => false,
+        }
+    }
+
+    /// Returns the change with the new value replaced, keeping the old value
+    pub fn with_new_value_of(&self, later: &Self) -> Self {
+        match (self, later) {
+            (Self::Rating { old, .. }, Self::Rating { new, .. }) => Self::Rating {
+                old: *old,
+                new: *new,
+            },
+            (Self::Moods { old, .. }, Self::Moods { new, .. }) => Self::Moods {
+                old: old.clone(),
+                new: new.clone(),
+            },
+            (Self::Activities { old, .. }, Self::Activities { new, .. }) => Self::Activities {
+                old: old.clone(),
+                new: new.clone(),
+            },
+            (Self::Date { old, .. }, Self::Date { new, .. }) => Self::Date {
+                old: *old,
+                new: *new,
+            },
+            (Self::Note { old, .. }, Self::Note { new, .. }) => Self::Note {
+                old: old.clone(),
+                new: new.clone(),
+            },
+            _ => later.clone(),
+        }
+    }
+
+    /// A short description of the change, shown in the revision history of a day
+    pub fn get_description(&self) -> String {
+        let mood_names = |moods: &[MoodTag]| {
+            moods
+                .iter()
+                .map(|mood| mood.get_text())
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        let activity_names = |activities: &[Activity]| {
+            activities
+                .iter()
+                .map(|activity| activity.get_activity_name())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        };
+        match self {
+            Self::Rating { old, new } => format!("Rating: {:.1} -> {:.1}", old, new),
+            Self::Moods { old, new } => {
+                format!("Moods: [{}] -> [{}]", mood_names(old), mood_names(new))
+            }
+            Self::Activities { old, new } => format!(
+                "Activities: [{}] -> [{}]",
+                activity_names(old),
+                activity_names(new)
+            ),
+            Self::Date { old, new } => format!(
+                "Date: {} -> {}",
+                old.format("%Y-%m-%d %H:%M"),
+                new.format("%Y-%m-%d %H:%M")
+            ),
+            Self::Note { old, new } => format!(
+                "Note: {} -> {} characters",
+                old.chars().count(),
+                new.chars().count()
+            ),
+            Self::LockOverridden { reason } => {
+                format!("Unlocked against the entry edit policy, {}", reason)
+            }
+        }
+    }
+}
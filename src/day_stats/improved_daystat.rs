@@ -1,29 +1,121 @@
+use crate::day_stats::day_revision::{DayRevision, RevisionChange};
 #[allow(deprecated)]
 use crate::day_stats::daystat::DayStat;
 use crate::prelude::MoodTag;
 use crate::state::activities::Activity;
+use crate::EDIT_HISTORY_MERGE_SECONDS;
 use chrono::{DateTime, Datelike, Local, Timelike};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
 use std::fmt::{Display, Formatter};
+use uuid::{Builder, Uuid};
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
-#[serde(default)]
+#[serde(from = "StoredDayStat")]
 pub struct ImprovedDayStat {
+    /// Identifies the day for as long as it exists, unlike its position in the day list, which changes as days are added and removed
+    id: Uuid,
     rating: f32,
     date: DateTime<Local>,
     note: String,
     mood_tags: Vec<MoodTag>,
     activities: Vec<Activity>,
+    /// Every edit made to the day since it was added, oldest first
+    revisions: Vec<DayRevision>,
+}
+
+/// An `ImprovedDayStat` as it is stored, days saved before they had an id are given one when read
+#[derive(Deserialize)]
+#[serde(default)]
+struct StoredDayStat {
+    id: Option<Uuid>,
+    rating: f32,
+    date: DateTime<Local>,
+    note: String,
+    mood_tags: Vec<MoodTag>,
+    activities: Vec<Activity>,
+    revisions: Vec<DayRevision>,
+}
+
+impl Default for StoredDayStat {
+    fn default() -> Self {
+        let day = ImprovedDayStat::default();
+        Self {
+            id: None,
+            rating: day.rating,
+            date: day.date,
+            note: day.note,
+            mood_tags: day.mood_tags,
+            activities: day.activities,
+            revisions: day.revisions,
+        }
+    }
+}
+
+impl From<StoredDayStat> for ImprovedDayStat {
+    fn from(value: StoredDayStat) -> Self {
+        Self {
+            id: value.id.unwrap_or_else(|| get_legacy_day_id(&value.date)),
+            rating: value.rating,
+            date: value.date,
+            note: value.note,
+            mood_tags: value.mood_tags,
+            activities: value.activities,
+            revisions: value.revisions,
+        }
+    }
+}
+
+/// Returns the id given to a day saved before days had ids.
+/// The id is derived from the date of the day, so the same legacy day is given the same id every time it is read, on every device.
+fn get_legacy_day_id(date: &DateTime<Local>) -> Uuid {
+    let hash = Sha256::new()
+        .chain_update(b"happy_chart_day")
+        .chain_update(date.timestamp_micros().to_le_bytes())
+        .finalize();
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&hash[..16]);
+    Builder::from_custom_bytes(bytes).into_uuid()
+}
+
+/// Gives every day that repeats the id of an earlier day in the list an id of its own, returning the number of days given a new id.
+/// Legacy days from the same second are given the same id by `get_legacy_day_id`, so each repeat is given an id derived from the repeated id and how many times it repeated,
+/// which keeps reading the same day list giving the same ids, on every device.
+pub fn assign_unique_day_ids(days: &mut [ImprovedDayStat]) -> usize {
+    let mut seen = HashSet::new();
+    let mut reassigned = 0;
+    for day in days.iter_mut() {
+        let repeated_id = day.id;
+        let mut repeat = 0u64;
+        while !seen.insert(day.id) {
+            repeat += 1;
+            let hash = Sha256::new()
+                .chain_update(b"happy_chart_repeated_day")
+                .chain_update(repeated_id.as_bytes())
+                .chain_update(repeat.to_le_bytes())
+                .finalize();
+            let mut bytes = [0u8; 16];
+            bytes.copy_from_slice(&hash[..16]);
+            day.id = Builder::from_custom_bytes(bytes).into_uuid();
+        }
+        if repeat > 0 {
+            reassigned += 1;
+        }
+    }
+    reassigned
 }
 
 impl Default for ImprovedDayStat {
     fn default() -> Self {
         Self {
+            id: Uuid::new_v4(),
             rating: 0.0,
             date: Local::now(),
             note: "DEFAULT NOTE".to_string(),
             mood_tags: vec![],
             activities: vec![],
+            revisions: vec![],
         }
     }
 }
@@ -79,23 +171,193 @@ impl ImprovedDayStat {
         activities: Vec<Activity>,
     ) -> Self {
         Self {
+            id: Uuid::new_v4(),
             rating,
             date,
             note: note.to_string(),
             mood_tags,
             activities,
+            revisions: vec![],
+        }
+    }
+
+    pub fn get_id(&self) -> &Uuid {
+        &self.id
+    }
+
+    /// Sets the rating, recording the edit as a revision
+    pub fn edit_rating(&mut self, rating: f32) {
+        let old = self.rating;
+        self.rating = rating;
+        self.record_revision(RevisionChange::Rating { old, new: rating }, true);
+    }
+
+    /// Sets the moods, recording the edit as a revision
+    pub fn edit_moods(&mut self, mood_tags: Vec<MoodTag>) {
+        let old = std::mem::replace(&mut self.mood_tags, mood_tags.clone());
+        self.record_revision(
+            RevisionChange::Moods {
+                old,
+                new: mood_tags,
+            },
+            true,
+        );
+    }
+
+    /// Sets the activities, recording the edit as a revision
+    pub fn edit_activities(&mut self, activities: Vec<Activity>) {
+        let old = std::mem::replace(&mut self.activities, activities.clone());
+        self.record_revision(
+            RevisionChange::Activities {
+                old,
+                new: activities,
+            },
+            true,
+        );
+    }
+
+    /// Sets the note, recording the edit as a revision
+    pub fn edit_note(&mut self, note: &str) {
+        let old = std::mem::replace(&mut self.note, note.to_string());
+        self.record_revision(
+            RevisionChange::Note {
+                old,
+                new: note.to_string(),
+            },
+            true,
+        );
+    }
+
+    /// Moves the day to another date and time, recording the edit as a revision
+    pub fn edit_date(&mut self, date: DateTime<Local>) {
+        let old = std::mem::replace(&mut self.date, date);
+        self.record_revision(RevisionChange::Date { old, new: date }, true);
+    }
+
+    /// Edits every part of the day that differs from `target` to match it, recording the edits as revisions, so the revisions of the day are kept
+    pub fn edit_to_match(&mut self, target: &Self) {
+        if self.rating != target.rating {
+            self.edit_rating(target.rating);
+        }
+        if self.mood_tags != target.mood_tags {
+            self.edit_moods(target.mood_tags.clone());
+        }
+        if self.activities != target.activities {
+            self.edit_activities(target.activities.clone());
+        }
+        if self.date != target.date {
+            self.edit_date(target.date);
+        }
+        if self.note != target.note {
+            self.edit_note(&target.note);
         }
     }
 
-    pub fn get_activities_mut(&mut self) -> &mut Vec<Activity> {
-        &mut self.activities
+    /// Records that the entry edit policy was overridden for this day, so the override is saved along with it
+    pub fn record_lock_override(&mut self, reason: &str) {
+        self.record_revision(
+            RevisionChange::LockOverridden {
+                reason: reason.to_string(),
+            },
+            false,
+        );
     }
-    pub fn get_moods_mut(&mut self) -> &mut Vec<MoodTag> {
-        &mut self.mood_tags
+
+    pub fn get_revisions(&self) -> &[DayRevision] {
+        &self.revisions
+    }
+
+    /// Restores the values the day had after its first `revision_count` revisions, zero restoring the values it was added with.
+    /// The revert is itself recorded as revisions, so the values it replaces are kept as well.
+    /// A restored date is passed through `unique_date`, so the day does not end up at the same time as another day.
+    pub fn revert_to_revision(
+        &mut self,
+        revision_count: usize,
+        unique_date: impl FnOnce(DateTime<Local>) -> DateTime<Local>,
+    ) {
+        let mut rating = None;
+        let mut mood_tags = None;
+        let mut activities = None;
+        let mut date = None;
+        let mut note = None;
+        // the old value of the first later edit to each part of the day is the value it had at that revision
+        for revision in self.revisions.iter().skip(revision_count) {
+            match &revision.change {
+                RevisionChange::Rating { old, .. } => {
+                    rating.get_or_insert(*old);
+                }
+                RevisionChange::Moods { old, .. } => {
+                    mood_tags.get_or_insert_with(|| old.clone());
+                }
+                RevisionChange::Activities { old, .. } => {
+                    activities.get_or_insert_with(|| old.clone());
+                }
+                RevisionChange::Date { old, .. } => {
+                    date.get_or_insert(*old);
+                }
+                RevisionChange::Note { old, .. } => {
+                    note.get_or_insert_with(|| old.clone());
+                }
+                RevisionChange::LockOverridden { .. } => {}
+            }
+        }
+
+        if let Some(rating) = rating {
+            let old = std::mem::replace(&mut self.rating, rating);
+            self.record_revision(RevisionChange::Rating { old, new: rating }, false);
+        }
+        if let Some(mood_tags) = mood_tags {
+            let old = std::mem::replace(&mut self.mood_tags, mood_tags.clone());
+            self.record_revision(
+                RevisionChange::Moods {
+                    old,
+                    new: mood_tags,
+                },
+                false,
+            );
+        }
+        if let Some(activities) = activities {
+            let old = std::mem::replace(&mut self.activities, activities.clone());
+            self.record_revision(
+                RevisionChange::Activities {
+                    old,
+                    new: activities,
+                },
+                false,
+            );
+        }
+        if let Some(date) = date.map(unique_date) {
+            let old = std::mem::replace(&mut self.date, date);
+            self.record_revision(RevisionChange::Date { old, new: date }, false);
+        }
+        if let Some(note) = note {
+            let old = std::mem::replace(&mut self.note, note.clone());
+            self.record_revision(RevisionChange::Note { old, new: note }, false);
+        }
     }
 
-    pub fn modify_rating(&mut self) -> &mut f32 {
-        &mut self.rating
+    /// Adds an edit to the revision list.
+    /// When `merge` is set, an edit to the same part of the day shortly after the last revision, such as from dragging a slider, extends that revision instead.
+    fn record_revision(&mut self, change: RevisionChange, merge: bool) {
+        let now = Local::now();
+        if merge {
+            if let Some(last) = self.revisions.last_mut() {
+                if last.change.is_same_field(&change)
+                    && now.signed_duration_since(last.time).num_seconds()
+                        < EDIT_HISTORY_MERGE_SECONDS
+                {
+                    last.change = last.change.with_new_value_of(&change);
+                    last.time = now;
+                    if last.change.is_unchanged() {
+                        self.revisions.pop();
+                    }
+                    return;
+                }
+            }
+        }
+        if !change.is_unchanged() {
+            self.revisions.push(DayRevision { time: now, change });
+        }
     }
 
     pub fn get_rating(&self) -> f32 {
@@ -117,6 +379,13 @@ impl ImprovedDayStat {
         &self.activities
     }
 
+    /// Returns true if the search term is in the note, is the name of one of the moods, or is the start of the id of the day
+    pub fn matches_search(&self, term: &str) -> bool {
+        self.note.contains(term)
+            || MoodTag::get_mood_by_name(term).is_some_and(|mood| self.mood_tags.contains(&mood))
+            || (term.len() >= 4 && self.id.to_string().starts_with(&term.to_lowercase()))
+    }
+
     pub fn get_current_time_system() -> DateTime<Local> {
         Local::now()
     }
@@ -137,11 +406,13 @@ impl From<DayStat> for ImprovedDayStat {
     fn from(value: DayStat) -> Self {
         let v = value.get_date_time().with_timezone(&Local);
         Self {
+            id: get_legacy_day_id(&v),
             rating: value.rating,
             date: v,
             note: value.note,
             mood_tags: vec![],
             activities: vec![],
+            revisions: vec![],
         }
     }
 }
@@ -172,12 +443,92 @@ impl From<&ImprovedDayStat> for DayStat {
 #[allow(deprecated)]
 impl From<&DayStat> for ImprovedDayStat {
     fn from(value: &DayStat) -> Self {
+        let date = value.get_date_time().with_timezone(&Local);
         Self {
+            id: get_legacy_day_id(&date),
             rating: value.rating,
-            date: value.get_date_time().with_timezone(&Local),
+            date,
             note: value.note.clone(),
             mood_tags: vec![],
             activities: vec![],
+            revisions: vec![],
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::data_paths::{DataLocation, DataPaths};
+    use crate::common::save_format::deserialize_save_data;
+    use crate::storage::{migrate_storage, StorageBackendKind};
+
+    #[test]
+    fn editing_to_match_keeps_the_revisions() {
+        let mut day = ImprovedDayStat::default();
+        day.edit_rating(10.0);
+        let mut target = day.clone();
+        target.edit_note("edited");
+
+        // a revision the target does not have, such as one made after it was taken
+        day.record_lock_override("reason");
+        day.edit_to_match(&target);
+
+        assert_eq!(day.get_note(), "edited");
+        assert_eq!(day.get_rating(), 10.0);
+        assert_eq!(day.get_revisions().len(), 3);
+        assert!(matches!(
+            day.get_revisions()[1].change,
+            RevisionChange::LockOverridden { .. }
+        ));
+        assert!(matches!(
+            day.get_revisions()[2].change,
+            RevisionChange::Note { .. }
+        ));
+    }
+
+    #[test]
+    fn legacy_days_from_the_same_second_get_unique_ids() {
+        let legacy_save =
+            br#"[{"rating":10.0,"date":1700000000,"note":"first"},{"rating":20.0,"date":1700000000,"note":"second"},{"rating":30.0,"date":1700000000,"note":"third"}]"#;
+        let (_, mut days) = deserialize_save_data(legacy_save).unwrap();
+        assert_eq!(days[0].get_id(), days[1].get_id());
+
+        assert_eq!(assign_unique_day_ids(&mut days), 2);
+        let ids = days
+            .iter()
+            .map(|day| *day.get_id())
+            .collect::<HashSet<Uuid>>();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(days[0].get_id(), &get_legacy_day_id(days[0].get_date()));
+
+        // reading the same save again gives the same ids
+        let (_, mut reread) = deserialize_save_data(legacy_save).unwrap();
+        assign_unique_day_ids(&mut reread);
+        for (day, reread_day) in days.iter().zip(&reread) {
+            assert_eq!(day.get_id(), reread_day.get_id());
+            assert_eq!(day.get_note(), reread_day.get_note());
+        }
+
+        // once saved with their new ids, nothing is reassigned
+        assert_eq!(assign_unique_day_ids(&mut days), 0);
+
+        // the days can be moved into SQLite storage, which keys its rows by id
+        let dir = std::env::temp_dir().join(format!(
+            "happy_chart_legacy_id_test_{}",
+            rand::random::<u64>()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data_paths = DataPaths::in_single_dir(dir.clone(), DataLocation::CommandLine);
+        let migrated = migrate_storage(
+            &data_paths,
+            &days,
+            &[],
+            StorageBackendKind::JsonFile,
+            StorageBackendKind::Sqlite,
+            None,
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        migrated.unwrap();
+    }
+}
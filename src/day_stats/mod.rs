@@ -1,2 +1,4 @@
+pub mod day_revision;
 pub mod daystat;
 pub mod improved_daystat;
+pub mod trashed_day;
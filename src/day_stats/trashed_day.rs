@@ -0,0 +1,25 @@
+use crate::day_stats::improved_daystat::ImprovedDayStat;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+/// A day that was deleted from the journal, kept in the trash so it can be restored until it is purged
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct TrashedDay {
+    pub deleted: DateTime<Local>,
+    pub day: ImprovedDayStat,
+}
+
+impl TrashedDay {
+    pub fn new(day: ImprovedDayStat) -> Self {
+        Self {
+            deleted: Local::now(),
+            day,
+        }
+    }
+
+    /// Returns true if the day has been in the trash for longer than `retention_days`, a negative retention keeps it forever
+    pub fn is_expired(&self, retention_days: i32, now: DateTime<Local>) -> bool {
+        retention_days >= 0
+            && now.signed_duration_since(self.deleted).num_days() >= i64::from(retention_days)
+    }
+}
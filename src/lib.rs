@@ -1,30 +1,103 @@
 #![deny(clippy::suboptimal_flops, clippy::cast_lossless)]
-#![allow(clippy::uninlined_format_args)]
+#![allow(
+    clippy::uninlined_format_args,
+    clippy::derivable_impls,
+    clippy::needless_borrows_for_generic_args,
+    clippy::unnecessary_map_or,
+    clippy::unnecessary_option_map_or_else
+)]
 pub(crate) mod common;
 pub(crate) mod day_stats;
 pub(crate) mod options;
 pub(crate) mod state;
+pub(crate) mod storage;
 pub(crate) mod ui;
 
 pub mod prelude {
+    pub use crate::common::data_paths::{migrate_working_directory_files, DataPaths};
     pub use crate::common::mood_tag::*;
+    pub use crate::common::profiles::get_startup_data_paths;
     pub use crate::common::save::read_last_session_save_file;
     pub use crate::common::save::read_save_file;
     pub use crate::day_stats::improved_daystat::*;
     pub use crate::state::happy_chart_state::HappyChartState;
+    pub use crate::storage::json_storage::JsonFileStorage;
+    pub use crate::storage::sqlite_storage::SqliteStorage;
+    pub use crate::storage::{open_storage, StorageBackend, StorageBackendKind};
 }
 
 // TODO: use source engine / half-life menu sounds when clicking and mousing over buttons
 
 pub(crate) const SAVE_FILE_NAME: &str = "save.ser";
 pub(crate) const NEW_SAVE_FILE_NAME: &str = "happy_chart_save.ser";
+pub(crate) const SQLITE_SAVE_FILE_NAME: &str = "happy_chart_save.sqlite";
+/// Format version of the save file written by this version of happy chart, see `common::save_format` for older versions
+pub(crate) const SAVE_FORMAT_VERSION: u32 = 2;
+/// Extension of files written while changing the encryption password, before they replace the original files
+pub(crate) const REKEY_STAGED_EXTENSION: &str = "rekey";
+/// Extension original files are moved to while changing the encryption password, until every file has been replaced
+pub(crate) const REKEY_ROLLBACK_EXTENSION: &str = "rekey_old";
 pub(crate) const LAST_SESSION_FILE_NAME: &str = "happy_chart_last_session.ser";
 pub(crate) const BACKUP_FILENAME_PREFIX: &str = "happy_chart_backup_";
 pub(crate) const MANUAL_BACKUP_SUFFIX: &str = "_manual";
+/// Suffix of the backup taken automatically before a backup is restored
+pub(crate) const PRE_RESTORE_BACKUP_SUFFIX: &str = "_pre_restore";
 pub(crate) const BACKUP_FILE_EXTENSION: &str = "zip";
+/// Format of the time a backup was made, as written in its file name
+pub(crate) const BACKUP_FILE_TIME_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+/// Format of the date in the names of backups made before the time of day was included
+pub(crate) const LEGACY_BACKUP_FILE_DATE_FORMAT: &str = "%m-%d-%Y";
+/// Name of the entry in every backup archive listing the other entries and their checksums
+pub(crate) const BACKUP_MANIFEST_FILE_NAME: &str = "happy_chart_backup_manifest.json";
 pub(crate) const GIT_DESCRIBE: &str = env!("VERGEN_GIT_DESCRIBE");
 pub(crate) const BUILD_TIMESTAMP: &str = env!("VERGEN_BUILD_TIMESTAMP");
 pub(crate) const MIN_ENCRYPT_KEY_LENGTH: usize = 4;
 pub(crate) const MAX_ENCRYPT_KEY_LENGTH: usize = 32;
+/// Highest Argon2 memory cost, in kibibytes, that a key is derived with, higher costs in an encryption header are rejected before deriving anything
+pub(crate) const MAX_KEY_DERIVATION_MEMORY_KIB: u32 = 1024 * 1024;
+/// Highest Argon2 iteration count that a key is derived with
+pub(crate) const MAX_KEY_DERIVATION_ITERATIONS: u32 = 16;
+/// Highest Argon2 parallelism that a key is derived with
+pub(crate) const MAX_KEY_DERIVATION_PARALLELISM: u32 = 8;
+/// Default age in days after which the entry edit policy applies to an entry
 pub(crate) const NOTE_OLD_NUM_DAYS: u32 = 3;
 pub(crate) const LOG_FILE_NAME: &str = "happy_chart_rs.log";
+/// Name of the folder created inside the platform data and config directories
+pub(crate) const APP_DIRECTORY_NAME: &str = "happy_chart_rs";
+/// A file with this name next to the executable makes happy chart store all of its data next to the executable
+pub(crate) const PORTABLE_MARKER_FILE_NAME: &str = "happy_chart_portable";
+/// Name of the folder inside the data and config folders that holds a folder for each named profile
+pub(crate) const PROFILES_DIRECTORY_NAME: &str = "profiles";
+/// Name of the file in the config folder remembering the last used profile
+pub(crate) const PROFILE_SETTINGS_FILE_NAME: &str = "happy_chart_profiles.json";
+/// Shown in place of a profile name for the profile stored directly in the data folders
+pub(crate) const DEFAULT_PROFILE_NAME: &str = "Default";
+/// Name of the file in the data folder marking that a running copy of happy chart has the profile open
+pub(crate) const LOCK_FILE_NAME: &str = "happy_chart.lock";
+/// Seconds between rewrites of the heartbeat in the lock file
+pub(crate) const LOCK_HEARTBEAT_SECONDS: u64 = 60;
+/// Seconds without a heartbeat before a lock file is considered left behind by a crash
+pub(crate) const STALE_LOCK_SECONDS: i64 = 300;
+/// Seconds between checks for the save file being changed by another program
+pub(crate) const SAVE_FILE_CHECK_SECONDS: u64 = 5;
+/// Minutes apart two days with the same note can be, and still be offered as duplicates of each other when merging journals
+pub(crate) const MERGE_NEAR_DUPLICATE_MINUTES: i64 = 60;
+/// Number of edits kept in the edit history, older edits can no longer be undone
+pub(crate) const EDIT_HISTORY_LIMIT: usize = 100;
+/// Seconds between repeated edits of the same part of a day for them to be treated as a single edit, both when undoing and in the revision history of the day
+pub(crate) const EDIT_HISTORY_MERGE_SECONDS: i64 = 2;
+/// Name of the file in the data folder holding what this device knows about the sync folder
+pub(crate) const SYNC_STATE_FILE_NAME: &str = "happy_chart_sync_state.ser";
+/// Start of the file name of every change log in the sync folder, followed by the id of the device writing it
+pub(crate) const SYNC_LOG_FILE_PREFIX: &str = "happy_chart_sync_";
+pub(crate) const SYNC_LOG_FILE_EXTENSION: &str = "jsonl";
+/// Start of an encrypted line of a change log, followed by the encrypted change in hex
+pub(crate) const SYNC_ENCRYPTED_LINE_PREFIX: &str = "encrypted:";
+/// Command line argument used to override the folder happy chart stores its data in
+pub(crate) const DATA_DIR_ARG: &str = "--data-dir";
+/// Extension appended to a file name while it is being written, before it is renamed into place
+pub(crate) const TEMP_FILE_EXTENSION: &str = "tmp";
+/// Extension appended to a file name to store the previous generation of that file
+pub(crate) const PREVIOUS_GENERATION_EXTENSION: &str = "bak";
+/// Extension appended to a save file name when it could not be read, so the damaged data is never overwritten
+pub(crate) const CORRUPT_FILE_EXTENSION: &str = "corrupt";
@@ -3,7 +3,10 @@
 
 use eframe::NativeOptions;
 use egui::{Vec2, ViewportBuilder};
-use happy_chart_rs::prelude::{read_last_session_save_file, HappyChartState};
+use happy_chart_rs::prelude::{
+    get_startup_data_paths, migrate_working_directory_files, read_last_session_save_file,
+    DataPaths, HappyChartState,
+};
 
 #[tracing::instrument]
 fn main() {
@@ -13,7 +16,13 @@ fn main() {
     )
     .expect("Unable to setup tracy layer");
 
-    let window_size: Vec2 = read_last_session_save_file().window_size.into();
+    let data_paths = DataPaths::from_args(std::env::args());
+    data_paths.create_dirs();
+    let data_migration_report = migrate_working_directory_files(&data_paths);
+    let data_paths = get_startup_data_paths(&data_paths);
+    data_paths.create_dirs();
+
+    let window_size: Vec2 = read_last_session_save_file(&data_paths).window_size.into();
 
     let native_options = NativeOptions {
         viewport: ViewportBuilder::default().with_inner_size(window_size),
@@ -23,7 +32,7 @@ fn main() {
     eframe::run_native(
         "Happy Chart",
         native_options,
-        Box::new(|cc| Box::new(HappyChartState::new(cc))),
+        Box::new(|cc| Box::new(HappyChartState::new(cc, data_paths, data_migration_report))),
     )
     .expect("Failed to run egui app");
 }
@@ -43,7 +43,7 @@ pub fn get_day_line_color() -> Color32 {
     Color32::from_rgba_unmultiplied(50, 50, 50, 100)
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(default)]
 pub struct ColorSettings {
     /// Color getter for text when displayed dynamically, not including font color for ui text.
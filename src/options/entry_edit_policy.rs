@@ -0,0 +1,61 @@
+use crate::day_stats::improved_daystat::ImprovedDayStat;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use strum_macros::EnumIter;
+
+/// Whether journal entries can still be changed once they are older than `entry_edit_policy_days`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumIter)]
+pub enum EntryEditPolicy {
+    AlwaysEditable,
+    /// Older entries can be changed, with a warning shown while editing them
+    WarnAfterDays,
+    /// Older entries can not be changed, edited, removed, or replaced by a merge, unless the lock is overridden
+    ReadOnlyAfterDays,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for EntryEditPolicy {
+    fn default() -> Self {
+        Self::WarnAfterDays
+    }
+}
+
+impl Display for EntryEditPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AlwaysEditable => write!(f, "Always editable"),
+            Self::WarnAfterDays => write!(f, "Warn when old"),
+            Self::ReadOnlyAfterDays => write!(f, "Read-only when old"),
+        }
+    }
+}
+
+/// What the entry edit policy allows for a single entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryEditState {
+    Editable,
+    /// The entry can be changed, but is old enough to warn about it
+    Warn,
+    Locked,
+}
+
+impl EntryEditPolicy {
+    /// Returns what this policy allows for the given day, `days` being the age in days after which the policy applies
+    pub fn get_edit_state(&self, days: u32, day: &ImprovedDayStat) -> EntryEditState {
+        self.get_date_edit_state(days, day.get_date())
+    }
+
+    /// Returns what this policy allows for a day at the given date, such as a day about to be added at a picked date
+    pub fn get_date_edit_state(&self, days: u32, date: &DateTime<Local>) -> EntryEditState {
+        let age_days = Local::now().signed_duration_since(date).num_days();
+        if age_days <= i64::from(days) {
+            return EntryEditState::Editable;
+        }
+        match self {
+            Self::AlwaysEditable => EntryEditState::Editable,
+            Self::WarnAfterDays => EntryEditState::Warn,
+            Self::ReadOnlyAfterDays => EntryEditState::Locked,
+        }
+    }
+}
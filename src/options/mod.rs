@@ -1,2 +1,3 @@
 pub mod color_setting;
+pub mod entry_edit_policy;
 pub mod program_options;
@@ -1,10 +1,18 @@
+use crate::common::backup_destination::BackupDestination;
+use crate::common::encryption::KeyDerivationSettings;
+use crate::day_stats::improved_daystat::ImprovedDayStat;
 use crate::options::color_setting::ColorSettings;
+use crate::options::entry_edit_policy::{EntryEditPolicy, EntryEditState};
 use crate::state::activities::ActivitySelectionList;
 use crate::state::prompt_states::UserPromptStates;
+use crate::storage::StorageBackendKind;
+use crate::NOTE_OLD_NUM_DAYS;
+use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
+use std::iter;
 use std::path::PathBuf;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(default)]
 pub struct ProgramOptions {
     pub graph_x_scale: f32,
@@ -23,13 +31,11 @@ pub struct ProgramOptions {
     /// Mostly unused, but most likely will be used to determine if we should try to update the program every N number of launches
     pub update_modulus: i32,
     pub color_settings: ColorSettings,
-    pub backup_save_path: PathBuf,
-    /// Days to elapse FULLY between automatically backing up the program state to a zip
-    pub auto_backup_days: i32,
-    /// Number of days FULLY elapsed before a backup is considered stale and will be deleted when there are enough backups present
-    pub backup_age_keep_days: i32,
-    /// The minimum number of backups before we try to automatically clean up stale backups
-    pub number_of_kept_backups: i32,
+    /// Where backups are written, and the schedule and retention of backups written there
+    #[serde(flatten)]
+    pub primary_backup_destination: BackupDestination,
+    /// Further folders every backup is mirrored to, each with its own schedule and retention
+    pub additional_backup_destinations: Vec<BackupDestination>,
     /// Draw a different color outline for day stats within a streak of time
     pub show_streak: bool,
     /// The gap in hours a streak is considered valid
@@ -51,6 +57,26 @@ pub struct ProgramOptions {
     pub activity_list: ActivitySelectionList,
     pub log_to_file: bool,
     pub user_prompts: UserPromptStates,
+
+    /// The kind of storage the day list is saved with
+    pub storage_backend: StorageBackendKind,
+    /// Cost of deriving the encryption key from the password, applied on the next save
+    pub key_derivation_settings: KeyDerivationSettings,
+    /// Encrypt backups with a key entered each session, instead of the save file encryption key
+    pub separate_backup_key: bool,
+    /// Seconds without a change before unsaved changes are saved automatically, -1 for disabled
+    pub autosave_idle_seconds: i32,
+    /// Folder shared between devices by a file sync tool, the journal is synced through it if set
+    pub sync_folder: Option<PathBuf>,
+    /// Minutes between syncs with the sync folder, -1 to only sync when asked to
+    pub sync_interval_minutes: i32,
+    /// Identifies this device's change log in the sync folder, chosen when a sync folder is first set
+    pub sync_device_id: String,
+    /// Whether entries older than `entry_edit_policy_days` can still be changed
+    pub entry_edit_policy: EntryEditPolicy,
+    pub entry_edit_policy_days: u32,
+    /// Days a deleted entry is kept in the trash before it is permanently deleted, -1 to keep it until the trash is emptied
+    pub trash_retention_days: i32,
 }
 
 impl Default for ProgramOptions {
@@ -71,10 +97,8 @@ impl Default for ProgramOptions {
             draw_daystat_lines: true,
             update_modulus: -1,
             color_settings: ColorSettings::default(),
-            backup_save_path: PathBuf::from("./backups/"),
-            auto_backup_days: -1,
-            backup_age_keep_days: -1,
-            number_of_kept_backups: -1,
+            primary_backup_destination: BackupDestination::default(),
+            additional_backup_destinations: vec![],
             show_streak: true,
             streak_leniency: 36,
             disable_update_list_error_showing: false,
@@ -86,6 +110,48 @@ impl Default for ProgramOptions {
             activity_list: ActivitySelectionList::default(),
             log_to_file: false,
             user_prompts: UserPromptStates::default(),
+            storage_backend: StorageBackendKind::default(),
+            key_derivation_settings: KeyDerivationSettings::default(),
+            separate_backup_key: false,
+            autosave_idle_seconds: 30,
+            sync_folder: None,
+            sync_interval_minutes: 5,
+            sync_device_id: String::new(),
+            entry_edit_policy: EntryEditPolicy::default(),
+            entry_edit_policy_days: NOTE_OLD_NUM_DAYS,
+            trash_retention_days: 30,
+        }
+    }
+}
+
+impl ProgramOptions {
+    /// Returns every backup destination, the primary destination first
+    pub fn get_backup_destinations(&self) -> impl Iterator<Item = &BackupDestination> {
+        iter::once(&self.primary_backup_destination).chain(&self.additional_backup_destinations)
+    }
+
+    /// Returns what the entry edit policy allows for the given day
+    pub fn get_entry_edit_state(&self, day: &ImprovedDayStat) -> EntryEditState {
+        self.entry_edit_policy
+            .get_edit_state(self.entry_edit_policy_days, day)
+    }
+
+    /// Returns what the entry edit policy allows for a day at the given date
+    pub fn get_entry_date_edit_state(&self, date: &DateTime<Local>) -> EntryEditState {
+        self.entry_edit_policy
+            .get_date_edit_state(self.entry_edit_policy_days, date)
+    }
+
+    /// Returns the options needed before an encrypted save file is unlocked, every other option is reset to its default.
+    /// These are the only options stored in plain text in the last session file while encryption is enabled.
+    pub fn get_unprotected_options(&self) -> Self {
+        Self {
+            encrypt_save_file: self.encrypt_save_file,
+            log_to_file: self.log_to_file,
+            storage_backend: self.storage_backend,
+            key_derivation_settings: self.key_derivation_settings,
+            separate_backup_key: self.separate_backup_key,
+            ..Self::default()
         }
     }
 }
@@ -29,7 +29,7 @@ impl Activity {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ActivitySelectionList {
     activity_list: Vec<Activity>,
 }
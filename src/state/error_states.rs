@@ -1,6 +1,14 @@
+use crate::common::backup_manifest::BackupProblem;
+use crate::common::encryption::KeyDerivationSettings;
+use crate::common::instance_lock::LockOwner;
+use crate::day_stats::improved_daystat::ImprovedDayStat;
+use crate::day_stats::trashed_day::TrashedDay;
+use crate::storage::StorageBackendKind;
+use chrono::{DateTime, Local};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::path::PathBuf;
+use uuid::Uuid;
 
 #[derive(Debug)]
 pub enum HappyChartError {
@@ -8,7 +16,7 @@ pub enum HappyChartError {
     Deserialization(serde_json::Error, Option<serde_json::Error>),
     ReadSaveFileIO(std::io::Error, PathBuf),
     WriteSaveFileIO(std::io::Error, PathBuf),
-    UpdateReleaseList(Box<dyn Error>),
+    UpdateReleaseList(Box<dyn Error + Send + Sync>),
     SaveBackupIO(std::io::Error),
     ExportIO(std::io::Error, Option<PathBuf>),
     /// Error thrown if the save file read was unreadable, suggesting it is encrypted, this error does not open the regular error screen, and instead prompts the user to enter an encryption key
@@ -28,6 +36,107 @@ pub enum HappyChartError {
         /// true if the secondary key is too long
         secondary_key_problem: bool,
     },
+    /// Error thrown if the save file could not be read, and is not encrypted, the previous generation of the save file is read in its place if possible
+    CorruptSaveFile {
+        /// The path of the corrupt save file
+        path: PathBuf,
+        /// The path the corrupt save file was copied to, so it can be inspected later
+        corrupt_copy_path: PathBuf,
+        deserialize_error: serde_json::Error,
+        /// The days read from the previous generation of the save file, if it could be read
+        recovered_days: Option<Vec<ImprovedDayStat>>,
+        /// The trash read from the previous generation of the save file, empty if it could not be read
+        recovered_trash: Vec<TrashedDay>,
+    },
+    SqliteStorage(rusqlite::Error, PathBuf),
+    /// Error thrown when a day with the given id could not be found in the storage backend
+    StorageDayNotFound(Uuid),
+    /// Error thrown when the days read back after a storage migration do not match the days that were migrated
+    StorageMigrationMismatch {
+        from: StorageBackendKind,
+        to: StorageBackendKind,
+    },
+    /// Error thrown when the current encryption key entered while changing the encryption password is not the key in use
+    IncorrectEncryptionKey,
+    /// Error thrown when a file could not be re-encrypted while changing the encryption password, every file is left as it was
+    EncryptionChange {
+        path: PathBuf,
+        error: Box<HappyChartError>,
+    },
+    /// Error thrown when backups are encrypted with a separate key, but that key has not been entered this session or is invalid
+    BackupEncryptionKey(Box<HappyChartError>),
+    /// Error thrown when an encryption key could not be derived from the password, most likely due to invalid key derivation settings
+    KeyDerivation(argon2::Error),
+    /// Error thrown when encrypted data has a header version this version of happy chart does not understand
+    UnsupportedEncryptionHeader(u8),
+    /// Error thrown when an encryption header asks for a key to be derived with cost settings above the limits
+    UnsupportedKeyDerivationSettings(KeyDerivationSettings),
+    /// Error thrown when a save file was written by a newer version of happy chart, using a format this version does not understand
+    UnsupportedSaveFormatVersion {
+        /// The format version found in the save file
+        found: u32,
+        /// The newest format version this version of happy chart can read
+        supported: u32,
+        /// The version of happy chart that wrote the save file
+        app_version: String,
+    },
+    /// Error thrown when a backup archive is encrypted, and no encryption key was given to read it with
+    EncryptedBackup(PathBuf),
+    /// Error thrown when a backup archive does not contain a save file in any known format
+    BackupMissingSaveFile(PathBuf),
+    /// Error thrown when a backup archive does not match its manifest, or has no manifest, meaning the backup is corrupt or incomplete
+    CorruptBackup {
+        path: PathBuf,
+        problem: BackupProblem,
+    },
+    /// Error thrown when a backup could not be written to one of the backup destinations, the other destinations are still backed up to
+    BackupDestination {
+        name: String,
+        path: PathBuf,
+        error: Box<HappyChartError>,
+    },
+    /// Error thrown at startup when a backup destination has not received a backup within its warning day count
+    StaleBackupDestination {
+        name: String,
+        path: PathBuf,
+        /// When the newest backup in the destination was made, None if it has no backups
+        last_backup: Option<DateTime<Local>>,
+        warn_after_days: i32,
+    },
+    /// Error thrown when a profile is created with a name that can not be used as a folder name, or that is already taken
+    InvalidProfileName(String),
+    /// Error thrown when another running copy of happy chart holds the lock on the profile, this error does not open the regular error screen, and instead asks the user how to continue
+    InstanceLocked {
+        path: PathBuf,
+        /// None if the lock file could not be read, it is then taken as held until it has not been written for a while
+        owner: Option<LockOwner>,
+    },
+    /// Error thrown when saving while the profile is open read-only, as another running copy of happy chart holds its lock
+    ReadOnlySession,
+    /// Error thrown when saving while the save files are being re-encrypted with a new password
+    EncryptionChangeRunning,
+    /// Error thrown when saving over a save file that was changed by another program since it was last read or written
+    SaveFileChangedExternally(PathBuf),
+    /// Error thrown when a journal chosen to merge is encrypted, and no key was given to read it
+    EncryptedJournal(PathBuf),
+    /// Error thrown when a file chosen to merge is not a save file, SQLite database, or backup in any known format
+    UnrecognizedJournal(PathBuf),
+    /// Error thrown when changing, removing, or merging over an entry the entry edit policy has made read-only
+    EntryLocked(DateTime<Local>),
+}
+
+impl HappyChartError {
+    /// Returns true if the program state must not be saved while this error is outstanding, as saving could overwrite data that has not been read, or that belongs to another copy of happy chart.
+    /// Other errors are only shown to the user, and do not stop saving.
+    pub fn blocks_saving(&self) -> bool {
+        matches!(
+            self,
+            Self::EncryptedSaveFile(_)
+                | Self::InstanceLocked { .. }
+                | Self::SaveFileChangedExternally(_)
+                | Self::ReadOnlySession
+        )
+    }
 }
 
 impl Display for HappyChartError {
@@ -93,6 +202,141 @@ impl Display for HappyChartError {
                 Self::EncryptKeyTooLong { .. } => {
                     "HappyChartError::EncryptKeyTooLong".to_string()
                 }
+                Self::CorruptSaveFile {
+                    path,
+                    deserialize_error,
+                    ..
+                } => {
+                    format!(
+                        "HappyChartError::CorruptSaveFile {} {}",
+                        deserialize_error,
+                        path.to_str().unwrap_or("UNABLE TO DISPLAY PATH")
+                    )
+                }
+                Self::SqliteStorage(err, path) => {
+                    format!(
+                        "HappyChartError::SqliteStorage {} {}",
+                        err,
+                        path.to_str().unwrap_or("UNABLE TO DISPLAY PATH")
+                    )
+                }
+                Self::StorageDayNotFound(id) => {
+                    format!("HappyChartError::StorageDayNotFound {}", id)
+                }
+                Self::StorageMigrationMismatch { from, to } => {
+                    format!("HappyChartError::StorageMigrationMismatch {} {}", from, to)
+                }
+                Self::IncorrectEncryptionKey => {
+                    "HappyChartError::IncorrectEncryptionKey".to_string()
+                }
+                Self::EncryptionChange { path, error } => {
+                    format!(
+                        "HappyChartError::EncryptionChange {} {}",
+                        error,
+                        path.to_str().unwrap_or("UNABLE TO DISPLAY PATH")
+                    )
+                }
+                Self::BackupEncryptionKey(err) => {
+                    format!("HappyChartError::BackupEncryptionKey {}", err)
+                }
+                Self::KeyDerivation(err) => {
+                    format!("HappyChartError::KeyDerivation {}", err)
+                }
+                Self::UnsupportedEncryptionHeader(version) => {
+                    format!("HappyChartError::UnsupportedEncryptionHeader {}", version)
+                }
+                Self::UnsupportedKeyDerivationSettings(settings) => {
+                    format!(
+                        "HappyChartError::UnsupportedKeyDerivationSettings {:?}",
+                        settings
+                    )
+                }
+                Self::UnsupportedSaveFormatVersion {
+                    found,
+                    supported,
+                    app_version,
+                } => {
+                    format!(
+                        "HappyChartError::UnsupportedSaveFormatVersion {} {} {}",
+                        found, supported, app_version
+                    )
+                }
+                Self::EncryptedBackup(path) => {
+                    format!(
+                        "HappyChartError::EncryptedBackup {}",
+                        path.to_str().unwrap_or("UNABLE TO DISPLAY PATH")
+                    )
+                }
+                Self::BackupMissingSaveFile(path) => {
+                    format!(
+                        "HappyChartError::BackupMissingSaveFile {}",
+                        path.to_str().unwrap_or("UNABLE TO DISPLAY PATH")
+                    )
+                }
+                Self::CorruptBackup { path, problem } => {
+                    format!(
+                        "HappyChartError::CorruptBackup {} {}",
+                        problem,
+                        path.to_str().unwrap_or("UNABLE TO DISPLAY PATH")
+                    )
+                }
+                Self::BackupDestination { name, path, error } => {
+                    format!(
+                        "HappyChartError::BackupDestination {} {} {}",
+                        name,
+                        error,
+                        path.to_str().unwrap_or("UNABLE TO DISPLAY PATH")
+                    )
+                }
+                Self::StaleBackupDestination {
+                    name,
+                    path,
+                    last_backup,
+                    ..
+                } => {
+                    format!(
+                        "HappyChartError::StaleBackupDestination {} {:?} {}",
+                        name,
+                        last_backup,
+                        path.to_str().unwrap_or("UNABLE TO DISPLAY PATH")
+                    )
+                }
+                Self::InvalidProfileName(name) => {
+                    format!("HappyChartError::InvalidProfileName {:?}", name)
+                }
+                Self::InstanceLocked {
+                    path,
+                    owner: Some(owner),
+                } => {
+                    format!(
+                        "HappyChartError::InstanceLocked {} {} {}",
+                        owner.pid,
+                        owner.hostname,
+                        path.to_str().unwrap_or("UNABLE TO DISPLAY PATH")
+                    )
+                }
+                Self::InstanceLocked { path, owner: None } => {
+                    format!(
+                        "HappyChartError::InstanceLocked unreadable {}",
+                        path.to_str().unwrap_or("UNABLE TO DISPLAY PATH")
+                    )
+                }
+                Self::ReadOnlySession => "HappyChartError::ReadOnlySession".to_string(),
+                Self::EncryptionChangeRunning => {
+                    "HappyChartError::EncryptionChangeRunning".to_string()
+                }
+                Self::SaveFileChangedExternally(path) => {
+                    format!("HappyChartError::SaveFileChangedExternally {:?}", path)
+                }
+                Self::EncryptedJournal(path) => {
+                    format!("HappyChartError::EncryptedJournal {:?}", path)
+                }
+                Self::UnrecognizedJournal(path) => {
+                    format!("HappyChartError::UnrecognizedJournal {:?}", path)
+                }
+                Self::EntryLocked(date) => {
+                    format!("HappyChartError::EntryLocked {}", date)
+                }
             }
         )
     }
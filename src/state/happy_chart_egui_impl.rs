@@ -1,6 +1,15 @@
 use crate::common::export::export_stats_to_csv;
+use crate::common::external_change::check_for_external_save_file_change;
+use crate::common::history::handle_history_shortcuts;
+use crate::common::instance_lock::{refresh_instance_lock, release_instance_lock};
+use crate::common::persistence::handle_persistence_events;
+use crate::common::profiles::switch_profile_when_saved;
+use crate::common::sync::sync_when_due;
 use crate::common::update::update_program;
-use crate::common::{first_load, handle_screenshot_event, set_file_logging_state};
+use crate::common::{
+    autosave, close_when_saved, first_load, handle_screenshot_event, save_on_close,
+    set_file_logging_state,
+};
 use crate::state::error_states::HappyChartError;
 use crate::state::happy_chart_state::HappyChartState;
 use crate::state::tutorial_state::TutorialGoal;
@@ -11,30 +20,97 @@ use crate::ui::central_screen::{
     draw_bottom_row_buttons, draw_day_lines, draw_stat_circles, draw_stat_line_segments,
     draw_stat_mouse_over_info, main_screen_button_ui,
 };
+use crate::ui::data_migration_screen::draw_data_migration_screen;
 use crate::ui::encryption::draw_decryption_screen;
 use crate::ui::error_screen::draw_error_screen;
+use crate::ui::external_change_screen::draw_external_change_screen;
+use crate::ui::history_screen::draw_history_screen;
+use crate::ui::instance_lock_screen::draw_instance_locked_screen;
+use crate::ui::merge_screen::draw_merge_screen;
 use crate::ui::mood_selector_menu::draw_mood_selector_screen;
 use crate::ui::note_edit_screen::draw_note_edit_screen;
 use crate::ui::options_menu::{
     draw_backup_settings_options_menu, draw_color_options_menu, draw_encryption_settings_menu,
-    draw_graphing_options_menu, draw_stat_drawing_options_menu, options_update_thread_block,
+    draw_entry_editing_options_menu, draw_graphing_options_menu, draw_profile_options_menu,
+    draw_stat_drawing_options_menu, draw_storage_options_menu, draw_sync_options_menu,
+    options_update_thread_block,
 };
+use crate::ui::persistence_screen::draw_loading_screen;
+use crate::ui::profile_screen::draw_profile_switcher_screen;
+use crate::ui::restore_screen::draw_restore_screen;
 use crate::ui::statistics_screen::draw_previous_duration_stats_screen;
+use crate::ui::trash_screen::draw_trash_screen;
 use crate::ui::tutorial_screen::draw_tutorial_screen;
 use crate::ui::user_prompt_screens::draw_user_prompts;
+use crate::LOCK_HEARTBEAT_SECONDS;
 use eframe::Frame;
 use egui::Context;
 use rand::Rng;
+use std::time::{Duration, SystemTime};
 use tracing::error;
 
 /// Update loop for egui
 impl eframe::App for HappyChartState {
     #[tracing::instrument(skip(self, ctx, _frame))]
     fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
+        // nothing is drawn behind the profile chooser, as no profile has been loaded yet
+        if self.ui_states.showing_profile_switcher {
+            egui::CentralPanel::default().show(ctx, |_| {});
+            egui::Window::new("Choose profile").show(ctx, |ui| {
+                draw_profile_switcher_screen(ui, self, ctx);
+            });
+            return;
+        }
+
         if self.first_load {
             first_load(self, ctx, true);
         }
 
+        handle_persistence_events(ctx, self);
+
+        // the save file is read on the persistence worker, nothing is drawn until it has been read
+        if self.loading {
+            egui::CentralPanel::default().show(ctx, |_| {});
+            egui::Window::new("Loading").show(ctx, |ui| {
+                draw_loading_screen(ui, self);
+            });
+            return;
+        }
+
+        check_for_external_save_file_change(ctx, self);
+        handle_history_shortcuts(ctx, self);
+        self.detect_program_options_change();
+        autosave(ctx, self);
+        sync_when_due(ctx, self);
+        if ctx.input(|i| i.viewport().close_requested()) {
+            save_on_close(ctx, self);
+        }
+        close_when_saved(ctx, self);
+        if switch_profile_when_saved(ctx, self) {
+            return;
+        }
+
+        // the lock heartbeat keeps other copies of happy chart from treating the lock as left behind by a crash
+        if self.instance_lock_held {
+            let heartbeat_interval = Duration::from_secs(LOCK_HEARTBEAT_SECONDS);
+            if self
+                .last_lock_heartbeat
+                .elapsed()
+                .is_ok_and(|elapsed| elapsed >= heartbeat_interval)
+            {
+                self.last_lock_heartbeat = SystemTime::now();
+                if let Err(err) = refresh_instance_lock(&self.data_paths) {
+                    error!("Unable to refresh instance lock: {}", err);
+                    if matches!(err, HappyChartError::InstanceLocked { .. }) {
+                        self.instance_lock_held = false;
+                        self.read_only = true;
+                    }
+                    self.error_states.push(err);
+                }
+            }
+            ctx.request_repaint_after(heartbeat_interval);
+        }
+
         if self.open_animation_animating {
             ctx.request_repaint();
             if self.get_day_index_animation() == self.days.len() {
@@ -102,7 +178,23 @@ impl eframe::App for HappyChartState {
 
                 draw_backup_settings_options_menu(ui, self, ctx);
 
-                draw_encryption_settings_menu(ui, self);
+                draw_encryption_settings_menu(ui, self, ctx);
+
+                draw_storage_options_menu(ui, self, ctx);
+
+                draw_profile_options_menu(ui, self, ctx);
+
+                draw_sync_options_menu(ui, self, ctx);
+
+                draw_entry_editing_options_menu(ui, self);
+
+                if ui
+                    .button("Merge another journal")
+                    .on_hover_text("Combine the entries of another save file or backup with this journal, for example a copy from another computer.")
+                    .clicked()
+                {
+                    self.ui_states.showing_merge_screen = true;
+                }
 
                 if ui.button("Export stats to CSV").clicked() {
                     if let Some(path) = rfd::FileDialog::new()
@@ -160,6 +252,7 @@ impl eframe::App for HappyChartState {
                         .collect::<Vec<ImprovedDayStat>>();
 
                     self.days = day_stats;
+                    self.mark_changed();
                     self.program_options.x_offset = 20.0;
                     self.program_options.graph_x_scale = ((100.0 / day_count as f32) / 3.0) * 0.9;
                 }
@@ -168,7 +261,10 @@ impl eframe::App for HappyChartState {
                     .checkbox(&mut self.program_options.log_to_file, "Log to file")
                     .changed()
                 {
-                    set_file_logging_state(self.program_options.log_to_file);
+                    set_file_logging_state(
+                        self.program_options.log_to_file,
+                        &self.data_paths.log_file(),
+                    );
                 }
 
                 if ui.button("Close Options Menu").clicked() {
@@ -177,6 +273,30 @@ impl eframe::App for HappyChartState {
             });
         }
 
+        if self.ui_states.showing_restore_screen {
+            egui::Window::new("Restore backup").show(ctx, |ui| {
+                draw_restore_screen(ui, self, ctx);
+            });
+        }
+
+        if self.ui_states.showing_history_screen {
+            egui::Window::new("Edit history").show(ctx, |ui| {
+                draw_history_screen(ui, self);
+            });
+        }
+
+        if self.ui_states.showing_trash_screen {
+            egui::Window::new("Trash").show(ctx, |ui| {
+                draw_trash_screen(ui, self);
+            });
+        }
+
+        if self.ui_states.showing_merge_screen {
+            egui::Window::new("Merge journal").show(ctx, |ui| {
+                draw_merge_screen(ui, self, ctx);
+            });
+        }
+
         if self.ui_states.showing_about_page {
             egui::Window::new("About").show(ctx, |ui| {
                 draw_about_page(ui, self);
@@ -195,17 +315,28 @@ impl eframe::App for HappyChartState {
             });
         }
 
+        if self.external_change.is_some() {
+            egui::Window::new("Save file changed on disk").show(ctx, |ui| {
+                draw_external_change_screen(ui, self);
+            });
+        }
+
         if !self.error_states.is_empty() {
             if self
+                .error_states
+                .iter()
+                .any(|err| matches!(err, HappyChartError::InstanceLocked { .. }))
+            {
+                egui::Window::new("Profile already open").show(ctx, |ui| {
+                    draw_instance_locked_screen(ui, self, ctx);
+                });
+            } else if self
                 .error_states
                 .iter()
                 .any(|err| matches!(err, HappyChartError::EncryptedSaveFile(_)))
             {
                 egui::Window::new("Unlock your save file").show(ctx, |ui| {
-                    if let Err(err) = draw_decryption_screen(ui, self, ctx) {
-                        error!("Error decrypting save file: {:?}", err);
-                        self.error_states.push(err);
-                    }
+                    draw_decryption_screen(ui, self, ctx);
                     if self
                         .error_states
                         .iter()
@@ -239,6 +370,20 @@ impl eframe::App for HappyChartState {
             });
         }
 
+        if !self.data_migration_report.is_empty() {
+            egui::Window::new("Save files moved").show(ctx, |ui| {
+                draw_data_migration_screen(ui, self);
+            });
+        }
+
         draw_user_prompts(ctx, self);
     }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        // closing is normally held off until every write has finished, this only guards against the process exiting partway through one
+        self.persistence.wait_until_idle();
+        if self.instance_lock_held {
+            release_instance_lock(&self.data_paths);
+        }
+    }
 }
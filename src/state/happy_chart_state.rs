@@ -1,31 +1,54 @@
 use crate::common::auto_update_status::AutoUpdateStatus;
+use crate::common::backup_destination::BackupDestination;
+use crate::common::backup_retention::{get_backups_to_prune, BackupFile};
+use crate::common::data_paths::{DataPaths, MigratedFile};
+use crate::common::encryption::{encryption_key_checks, SaveCipher};
+use crate::common::external_change::{ExternalChange, SaveFileWatch};
+use crate::common::history::EditHistory;
 use crate::common::math::{calculate_centered_graph_scaling, improved_calculate_x};
+use crate::common::merge::JournalMerge;
 use crate::common::mood_tag::MoodTag;
+use crate::common::persistence::PersistenceWorker;
+use crate::common::profiles::{list_profiles, read_profile_settings, ProfileSettings};
+use crate::common::restore::{BackupArchive, PendingRestore, RestorePreview};
+use crate::common::text_diff::{diff_lines, DiffLine};
 use crate::day_stats::improved_daystat::ImprovedDayStat;
+use crate::day_stats::trashed_day::TrashedDay;
+use crate::options::entry_edit_policy::EntryEditState;
 use crate::options::program_options::ProgramOptions;
 use crate::state::activities::ActivityUIState;
 use crate::state::error_states::HappyChartError;
 use crate::state::state_stats::StateStats;
 use crate::state::tutorial_state::TutorialGoal;
-use crate::{BACKUP_FILENAME_PREFIX, BACKUP_FILE_EXTENSION, MANUAL_BACKUP_SUFFIX};
 use chrono::{DateTime, Local};
 use egui::Context;
 use self_update::update::Release;
 use self_update::Status;
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
 use std::fs;
-use std::fs::DirEntry;
+use std::path::PathBuf;
+use std::rc::Rc;
 use std::thread::JoinHandle;
 use std::time::{Duration, SystemTime};
-use tracing::{debug, error, info};
+use tracing::{error, info, warn};
+use uuid::Uuid;
 
 pub struct HappyChartState {
     pub rating: f64,
     pub days: Vec<ImprovedDayStat>,
+    /// Days deleted from the journal, oldest deletion first, kept in the save file until they are restored or purged
+    pub trash: Vec<TrashedDay>,
     pub first_load: bool,
     pub note_input: String,
-    /// The length of days recorded since the last session. Used to determine if the user has made changes to the day list
-    pub starting_length: usize,
+    /// True if the day list or program options changed since they were last saved, see `mark_changed`
+    unsaved_changes: Cell<bool>,
+    /// When the day list or program options last changed, autosave waits until this is old enough
+    pub last_change_time: SystemTime,
+    /// When the program state was last written this session
+    last_save_date: Cell<Option<DateTime<Local>>>,
+    /// The program options as of the last save, compared against every frame as options are changed from many places
+    saved_program_options: RefCell<ProgramOptions>,
 
     pub program_options: ProgramOptions,
     /// The status on updating the program, see the enum for more information
@@ -57,6 +80,11 @@ pub struct HappyChartState {
 
     pub encryption_key: String,
     pub encryption_key_second_check: String,
+    /// Key used to encrypt backups when a separate backup key is enabled, entered each session
+    pub backup_encryption_key: String,
+    pub backup_encryption_key_second_check: String,
+    /// Cipher reused between saves, so the encryption key is only derived again when the password or its settings change
+    save_cipher: RefCell<Option<SaveCipher>>,
 
     pub program_open_time: SystemTime,
 
@@ -65,10 +93,53 @@ pub struct HappyChartState {
     /// Represents the height which is where it is safe to draw things relating to the graph
     pub central_ui_safezone_start: f32,
 
-    /// Index of the desired note to edit
-    pub note_edit_selected: Option<usize>,
+    /// Id of the desired note to edit
+    pub note_edit_selected: Option<Uuid>,
 
     pub ui_states: UIStates,
+
+    /// The folders all save files are read from and written to
+    pub data_paths: DataPaths,
+
+    /// Files moved out of the working directory on launch, shown to the user until dismissed
+    pub data_migration_report: Vec<MigratedFile>,
+
+    /// Settings shared between every profile
+    pub profile_settings: ProfileSettings,
+
+    /// True while this copy of happy chart holds the lock on the profile, see `common::instance_lock`
+    pub instance_lock_held: bool,
+    /// When the heartbeat in the lock file was last rewritten
+    pub last_lock_heartbeat: SystemTime,
+    /// True if another copy of happy chart has the profile open, nothing is saved while this is set
+    pub read_only: bool,
+
+    /// Reads and writes save files and backups off the UI thread
+    pub persistence: PersistenceWorker,
+    /// True while the persistence worker reads the save file on launch, nothing else is drawn until it has been read
+    pub loading: bool,
+    /// True once the user has asked to quit, the program closes when every pending write has finished
+    pub quit_requested: bool,
+    /// The profile to switch to once every pending write has finished, the inner None being the default profile
+    pub profile_switch_requested: Option<Option<String>>,
+    /// True while the persistence worker re-encrypts the save files with a new password, nothing is saved until it has finished
+    pub encryption_change_running: bool,
+
+    /// The save file as last read or written, used to notice another program such as a file sync tool changing it
+    pub save_file_watch: SaveFileWatch,
+    /// When the save file was last checked for changes made by another program
+    pub last_save_file_check: SystemTime,
+    /// The day list on disk after another program changed the save file, shown until the user chooses which version to keep
+    pub external_change: Option<ExternalChange>,
+
+    /// When the journal was last synced with the sync folder
+    pub last_sync_time: SystemTime,
+
+    /// Edits made this session that can be undone and redone
+    pub edit_history: EditHistory,
+
+    /// Entries the user chose to change this session even though the entry edit policy has made them read-only
+    pub entry_lock_overrides: HashSet<Uuid>,
 }
 
 #[derive(Debug, Clone)]
@@ -79,6 +150,35 @@ pub struct UIStates {
     pub showing_statistics_screen: bool,
     pub showing_graph_controls: bool,
     pub activity_ui_state: ActivityUIState,
+    pub password_change: PasswordChangeState,
+    pub showing_restore_screen: bool,
+    pub restore_ui_state: RestoreUIState,
+    /// Result of the last time every backup was verified, shown in the backup options menu
+    pub backup_verification_status: Option<String>,
+    /// Backups the retention policy would remove, shown in the backup options menu
+    pub backup_prune_preview: Option<Vec<BackupFile>>,
+    /// Shown on launch instead of loading the last used profile, when the user asked to choose a profile at startup
+    pub showing_profile_switcher: bool,
+    /// Name typed into the new profile field of the profile options menu
+    pub new_profile_name: String,
+    pub showing_merge_screen: bool,
+    pub merge_ui_state: MergeUIState,
+    /// Result of the last sync, shown in the sync options menu
+    pub sync_status: Option<String>,
+    pub showing_history_screen: bool,
+    pub showing_trash_screen: bool,
+    /// Date and time the next day is added with, None to add it at the current time
+    pub add_day_time: Option<DateTime<Local>>,
+    /// Note text being edited in the note editor, not yet saved to its day
+    pub note_draft: Option<NoteDraft>,
+    /// Show the changes made to the note in the note editor before saving them
+    pub showing_note_diff: bool,
+    /// The diff last shown in the note editor
+    pub note_diff: Option<NoteDiff>,
+    /// True while the persistence worker unlocks the save file with the key entered in the decryption screen
+    pub unlocking: bool,
+    /// True while the persistence worker migrates the day list to another kind of storage
+    pub migrating_storage: bool,
 }
 
 #[allow(clippy::derivable_impls)]
@@ -91,10 +191,122 @@ impl Default for UIStates {
             showing_statistics_screen: false,
             showing_graph_controls: false,
             activity_ui_state: ActivityUIState::default(),
+            password_change: PasswordChangeState::default(),
+            showing_restore_screen: false,
+            restore_ui_state: RestoreUIState::default(),
+            backup_verification_status: None,
+            backup_prune_preview: None,
+            showing_profile_switcher: false,
+            new_profile_name: String::new(),
+            showing_merge_screen: false,
+            merge_ui_state: MergeUIState::default(),
+            sync_status: None,
+            showing_history_screen: false,
+            showing_trash_screen: false,
+            add_day_time: None,
+            note_draft: None,
+            showing_note_diff: false,
+            note_diff: None,
+            unlocking: false,
+            migrating_storage: false,
         }
     }
 }
 
+/// Text entered into the change encryption password menu
+#[derive(Debug, Clone, Default)]
+pub struct PasswordChangeState {
+    pub current_password: String,
+    pub new_password: String,
+    pub new_password_check: String,
+    /// Result of the last password change, shown below the menu
+    pub status: Option<String>,
+}
+
+/// State of the restore from backup screen
+#[derive(Debug, Clone, Default)]
+pub struct RestoreUIState {
+    /// Key used to read encrypted backups, the backup encryption key in use is tried if this is empty
+    pub encryption_key: String,
+    /// Backups read when the screen was opened, None if the backup folder needs to be read again
+    pub archives: Option<Rc<Vec<BackupArchive>>>,
+    /// True while the persistence worker reads the backup archives
+    pub listing: bool,
+    pub selected_archive: Option<usize>,
+    /// Differences between the current journal and the selected backup
+    pub preview: Option<Rc<RestorePreview>>,
+    /// Restore the program options from the backup as well as the day list
+    pub restore_program_options: bool,
+    /// The restore waiting for the backup of the current state to be taken, None if no restore is running
+    pub pending_restore: Option<Rc<PendingRestore>>,
+    /// Result of the last restore, shown at the bottom of the screen
+    pub status: Option<String>,
+}
+
+/// A note being edited in the note editor
+#[derive(Debug, Clone)]
+pub struct NoteDraft {
+    /// Id of the day the note belongs to
+    pub day: Uuid,
+    /// The note of the day when editing started, or when the draft was last saved
+    pub original: String,
+    pub text: String,
+}
+
+impl NoteDraft {
+    pub fn new(day: &ImprovedDayStat) -> Self {
+        Self {
+            day: *day.get_id(),
+            original: day.get_note().to_string(),
+            text: day.get_note().to_string(),
+        }
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.text != self.original
+    }
+}
+
+/// The diff between the saved note and the draft shown in the note editor, kept until either of them changes so it is not recomputed every frame
+#[derive(Debug, Clone)]
+pub struct NoteDiff {
+    saved: String,
+    draft: String,
+    pub lines: Vec<DiffLine<String>>,
+}
+
+impl NoteDiff {
+    pub fn new(saved: &str, draft: &str) -> Self {
+        Self {
+            saved: saved.to_string(),
+            draft: draft.to_string(),
+            lines: diff_lines(saved, draft)
+                .into_iter()
+                .map(DiffLine::to_owned_line)
+                .collect(),
+        }
+    }
+
+    pub fn is_for(&self, saved: &str, draft: &str) -> bool {
+        self.saved == saved && self.draft == draft
+    }
+}
+
+/// State of the merge journal screen
+#[derive(Debug, Clone, Default)]
+pub struct MergeUIState {
+    /// Key used to read an encrypted journal, the save encryption key in use is tried if this is empty
+    pub encryption_key: String,
+    /// Where the journal being merged was read from, shown at the top of the screen
+    pub source: Option<String>,
+    /// True while the persistence worker reads the journal chosen to merge
+    pub reading: bool,
+    /// The merge waiting for its conflicts to be resolved
+    pub merge: Option<JournalMerge>,
+    /// Result of the last merge, shown at the bottom of the screen
+    pub status: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 #[deprecated]
 #[allow(dead_code)]
@@ -151,13 +363,33 @@ impl HappyChartState {
     const COMMON_GRAPH_STARTING_HEIGHT: f32 = 155.0;
 
     #[tracing::instrument(skip(_cc))]
-    pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
+    pub fn new(
+        _cc: &eframe::CreationContext<'_>,
+        data_paths: DataPaths,
+        data_migration_report: Vec<MigratedFile>,
+    ) -> Self {
+        let mut app = Self::from_data_paths(data_paths, data_migration_report);
+        app.ui_states.showing_profile_switcher = app.profile_settings.choose_profile_at_startup
+            && !list_profiles(&app.data_paths).is_empty();
+        app
+    }
+
+    /// Creates an unloaded program state for the profile the data paths point to, the profile is loaded on the first frame
+    #[tracing::instrument]
+    pub fn from_data_paths(
+        data_paths: DataPaths,
+        data_migration_report: Vec<MigratedFile>,
+    ) -> Self {
         Self {
             rating: 0.0,
             days: vec![],
+            trash: vec![],
             first_load: true,
             note_input: String::new(),
-            starting_length: 0,
+            unsaved_changes: Cell::new(false),
+            last_change_time: SystemTime::now(),
+            last_save_date: Cell::new(None),
+            saved_program_options: RefCell::new(ProgramOptions::default()),
             program_options: ProgramOptions::default(),
             update_status: AutoUpdateStatus::NotChecked,
             update_thread: Cell::new(None),
@@ -173,12 +405,261 @@ impl HappyChartState {
             tutorial_state: TutorialGoal::default(),
             encryption_key: String::new(),
             encryption_key_second_check: String::new(),
+            backup_encryption_key: String::new(),
+            backup_encryption_key_second_check: String::new(),
+            save_cipher: RefCell::new(None),
             program_open_time: SystemTime::now(),
             open_animation_animating: true,
             central_ui_safezone_start: 0.0,
             note_edit_selected: None,
             ui_states: UIStates::default(),
+            profile_settings: read_profile_settings(&data_paths),
+            instance_lock_held: false,
+            last_lock_heartbeat: SystemTime::now(),
+            read_only: false,
+            persistence: PersistenceWorker::new(),
+            loading: false,
+            quit_requested: false,
+            profile_switch_requested: None,
+            encryption_change_running: false,
+            save_file_watch: SaveFileWatch::default(),
+            last_save_file_check: SystemTime::now(),
+            external_change: None,
+            // synced as soon as the journal is loaded
+            last_sync_time: SystemTime::UNIX_EPOCH,
+            edit_history: EditHistory::default(),
+            entry_lock_overrides: HashSet::new(),
+            data_paths,
+            data_migration_report,
+        }
+    }
+
+    /// Marks the program state as changed since it was last saved, every change to the day list must call this
+    pub fn mark_changed(&mut self) {
+        self.unsaved_changes.set(true);
+        self.last_change_time = SystemTime::now();
+    }
+
+    /// Marks changes made to the program options since they were last saved, as there are too many options to mark each change where it is made
+    pub fn detect_program_options_change(&mut self) {
+        if !self.unsaved_changes.get()
+            && *self.saved_program_options.borrow() != self.program_options
+        {
+            self.mark_changed();
+        }
+    }
+
+    pub fn has_unsaved_changes(&self) -> bool {
+        self.unsaved_changes.get()
+    }
+
+    pub fn get_last_save_date(&self) -> Option<DateTime<Local>> {
+        self.last_save_date.get()
+    }
+
+    /// Marks the program state as matching what was just read from disk, or what was just handed to the persistence worker to write
+    pub fn mark_unchanged(&self) {
+        self.unsaved_changes.set(false);
+        self.saved_program_options
+            .replace(self.program_options.clone());
+    }
+
+    /// Records that a save finished writing
+    pub fn record_save(&self) {
+        self.last_save_date.set(Some(Local::now()));
+    }
+
+    /// Replaces the whole day list, such as with a day list read from disk or merged from elsewhere, and recalculates the stats
+    pub fn replace_days(&mut self, mut days: Vec<ImprovedDayStat>) {
+        days.sort_by_key(|day| *day.get_date());
+        self.days = days;
+        if self
+            .note_edit_selected
+            .is_some_and(|id| self.get_day(&id).is_none())
+        {
+            self.note_edit_selected = None;
         }
+        self.stats
+            .calc_all_stats(&self.days, self.program_options.streak_leniency);
+    }
+
+    /// Returns the day with the given id, if it is in the day list
+    pub fn get_day(&self, id: &Uuid) -> Option<&ImprovedDayStat> {
+        self.days.iter().find(|day| day.get_id() == id)
+    }
+
+    pub fn get_day_mut(&mut self, id: &Uuid) -> Option<&mut ImprovedDayStat> {
+        self.days.iter_mut().find(|day| day.get_id() == id)
+    }
+
+    /// Returns `date`, moved a microsecond at a time until no day other than the one with `id` is at that time, in the journal or in the trash.
+    /// Days are listed and charted in order of their time, so a picked date and time is kept apart from every other day to keep that order unambiguous, including days that may be restored from the trash later.
+    pub fn get_unique_entry_time(
+        &self,
+        date: DateTime<Local>,
+        id: Option<&Uuid>,
+    ) -> DateTime<Local> {
+        let mut date = date;
+        while self
+            .days
+            .iter()
+            .chain(self.trash.iter().map(|trashed| &trashed.day))
+            .any(|day| {
+                Some(day.get_id()) != id
+                    && day.get_date().timestamp_micros() == date.timestamp_micros()
+            })
+        {
+            date += Duration::from_micros(1);
+        }
+        date
+    }
+
+    /// Returns true if the entry edit policy has made the day read-only, and the user has not overridden it this session
+    pub fn is_entry_locked(&self, day: &ImprovedDayStat) -> bool {
+        self.program_options.get_entry_edit_state(day) == EntryEditState::Locked
+            && !self.entry_lock_overrides.contains(day.get_id())
+    }
+
+    /// Allows a read-only day to be changed for the rest of the session.
+    /// As the override goes against the entry edit policy, it is logged and recorded in the revision history of the day, which is saved with it.
+    pub fn override_entry_lock(&mut self, day: &ImprovedDayStat, reason: &str) {
+        warn!(
+            "Entry edit policy overridden for the entry from {} ({}): {}",
+            day.get_date(),
+            day.get_id(),
+            reason
+        );
+        self.entry_lock_overrides.insert(*day.get_id());
+        if let Some(stored_day) = self
+            .days
+            .iter_mut()
+            .find(|stored_day| stored_day.get_id() == day.get_id())
+        {
+            stored_day.record_lock_override(reason);
+            self.mark_changed();
+        }
+    }
+
+    /// Puts every day the entry edit policy has made read-only back into a day list that is about to replace the current one, as it was before.
+    /// Returns the number of read-only days that would otherwise have been changed or removed.
+    pub fn keep_locked_days(&self, days: &mut Vec<ImprovedDayStat>) -> usize {
+        let serialize = |day: &ImprovedDayStat| serde_json::to_string(day).unwrap_or_default();
+        let mut kept_count = 0;
+        for locked_day in self.days.iter().filter(|day| self.is_entry_locked(day)) {
+            match days
+                .iter_mut()
+                .find(|day| day.get_id() == locked_day.get_id())
+            {
+                Some(day) if serialize(day) == serialize(locked_day) => continue,
+                Some(day) => *day = locked_day.clone(),
+                None => days.push(locked_day.clone()),
+            }
+            kept_count += 1;
+        }
+        if kept_count > 0 {
+            warn!(
+                "Kept {} read-only entries that would have been changed or removed",
+                kept_count
+            );
+        }
+        kept_count
+    }
+
+    /// Removes the day with the given id, returning it if it was in the day list
+    pub fn remove_day(&mut self, id: &Uuid) -> Option<ImprovedDayStat> {
+        let position = self.days.iter().position(|day| day.get_id() == id)?;
+        if self.note_edit_selected.as_ref() == Some(id) {
+            self.note_edit_selected = None;
+        }
+        Some(self.days.remove(position))
+    }
+
+    /// Removes the day with the given id from the trash, returning it if it was in the trash
+    pub fn take_from_trash(&mut self, id: &Uuid) -> Option<TrashedDay> {
+        let position = self
+            .trash
+            .iter()
+            .position(|trashed| trashed.day.get_id() == id)?;
+        Some(self.trash.remove(position))
+    }
+
+    /// Permanently deletes the days that have been in the trash for longer than the trash retention period
+    pub fn purge_expired_trash(&mut self) {
+        let retention_days = self.program_options.trash_retention_days;
+        let now = Local::now();
+        let len = self.trash.len();
+        self.trash
+            .retain(|trashed| !trashed.is_expired(retention_days, now));
+        if self.trash.len() != len {
+            info!(
+                "Purged {} days from the trash older than {} days",
+                len - self.trash.len(),
+                retention_days
+            );
+            self.mark_changed();
+        }
+    }
+
+    /// Returns the folder of the primary backup destination
+    pub fn get_backup_dir(&self) -> PathBuf {
+        self.get_destination_dir(&self.program_options.primary_backup_destination)
+    }
+
+    /// Returns the folder backups are stored in at a destination, relative backup paths are relative to the data folder
+    pub fn get_destination_dir(&self, destination: &BackupDestination) -> PathBuf {
+        self.data_paths.resolve(&destination.path)
+    }
+
+    /// Returns the folder of every backup destination, a folder used by more than one destination is only returned once
+    pub fn get_backup_dirs(&self) -> Vec<PathBuf> {
+        let mut backup_dirs: Vec<PathBuf> = vec![];
+        for backup_dir in self
+            .program_options
+            .get_backup_destinations()
+            .map(|destination| self.get_destination_dir(destination))
+        {
+            if !backup_dirs.contains(&backup_dir) {
+                backup_dirs.push(backup_dir);
+            }
+        }
+        backup_dirs
+    }
+
+    /// Returns the cipher used to encrypt the save file, or None if save file encryption is disabled.
+    /// Each call draws a new salt, so no two writes share one, while keys derived by earlier calls stay cached.
+    pub fn get_save_cipher(&self) -> Option<SaveCipher> {
+        if !self.program_options.encrypt_save_file {
+            return None;
+        }
+        let settings = self.program_options.key_derivation_settings;
+        let mut save_cipher = self.save_cipher.borrow_mut();
+        match save_cipher.as_ref() {
+            // every write is given its own salt, the cached cipher only keeps the derived keys around
+            Some(cipher) if cipher.is_for(&self.encryption_key, settings) => {
+                Some(cipher.with_new_salt())
+            }
+            _ => {
+                let cipher = SaveCipher::new(&self.encryption_key, settings);
+                *save_cipher = Some(cipher.clone());
+                Some(cipher)
+            }
+        }
+    }
+
+    /// Returns the cipher used to encrypt backups, or None if save file encryption is disabled
+    pub fn get_backup_cipher(&self) -> Result<Option<SaveCipher>, HappyChartError> {
+        if !self.program_options.encrypt_save_file || !self.program_options.separate_backup_key {
+            return Ok(self.get_save_cipher());
+        }
+        encryption_key_checks(
+            &self.backup_encryption_key,
+            &self.backup_encryption_key_second_check,
+        )
+        .map_err(|err| HappyChartError::BackupEncryptionKey(Box::new(err)))?;
+        Ok(Some(SaveCipher::new(
+            &self.backup_encryption_key,
+            self.program_options.key_derivation_settings,
+        )))
     }
 
     /// Returns a fraction relating to how far through the program opening animation we are, ranged from 0.0..=1.0
@@ -224,30 +705,29 @@ impl HappyChartState {
         (idx as usize).clamp(0, self.days.len())
     }
 
+    /// Returns the automatic backups the retention policy of each backup destination would remove
+    pub fn get_backups_to_prune(&self, now: DateTime<Local>) -> Vec<BackupFile> {
+        self.program_options
+            .get_backup_destinations()
+            .flat_map(|destination| {
+                get_backups_to_prune(&self.get_destination_dir(destination), destination, now)
+            })
+            .collect()
+    }
+
+    /// Removes the automatic backups the retention policy of each backup destination prunes
     #[tracing::instrument(skip_all)]
     pub fn remove_old_backup_files(&self) {
         info!("Removing old backup files");
-        let list = self.get_backup_file_list();
-
-        // only remove old backup files if the number of old backups exceeds the amount allowed
         let mut removed_count = 0;
-        let number_to_remove = list.len() as i32 - self.program_options.number_of_kept_backups;
-        if list.len() > self.program_options.number_of_kept_backups as usize
-            && self.program_options.number_of_kept_backups >= 0
-        {
-            for entry in list {
-                if removed_count >= number_to_remove {
-                    break;
+        for backup in self.get_backups_to_prune(Local::now()) {
+            match fs::remove_file(&backup.path) {
+                Ok(()) => {
+                    info!("Removing {:?}", backup.path);
+                    removed_count += 1;
                 }
-                let res = fs::remove_file(entry.path());
-                removed_count += 1;
-                match res {
-                    Ok(_) => {
-                        info!("Removing {:?}", entry);
-                    }
-                    Err(e) => {
-                        error!("Error removing {:?} {:?}", entry, e);
-                    }
+                Err(e) => {
+                    error!("Error removing {:?} {:?}", backup.path, e);
                 }
             }
         }
@@ -277,54 +757,6 @@ impl HappyChartState {
             .collect()
     }
 
-    #[tracing::instrument(skip_all)]
-    pub fn get_backup_file_list(&self) -> Vec<DirEntry> {
-        if self.program_options.backup_age_keep_days < 0 {
-            return vec![];
-        }
-
-        debug!(
-            "Backup save path: {:?}",
-            self.program_options.backup_save_path
-        );
-        match fs::read_dir(&self.program_options.backup_save_path) {
-            Ok(dir_list) => dir_list
-                .filter_map(|item| {
-                    debug!("{:?}", item);
-                    item.ok()
-                })
-                .filter(|entry| {
-                    let mut keep = false;
-                    if let Some(f_name) = entry.file_name().to_str() {
-                        if !f_name.contains(MANUAL_BACKUP_SUFFIX)
-                            && f_name.contains(BACKUP_FILENAME_PREFIX)
-                            && f_name.contains(BACKUP_FILE_EXTENSION)
-                        {
-                            if let Ok(meta_data) = entry.metadata() {
-                                if let Ok(created_time) = meta_data.created() {
-                                    let dt: DateTime<Local> = created_time.into();
-                                    let days = Local::now().signed_duration_since(dt).num_days();
-                                    let hours = Local::now().signed_duration_since(dt).num_hours();
-                                    debug!(
-                                        "Backup: {} age: {} days hours: {}",
-                                        f_name, days, hours
-                                    );
-
-                                    keep =
-                                        days > i64::from(self.program_options.backup_age_keep_days);
-                                }
-                            }
-                        }
-                    }
-                    keep
-                })
-                .collect::<Vec<DirEntry>>(),
-            Err(_) => {
-                vec![]
-            }
-        }
-    }
-
     /// Returns the Y line value relative to all the programs settings
     pub fn get_day_line_y_value(&self) -> f32 {
         if self.program_options.move_day_lines_with_ui {
@@ -1,11 +1,12 @@
 use crate::common::math::get_average_for_day_of_week;
 use crate::day_stats::improved_daystat::ImprovedDayStat;
 use crate::state::activities::Activity;
-use chrono::Weekday;
+use chrono::{DateTime, Local, Weekday};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use tracing::info;
+use uuid::Uuid;
 
 #[derive(Debug)]
 pub struct StateStats {
@@ -134,8 +135,10 @@ impl ActivityStats {
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Days {
     pub longest_streak: u32,
-    pub streak_start_index: usize,
-    pub streak_end_index: usize,
+    /// Id of the first day of the longest streak
+    pub streak_start_id: Option<Uuid>,
+    /// Id of the last day of the longest streak
+    pub streak_end_id: Option<Uuid>,
 }
 
 #[derive(Debug)]
@@ -167,8 +170,8 @@ impl StateStats {
             avg_weekdays: WeekdayAverages::new(),
             longest_streak: Days {
                 longest_streak: 0,
-                streak_start_index: 0,
-                streak_end_index: 0,
+                streak_start_id: None,
+                streak_end_id: None,
             },
             activity_stats: ActivityStats::new(),
         }
@@ -239,8 +242,11 @@ impl StateStats {
 
         self.longest_streak = Days {
             longest_streak: current_max,
-            streak_start_index,
-            streak_end_index,
+            streak_start_id: list.get(streak_start_index).map(|day| *day.get_id()),
+            streak_end_id: streak_end_index
+                .checked_sub(1)
+                .and_then(|index| list.get(index))
+                .map(|day| *day.get_id()),
         };
     }
 }
@@ -272,12 +278,30 @@ impl WeekdayAverages {
     }
 }
 
+impl Days {
+    /// Returns the dates of the first and last day of the longest streak, if both are in the day list
+    pub fn get_streak_dates(
+        &self,
+        days: &[ImprovedDayStat],
+    ) -> Option<(DateTime<Local>, DateTime<Local>)> {
+        let find_date = |id: Option<Uuid>| {
+            days.iter()
+                .find(|day| Some(*day.get_id()) == id)
+                .map(|day| *day.get_date())
+        };
+        Some((
+            find_date(self.streak_start_id)?,
+            find_date(self.streak_end_id)?,
+        ))
+    }
+}
+
 impl Display for Days {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{} {} {}",
-            self.longest_streak, self.streak_start_index, self.streak_end_index
+            "{} {:?} {:?}",
+            self.longest_streak, self.streak_start_id, self.streak_end_id
         )
     }
 }
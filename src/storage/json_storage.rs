@@ -0,0 +1,122 @@
+use crate::common::data_paths::DataPaths;
+use crate::common::encryption::{decrypt_save_file, is_encrypted_container, SaveCipher};
+use crate::common::save::{read_save_file, write_save_file};
+use crate::common::save_format::read_save_trash;
+use crate::day_stats::improved_daystat::ImprovedDayStat;
+use crate::day_stats::trashed_day::TrashedDay;
+use crate::state::error_states::HappyChartError;
+use crate::storage::{StorageBackend, StorageBackendKind};
+use chrono::{DateTime, Local};
+use std::fs;
+use std::io::ErrorKind;
+use uuid::Uuid;
+
+/// Storage that keeps the whole day list and the trash in a single json file, every change rewrites the file
+pub struct JsonFileStorage {
+    data_paths: DataPaths,
+    cipher: Option<SaveCipher>,
+}
+
+impl JsonFileStorage {
+    pub fn new(data_paths: DataPaths, cipher: Option<SaveCipher>) -> Self {
+        Self { data_paths, cipher }
+    }
+}
+
+impl StorageBackend for JsonFileStorage {
+    fn kind(&self) -> StorageBackendKind {
+        StorageBackendKind::JsonFile
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn load(&mut self) -> Result<Vec<ImprovedDayStat>, HappyChartError> {
+        let mut days = match (read_save_file(&self.data_paths), &self.cipher) {
+            (Err(HappyChartError::EncryptedSaveFile(encrypted_data)), Some(cipher)) => {
+                decrypt_save_file(cipher, &encrypted_data)?
+            }
+            (res, _) => res?,
+        };
+        days.sort_by_key(|day| *day.get_date());
+        Ok(days)
+    }
+
+    #[tracing::instrument(skip_all)]
+    fn append(&mut self, day: &ImprovedDayStat) -> Result<(), HappyChartError> {
+        let mut days = self.load()?;
+        days.push(day.clone());
+        let trash = self.load_trash()?;
+        self.store_all(&days, &trash)
+    }
+
+    #[tracing::instrument(skip_all)]
+    fn update(&mut self, day: &ImprovedDayStat) -> Result<(), HappyChartError> {
+        let mut days = self.load()?;
+        let stored_day = days
+            .iter_mut()
+            .find(|stored_day| stored_day.get_id() == day.get_id())
+            .ok_or(HappyChartError::StorageDayNotFound(*day.get_id()))?;
+        *stored_day = day.clone();
+        let trash = self.load_trash()?;
+        self.store_all(&days, &trash)
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn delete(&mut self, id: &Uuid) -> Result<(), HappyChartError> {
+        let mut days = self.load()?;
+        let len = days.len();
+        days.retain(|day| day.get_id() != id);
+        if days.len() == len {
+            return Err(HappyChartError::StorageDayNotFound(*id));
+        }
+        let trash = self.load_trash()?;
+        self.store_all(&days, &trash)
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn query_range(
+        &mut self,
+        start: &DateTime<Local>,
+        end: &DateTime<Local>,
+    ) -> Result<Vec<ImprovedDayStat>, HappyChartError> {
+        Ok(self
+            .load()?
+            .into_iter()
+            .filter(|day| (start..=end).contains(&day.get_date()))
+            .collect())
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn load_trash(&mut self) -> Result<Vec<TrashedDay>, HappyChartError> {
+        let path = self.data_paths.save_file();
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            // a save file not written yet, or only found at the legacy path, has no trash
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(HappyChartError::ReadSaveFileIO(err, path)),
+        };
+        if data.iter().all(u8::is_ascii_whitespace) {
+            return Ok(vec![]);
+        }
+        let mut trash = match (is_encrypted_container(&data), &self.cipher) {
+            (true, Some(cipher)) => read_save_trash(&cipher.decrypt(&data)?)?,
+            (true, None) => return Err(HappyChartError::EncryptedSaveFile(data)),
+            (false, _) => read_save_trash(&data)?,
+        };
+        trash.sort_by_key(|trashed| trashed.deleted);
+        Ok(trash)
+    }
+
+    #[tracing::instrument(skip_all)]
+    fn store_all(
+        &mut self,
+        days: &[ImprovedDayStat],
+        trash: &[TrashedDay],
+    ) -> Result<(), HappyChartError> {
+        write_save_file(
+            &self.data_paths.save_file(),
+            days,
+            trash,
+            self.cipher.as_ref(),
+        )
+    }
+}
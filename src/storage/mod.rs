@@ -0,0 +1,283 @@
+use crate::common::data_paths::DataPaths;
+use crate::common::encryption::SaveCipher;
+use crate::common::persistence::{queue_save, PersistenceJob};
+use crate::day_stats::improved_daystat::ImprovedDayStat;
+use crate::day_stats::trashed_day::TrashedDay;
+use crate::prelude::HappyChartState;
+use crate::state::error_states::HappyChartError;
+use crate::storage::json_storage::JsonFileStorage;
+use crate::storage::sqlite_storage::SqliteStorage;
+use crate::{NEW_SAVE_FILE_NAME, SQLITE_SAVE_FILE_NAME};
+use chrono::{DateTime, Local};
+use egui::Context;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use strum_macros::EnumIter;
+use tracing::{error, info};
+use uuid::Uuid;
+
+pub mod json_storage;
+pub mod sqlite_storage;
+
+/// The kinds of storage a journal can be saved with, selectable in the options menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumIter)]
+pub enum StorageBackendKind {
+    /// The whole day list serialized as a single json file
+    JsonFile,
+    /// An embedded SQLite database holding one row per day
+    Sqlite,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for StorageBackendKind {
+    fn default() -> Self {
+        Self::JsonFile
+    }
+}
+
+impl Display for StorageBackendKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::JsonFile => write!(f, "JSON file"),
+            Self::Sqlite => write!(f, "SQLite database"),
+        }
+    }
+}
+
+impl StorageBackendKind {
+    /// Returns the path of the file this kind of storage keeps the day list in
+    pub fn get_save_file_path(&self, data_paths: &DataPaths) -> PathBuf {
+        match self {
+            Self::JsonFile => data_paths.save_file(),
+            Self::Sqlite => data_paths.sqlite_save_file(),
+        }
+    }
+
+    pub fn get_save_file_name(&self) -> &'static str {
+        match self {
+            Self::JsonFile => NEW_SAVE_FILE_NAME,
+            Self::Sqlite => SQLITE_SAVE_FILE_NAME,
+        }
+    }
+}
+
+/// Persistence for the day list and the trash, days are identified by their id
+pub trait StorageBackend {
+    fn kind(&self) -> StorageBackendKind;
+
+    /// Reads every stored day, sorted by date
+    fn load(&mut self) -> Result<Vec<ImprovedDayStat>, HappyChartError>;
+
+    fn append(&mut self, day: &ImprovedDayStat) -> Result<(), HappyChartError>;
+
+    /// Replaces the stored day that has the same id as `day`
+    fn update(&mut self, day: &ImprovedDayStat) -> Result<(), HappyChartError>;
+
+    fn delete(&mut self, id: &Uuid) -> Result<(), HappyChartError>;
+
+    /// Reads every stored day within `start..=end`, sorted by date
+    fn query_range(
+        &mut self,
+        start: &DateTime<Local>,
+        end: &DateTime<Local>,
+    ) -> Result<Vec<ImprovedDayStat>, HappyChartError>;
+
+    /// Reads every day in the trash, oldest deletion first
+    fn load_trash(&mut self) -> Result<Vec<TrashedDay>, HappyChartError>;
+
+    /// Replaces everything stored with the given day list and trash
+    fn store_all(
+        &mut self,
+        days: &[ImprovedDayStat],
+        trash: &[TrashedDay],
+    ) -> Result<(), HappyChartError>;
+
+    /// Stores the given day list and trash over what is stored, storage that can change a single day writes only the days that differ
+    fn store_changes(
+        &mut self,
+        days: &[ImprovedDayStat],
+        trash: &[TrashedDay],
+    ) -> Result<(), HappyChartError> {
+        self.store_all(days, trash)
+    }
+}
+
+/// Opens the given kind of storage, encrypting and decrypting days with the cipher if one is given
+#[tracing::instrument(skip(cipher))]
+pub fn open_storage(
+    data_paths: &DataPaths,
+    kind: StorageBackendKind,
+    cipher: Option<SaveCipher>,
+) -> Result<Box<dyn StorageBackend>, HappyChartError> {
+    match kind {
+        StorageBackendKind::JsonFile => {
+            Ok(Box::new(JsonFileStorage::new(data_paths.clone(), cipher)))
+        }
+        StorageBackendKind::Sqlite => Ok(Box::new(SqliteStorage::open(
+            &data_paths.sqlite_save_file(),
+            cipher,
+        )?)),
+    }
+}
+
+/// Everything needed to migrate the day list and the trash to another kind of storage on the persistence worker
+pub struct StorageMigrationJob {
+    data_paths: DataPaths,
+    days: Vec<ImprovedDayStat>,
+    trash: Vec<TrashedDay>,
+    from: StorageBackendKind,
+    pub to: StorageBackendKind,
+    cipher: Option<SaveCipher>,
+}
+
+/// Queues migrating the day list and the trash to another kind of storage, the storage in use is switched by `finish_storage_migration` once it has been verified.
+/// Jobs run in order, so saves queued before the migration are written to the storage being migrated away from first.
+#[tracing::instrument(skip(ctx, app))]
+pub fn queue_storage_migration(ctx: &Context, app: &mut HappyChartState, to: StorageBackendKind) {
+    let job = StorageMigrationJob {
+        data_paths: app.data_paths.clone(),
+        days: app.days.clone(),
+        trash: app.trash.clone(),
+        from: app.program_options.storage_backend,
+        to,
+        cipher: app.get_save_cipher(),
+    };
+    app.ui_states.migrating_storage = true;
+    app.persistence
+        .queue(ctx, PersistenceJob::MigrateStorage(job));
+}
+
+#[tracing::instrument(skip_all)]
+pub fn run_storage_migration_job(job: StorageMigrationJob) -> Result<(), HappyChartError> {
+    migrate_storage(
+        &job.data_paths,
+        &job.days,
+        &job.trash,
+        job.from,
+        job.to,
+        job.cipher,
+    )
+}
+
+/// Switches to the storage migrated to once it has been verified, and queues saving the program options so it is used from the next launch on
+#[tracing::instrument(skip(ctx, app, result))]
+pub fn finish_storage_migration(
+    ctx: &Context,
+    app: &mut HappyChartState,
+    to: StorageBackendKind,
+    result: Result<(), HappyChartError>,
+) {
+    app.ui_states.migrating_storage = false;
+    if let Err(err) = result {
+        error!("Error migrating storage: {}", err);
+        app.error_states.push(err);
+        return;
+    }
+    app.program_options.storage_backend = to;
+    if let Err(err) = queue_save(ctx, app) {
+        error!(
+            "Error saving program state after storage migration: {}",
+            err
+        );
+        app.error_states.push(err);
+    }
+}
+
+/// Copies the day list and the trash into another kind of storage, then reads them back to make sure nothing was lost along the way.
+/// The storage being migrated away from is left untouched.
+#[tracing::instrument(skip(days, trash, cipher))]
+pub fn migrate_storage(
+    data_paths: &DataPaths,
+    days: &[ImprovedDayStat],
+    trash: &[TrashedDay],
+    from: StorageBackendKind,
+    to: StorageBackendKind,
+    cipher: Option<SaveCipher>,
+) -> Result<(), HappyChartError> {
+    info!("Migrating {} days from {} to {}", days.len(), from, to);
+    let mut target = open_storage(data_paths, to, cipher)?;
+    target.store_all(days, trash)?;
+
+    let mut expected = days.to_vec();
+    expected.sort_by_key(|day| *day.get_date());
+    let stored = target.load()?;
+    let stored_trash = target.load_trash()?;
+
+    let expected_ser =
+        serde_json::to_string(&(&expected, trash)).map_err(HappyChartError::Serialization)?;
+    let stored_ser =
+        serde_json::to_string(&(&stored, &stored_trash)).map_err(HappyChartError::Serialization)?;
+
+    if expected_ser != stored_ser {
+        error!(
+            "Migrated storage does not match, expected {} days and {} trashed days, found {} and {}",
+            expected.len(),
+            trash.len(),
+            stored.len(),
+            stored_trash.len()
+        );
+        return Err(HappyChartError::StorageMigrationMismatch { from, to });
+    }
+
+    info!("Migration from {} to {} verified", from, to);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::data_paths::DataLocation;
+    use chrono::{Duration, TimeZone};
+    use std::env;
+    use std::fs;
+
+    fn day_at(date: DateTime<Local>, note: &str) -> ImprovedDayStat {
+        ImprovedDayStat::new(50.0, date, note, vec![], vec![])
+    }
+
+    /// Stores days an hour apart in each kind of storage, and reads back the ones within a range of them
+    fn check_query_range(kind: StorageBackendKind) {
+        let dir = env::temp_dir().join(format!(
+            "happy_chart_query_range_test_{}",
+            rand::random::<u64>()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let data_paths = DataPaths::in_single_dir(dir.clone(), DataLocation::CommandLine);
+
+        let start = Local.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let days = (0..5)
+            .map(|hour| day_at(start + Duration::hours(hour), &format!("hour {}", hour)))
+            .collect::<Vec<ImprovedDayStat>>();
+
+        let result = open_storage(&data_paths, kind, None).and_then(|mut storage| {
+            // stored out of order, so the range has to be sorted when read
+            let mut shuffled = days.clone();
+            shuffled.reverse();
+            storage.store_all(&shuffled, &[])?;
+            let inside = storage.query_range(days[1].get_date(), days[3].get_date())?;
+            let empty =
+                storage.query_range(&(start - Duration::days(2)), &(start - Duration::days(1)))?;
+            Ok((inside, empty))
+        });
+        fs::remove_dir_all(&dir).unwrap();
+        let (inside, empty) = result.unwrap();
+
+        let notes = inside
+            .iter()
+            .map(|day| day.get_note().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(notes, ["hour 1", "hour 2", "hour 3"]);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn json_storage_queries_range() {
+        check_query_range(StorageBackendKind::JsonFile);
+    }
+
+    #[test]
+    fn sqlite_storage_queries_range() {
+        check_query_range(StorageBackendKind::Sqlite);
+    }
+}
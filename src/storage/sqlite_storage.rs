@@ -0,0 +1,397 @@
+use crate::common::encryption::{is_encrypted_container, SaveCipher};
+use crate::common::save_format::SaveMetadata;
+use crate::day_stats::improved_daystat::ImprovedDayStat;
+use crate::day_stats::trashed_day::TrashedDay;
+use crate::state::error_states::HappyChartError;
+use crate::storage::{StorageBackend, StorageBackendKind};
+use crate::SAVE_FORMAT_VERSION;
+use chrono::{DateTime, Local};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// Storage that keeps one row per day in an embedded SQLite database, so changes only touch the rows involved.
+/// Rows are keyed by the id of their day, along with its time so they can be read in order.
+/// Each row holds the day serialized as json, encrypted on its own when a cipher is given, deleted days are kept the same way in the trash table.
+/// The save file metadata is kept as json in a table of its own, and refreshed on every change.
+pub struct SqliteStorage {
+    connection: Connection,
+    path: PathBuf,
+    cipher: Option<SaveCipher>,
+    metadata: SaveMetadata,
+}
+
+impl SqliteStorage {
+    #[tracing::instrument(skip(cipher))]
+    pub fn open(path: &Path, cipher: Option<SaveCipher>) -> Result<Self, HappyChartError> {
+        info!("Opening SQLite storage at path: {:?}", path);
+        let connection = Connection::open(path)
+            .map_err(|err| HappyChartError::SqliteStorage(err, path.to_path_buf()))?;
+        let sqlite_error = |err| HappyChartError::SqliteStorage(err, path.to_path_buf());
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS days (
+                    id TEXT PRIMARY KEY,
+                    timestamp_micros INTEGER NOT NULL,
+                    data BLOB NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS days_by_time ON days (timestamp_micros);
+                CREATE TABLE IF NOT EXISTS trash (
+                    id TEXT PRIMARY KEY,
+                    data BLOB NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS save_metadata (
+                    id INTEGER PRIMARY KEY CHECK (id = 0),
+                    data TEXT NOT NULL
+                );",
+            )
+            .map_err(sqlite_error)?;
+
+        let stored_metadata = connection
+            .query_row("SELECT data FROM save_metadata WHERE id = 0", [], |row| {
+                row.get::<_, String>(0)
+            })
+            .optional()
+            .map_err(sqlite_error)?;
+
+        let metadata = match stored_metadata {
+            Some(stored_metadata) => serde_json::from_str::<SaveMetadata>(&stored_metadata)
+                .map_err(|err| HappyChartError::Deserialization(err, None))?,
+            None => SaveMetadata::current(Local::now()),
+        };
+
+        if metadata.format_version > SAVE_FORMAT_VERSION {
+            return Err(HappyChartError::UnsupportedSaveFormatVersion {
+                found: metadata.format_version,
+                supported: SAVE_FORMAT_VERSION,
+                app_version: metadata.app_version,
+            });
+        }
+
+        Ok(Self {
+            connection,
+            path: path.to_path_buf(),
+            cipher,
+            metadata,
+        })
+    }
+
+    fn sqlite_error(&self, err: rusqlite::Error) -> HappyChartError {
+        HappyChartError::SqliteStorage(err, self.path.clone())
+    }
+
+    /// Marks the database as written by this version of happy chart, keeping its creation date
+    fn write_metadata(&mut self) -> Result<(), HappyChartError> {
+        self.metadata = SaveMetadata::current(self.metadata.created);
+        let ser = serde_json::to_string(&self.metadata).map_err(HappyChartError::Serialization)?;
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO save_metadata (id, data) VALUES (0, ?1)",
+                params![ser],
+            )
+            .map_err(|err| self.sqlite_error(err))?;
+        Ok(())
+    }
+
+    /// Rebuilds the database file, so nothing of deleted or replaced rows is left in its free pages
+    pub fn vacuum(&self) -> Result<(), HappyChartError> {
+        self.connection
+            .execute_batch("VACUUM")
+            .map_err(|err| self.sqlite_error(err))
+    }
+
+    /// Appends, updates and deletes the rows of the days that differ between the stored day list and the given one, and replaces the trash
+    fn write_changes(
+        &mut self,
+        days: &[ImprovedDayStat],
+        trash: &[TrashedDay],
+    ) -> Result<(usize, usize, usize), HappyChartError> {
+        let serialize = |day: &ImprovedDayStat| {
+            serde_json::to_string(day).map_err(HappyChartError::Serialization)
+        };
+        let mut stored_days = self
+            .load()?
+            .iter()
+            .map(|day| Ok((*day.get_id(), serialize(day)?)))
+            .collect::<Result<HashMap<Uuid, String>, HappyChartError>>()?;
+
+        let (mut appended, mut updated) = (0, 0);
+        for day in days {
+            match stored_days.remove(day.get_id()) {
+                None => {
+                    self.append(day)?;
+                    appended += 1;
+                }
+                Some(stored_day) if stored_day != serialize(day)? => {
+                    self.update(day)?;
+                    updated += 1;
+                }
+                Some(_) => {}
+            }
+        }
+        for id in stored_days.keys() {
+            self.delete(id)?;
+        }
+
+        let trash_rows = trash
+            .iter()
+            .map(|trashed| Ok((trashed.day.get_id().to_string(), self.encode_row(trashed)?)))
+            .collect::<Result<Vec<(String, Vec<u8>)>, HappyChartError>>()?;
+        self.connection
+            .execute("DELETE FROM trash", [])
+            .map_err(|err| self.sqlite_error(err))?;
+        for (id, data) in trash_rows {
+            self.connection
+                .execute(
+                    "INSERT INTO trash (id, data) VALUES (?1, ?2)",
+                    params![id, data],
+                )
+                .map_err(|err| self.sqlite_error(err))?;
+        }
+        self.write_metadata()?;
+        Ok((appended, updated, stored_days.len()))
+    }
+
+    /// Serializes a day, or a day in the trash, into the bytes stored in its row
+    fn encode_row<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, HappyChartError> {
+        let ser = serde_json::to_vec(value).map_err(HappyChartError::Serialization)?;
+        match &self.cipher {
+            Some(cipher) => cipher.encrypt(&ser),
+            None => Ok(ser),
+        }
+    }
+
+    /// Deserializes the bytes stored in a row, decrypting them first if they are encrypted
+    fn decode_row<T: DeserializeOwned>(&self, data: Vec<u8>) -> Result<T, HappyChartError> {
+        match serde_json::from_slice::<T>(&data) {
+            Ok(value) => Ok(value),
+            Err(err) if is_encrypted_container(&data) => match &self.cipher {
+                Some(cipher) => serde_json::from_slice::<T>(&cipher.decrypt(&data)?)
+                    .map_err(|err| HappyChartError::Deserialization(err, None)),
+                None => {
+                    info!("SQLite storage row is encrypted: {}", err);
+                    Err(HappyChartError::EncryptedSaveFile(data))
+                }
+            },
+            Err(err) => Err(HappyChartError::Deserialization(err, None)),
+        }
+    }
+
+    /// Returns true if any stored day, or day in the trash, is encrypted
+    pub fn has_encrypted_rows(&self) -> Result<bool, HappyChartError> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT data FROM days UNION ALL SELECT data FROM trash")
+            .map_err(|err| self.sqlite_error(err))?;
+        let mut rows = statement
+            .query_map([], |row| row.get::<_, Vec<u8>>(0))
+            .map_err(|err| self.sqlite_error(err))?;
+
+        rows.try_fold(false, |encrypted, row| {
+            Ok(encrypted || is_encrypted_container(&row.map_err(|err| self.sqlite_error(err))?))
+        })
+    }
+
+    fn query_rows<T: DeserializeOwned>(
+        &self,
+        sql: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<T>, HappyChartError> {
+        let mut statement = self
+            .connection
+            .prepare(sql)
+            .map_err(|err| self.sqlite_error(err))?;
+        let rows = statement
+            .query_map(params, |row| row.get::<_, Vec<u8>>(0))
+            .map_err(|err| self.sqlite_error(err))?;
+
+        rows.map(|row| self.decode_row(row.map_err(|err| self.sqlite_error(err))?))
+            .collect()
+    }
+}
+
+impl StorageBackend for SqliteStorage {
+    fn kind(&self) -> StorageBackendKind {
+        StorageBackendKind::Sqlite
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn load(&mut self) -> Result<Vec<ImprovedDayStat>, HappyChartError> {
+        self.query_rows("SELECT data FROM days ORDER BY timestamp_micros", [])
+    }
+
+    #[tracing::instrument(skip_all)]
+    fn append(&mut self, day: &ImprovedDayStat) -> Result<(), HappyChartError> {
+        let data = self.encode_row(day)?;
+        self.connection
+            .execute(
+                "INSERT INTO days (id, timestamp_micros, data) VALUES (?1, ?2, ?3)",
+                params![
+                    day.get_id().to_string(),
+                    day.get_date().timestamp_micros(),
+                    data
+                ],
+            )
+            .map_err(|err| self.sqlite_error(err))?;
+        self.write_metadata()
+    }
+
+    #[tracing::instrument(skip_all)]
+    fn update(&mut self, day: &ImprovedDayStat) -> Result<(), HappyChartError> {
+        let data = self.encode_row(day)?;
+        let changed = self
+            .connection
+            .execute(
+                "UPDATE days SET timestamp_micros = ?2, data = ?3 WHERE id = ?1",
+                params![
+                    day.get_id().to_string(),
+                    day.get_date().timestamp_micros(),
+                    data
+                ],
+            )
+            .map_err(|err| self.sqlite_error(err))?;
+        if changed == 0 {
+            return Err(HappyChartError::StorageDayNotFound(*day.get_id()));
+        }
+        self.write_metadata()
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn delete(&mut self, id: &Uuid) -> Result<(), HappyChartError> {
+        let changed = self
+            .connection
+            .execute("DELETE FROM days WHERE id = ?1", params![id.to_string()])
+            .map_err(|err| self.sqlite_error(err))?;
+        if changed == 0 {
+            return Err(HappyChartError::StorageDayNotFound(*id));
+        }
+        self.write_metadata()
+    }
+
+    /// Reads only the rows within the range, using the index on their time
+    #[tracing::instrument(skip(self))]
+    fn query_range(
+        &mut self,
+        start: &DateTime<Local>,
+        end: &DateTime<Local>,
+    ) -> Result<Vec<ImprovedDayStat>, HappyChartError> {
+        self.query_rows(
+            "SELECT data FROM days WHERE timestamp_micros BETWEEN ?1 AND ?2 ORDER BY timestamp_micros",
+            params![start.timestamp_micros(), end.timestamp_micros()],
+        )
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn load_trash(&mut self) -> Result<Vec<TrashedDay>, HappyChartError> {
+        let mut trash = self.query_rows::<TrashedDay>("SELECT data FROM trash", [])?;
+        trash.sort_by_key(|trashed| trashed.deleted);
+        Ok(trash)
+    }
+
+    /// Writes only the rows of days that were added, changed or removed, in a single transaction so a failure part way through leaves the previous rows in place
+    #[tracing::instrument(skip_all)]
+    fn store_changes(
+        &mut self,
+        days: &[ImprovedDayStat],
+        trash: &[TrashedDay],
+    ) -> Result<(), HappyChartError> {
+        self.connection
+            .execute_batch("BEGIN")
+            .map_err(|err| self.sqlite_error(err))?;
+        match self.write_changes(days, trash) {
+            Ok((appended, updated, deleted)) => {
+                self.connection
+                    .execute_batch("COMMIT")
+                    .map_err(|err| self.sqlite_error(err))?;
+                info!(
+                    "Stored changes in SQLite storage: {} days added, {} updated, {} deleted",
+                    appended, updated, deleted
+                );
+                Ok(())
+            }
+            Err(err) => {
+                if let Err(rollback_err) = self.connection.execute_batch("ROLLBACK") {
+                    error!(
+                        "Unable to roll back SQLite storage changes: {}",
+                        rollback_err
+                    );
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Replaces every row in a single transaction, so a failure part way through leaves the previous rows in place
+    #[tracing::instrument(skip_all)]
+    fn store_all(
+        &mut self,
+        days: &[ImprovedDayStat],
+        trash: &[TrashedDay],
+    ) -> Result<(), HappyChartError> {
+        let rows = days
+            .iter()
+            .map(|day| {
+                Ok((
+                    day.get_id().to_string(),
+                    day.get_date().timestamp_micros(),
+                    self.encode_row(day)?,
+                ))
+            })
+            .collect::<Result<Vec<(String, i64, Vec<u8>)>, HappyChartError>>()?;
+        let trash_rows = trash
+            .iter()
+            .map(|trashed| Ok((trashed.day.get_id().to_string(), self.encode_row(trashed)?)))
+            .collect::<Result<Vec<(String, Vec<u8>)>, HappyChartError>>()?;
+
+        self.metadata = SaveMetadata::current(self.metadata.created);
+        let metadata_ser =
+            serde_json::to_string(&self.metadata).map_err(HappyChartError::Serialization)?;
+
+        let path = self.path.clone();
+        let sqlite_error = |err| HappyChartError::SqliteStorage(err, path.clone());
+
+        let transaction = self.connection.transaction().map_err(sqlite_error)?;
+        transaction
+            .execute("DELETE FROM days", [])
+            .map_err(sqlite_error)?;
+        {
+            let mut insert = transaction
+                .prepare("INSERT INTO days (id, timestamp_micros, data) VALUES (?1, ?2, ?3)")
+                .map_err(sqlite_error)?;
+            for (id, timestamp_micros, data) in rows {
+                insert
+                    .execute(params![id, timestamp_micros, data])
+                    .map_err(sqlite_error)?;
+            }
+        }
+        transaction
+            .execute("DELETE FROM trash", [])
+            .map_err(sqlite_error)?;
+        {
+            let mut insert = transaction
+                .prepare("INSERT INTO trash (id, data) VALUES (?1, ?2)")
+                .map_err(sqlite_error)?;
+            for (id, data) in trash_rows {
+                insert.execute(params![id, data]).map_err(sqlite_error)?;
+            }
+        }
+        transaction
+            .execute(
+                "INSERT OR REPLACE INTO save_metadata (id, data) VALUES (0, ?1)",
+                params![metadata_ser],
+            )
+            .map_err(sqlite_error)?;
+        transaction.commit().map_err(sqlite_error)?;
+
+        info!(
+            "Stored {} days and {} trashed days in SQLite storage",
+            days.len(),
+            trash.len()
+        );
+        Ok(())
+    }
+}
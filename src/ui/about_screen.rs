@@ -1,6 +1,6 @@
 use crate::state::happy_chart_state::HappyChartState;
 use crate::ui::help_screen::draw_help_dropdown;
-use crate::{BUILD_TIMESTAMP, GIT_DESCRIBE};
+use crate::{BUILD_TIMESTAMP, DEFAULT_PROFILE_NAME, GIT_DESCRIBE};
 use egui::Ui;
 use self_update::cargo_crate_version;
 
@@ -22,6 +22,19 @@ pub fn draw_about_page(about_page_ui: &mut Ui, app: &mut HappyChartState) {
     about_page_ui.label(format!("BUILD_TIMESTAMP: {}", BUILD_TIMESTAMP));
 
     about_page_ui.separator();
+    about_page_ui.label(format!(
+        "Profile: {}",
+        app.data_paths.profile().unwrap_or(DEFAULT_PROFILE_NAME)
+    ));
+    about_page_ui.label(format!(
+        "Data folder: {} [{}]",
+        app.data_paths.data_dir().display(),
+        app.data_paths.location()
+    ));
+    about_page_ui.label(format!(
+        "Config folder: {}",
+        app.data_paths.config_dir().display()
+    ));
     about_page_ui.label(format!("Last backup date: {}", app.last_backup_date));
     about_page_ui.label(format!("Last open date: {}", app.last_open_date));
     about_page_ui.label(format!(
@@ -1,3 +1,4 @@
+use crate::common::history::{execute_command, JournalCommand};
 use crate::prelude::HappyChartState;
 use crate::state::activities::Activity;
 use egui::{Context, Ui};
@@ -11,15 +12,14 @@ pub fn draw_activity_selector_screen(ui: &mut Ui, _ctx: &Context, app: &mut Happ
                 ui.label("Activity name:");
                 ui.text_edit_singleline(&mut app.ui_states.activity_ui_state.activity_creat_text);
                 if ui.button("Add new activity").clicked() {
-                    app.program_options
-                        .activity_list
-                        .add_new_activity(Activity::new(
-                            app.ui_states
-                                .activity_ui_state
-                                .activity_creat_text
-                                .as_str()
-                                .trim(),
-                        ));
+                    let activity = Activity::new(
+                        app.ui_states
+                            .activity_ui_state
+                            .activity_creat_text
+                            .as_str()
+                            .trim(),
+                    );
+                    execute_command(app, JournalCommand::AddActivity(activity));
                 }
             });
         }
@@ -34,7 +34,7 @@ pub fn draw_activity_selector_screen(ui: &mut Ui, _ctx: &Context, app: &mut Happ
                 ui.horizontal(|ui| {
                     ui.label(format!("Remove {}", activity.get_activity_name()));
                     if ui.button("X").clicked() {
-                        app.program_options.activity_list.remove_activity(&activity);
+                        execute_command(app, JournalCommand::RemoveActivity(activity.clone()));
                     }
                 });
             }
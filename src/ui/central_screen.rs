@@ -1,14 +1,18 @@
 use crate::common::color::{get_tutorial_lowlight_glowing_color, tutorial_button_colors};
+use crate::common::history::{execute_command, JournalCommand};
 use crate::common::math::{calculate_centered_graph_scaling, distance, improved_calculate_x};
-use crate::common::mood_tag::MoodTag;
 use crate::common::quit;
 use crate::common::update::{should_show_update, update_program};
 use crate::day_stats::improved_daystat::ImprovedDayStat;
 use crate::options::color_setting;
+use crate::options::entry_edit_policy::EntryEditState;
+use crate::state::error_states::HappyChartError;
 use crate::state::happy_chart_state::HappyChartState;
 use crate::state::tutorial_state::TutorialGoal;
+use crate::ui::date_time_picker::draw_date_time_picker;
+use crate::ui::persistence_screen::draw_persistence_progress;
 use crate::{BUILD_TIMESTAMP, GIT_DESCRIBE};
-use chrono::Days;
+use chrono::{Days, Local};
 use eframe::emath::{Align2, Pos2, Rect, Vec2};
 use eframe::epaint::{Color32, FontId, Rounding, Stroke};
 use egui::{Context, Id, LayerId, Layout, Order, Rangef, RichText, Ui, ViewportCommand};
@@ -125,17 +129,60 @@ pub fn main_screen_button_ui(central_panel_ui: &mut Ui, app: &mut HappyChartStat
         });
     });
 
+    central_panel_ui.horizontal(|ui| {
+        let mut backdate = app.ui_states.add_day_time.is_some();
+        if ui
+            .checkbox(&mut backdate, "Pick date")
+            .on_hover_text("Adds the day at a picked date and time instead of now, such as for a day you forgot to add.")
+            .changed()
+        {
+            app.ui_states.add_day_time = backdate.then(Local::now);
+        }
+        if let Some(add_day_time) = app.ui_states.add_day_time {
+            if let Some(picked) = draw_date_time_picker(ui, &add_day_time) {
+                app.ui_states.add_day_time = Some(picked);
+            }
+        }
+    });
+
+    let policy_days = app.program_options.entry_edit_policy_days;
+    let add_day_state = app
+        .ui_states
+        .add_day_time
+        .map(|date| app.program_options.get_entry_date_edit_state(&date));
+    match add_day_state {
+        Some(EntryEditState::Warn) => {
+            central_panel_ui.label(RichText::new(format!("The picked date is more than {} days ago, your memory of days that long ago may not be representative.", policy_days)).color(Color32::LIGHT_RED));
+        }
+        Some(EntryEditState::Locked) => {
+            central_panel_ui.label(RichText::new(format!("The picked date is more than {} days ago, days that old are read-only under the entry edit policy, so a day can not be added there.", policy_days)).color(Color32::LIGHT_RED));
+        }
+        Some(EntryEditState::Editable) | None => {}
+    }
+
     let old_widget_visuals = central_panel_ui.style().visuals.widgets.inactive;
 
     if matches!(app.tutorial_state, TutorialGoal::AddDay) {
         tutorial_button_colors(central_panel_ui);
     }
 
-    if central_panel_ui.button("Add day").clicked() {
-        info!("Day added");
-        app.days.push(ImprovedDayStat::new(
+    if central_panel_ui
+        .add_enabled(
+            add_day_state != Some(EntryEditState::Locked),
+            egui::Button::new("Add day"),
+        )
+        .on_disabled_hover_text("The picked date is read-only under the entry edit policy, pick a more recent date or change the policy in the options menu.")
+        .clicked()
+    {
+        // a picked date is only used for the day it was picked for
+        let date = app
+            .ui_states
+            .add_day_time
+            .take()
+            .unwrap_or_else(ImprovedDayStat::get_current_time_system);
+        let day = ImprovedDayStat::new(
             app.rating as f32,
-            ImprovedDayStat::get_current_time_system(),
+            app.get_unique_entry_time(date, None),
             &app.note_input,
             app.mood_selection_list.clone(),
             app.ui_states
@@ -143,24 +190,28 @@ pub fn main_screen_button_ui(central_panel_ui: &mut Ui, app: &mut HappyChartStat
                 .added_activity_list
                 .get_activity_list()
                 .clone(),
-        ));
+        );
+        execute_command(app, JournalCommand::AddDay(day));
 
         if matches!(app.tutorial_state, TutorialGoal::AddDay) {
             app.tutorial_state = TutorialGoal::OpenOptions;
         }
 
-        app.stats
-            .calc_all_stats(&app.days, app.program_options.streak_leniency);
         info!("Day added, new day stat length: {}", app.days.len());
     }
 
     central_panel_ui.style_mut().visuals.widgets.inactive = old_widget_visuals;
 
-    if central_panel_ui.button("Remove day").clicked() && !app.days.is_empty() {
-        info!("Day removed");
-        app.days.remove(app.days.len() - 1);
-        app.stats
-            .calc_all_stats(&app.days, app.program_options.streak_leniency);
+    let last_day_locked = app.days.last().is_some_and(|day| app.is_entry_locked(day));
+    if central_panel_ui
+        .add_enabled(!last_day_locked, egui::Button::new("Remove day"))
+        .on_hover_text("Moves the last day to the trash, which can be undone with Ctrl+Z. Any day can be deleted from its note editor.")
+        .on_disabled_hover_text("The last day is read-only under the entry edit policy, unlock it in the note editor to remove it.")
+        .clicked()
+    {
+        if let Some(day) = app.days.last().cloned() {
+            execute_command(app, JournalCommand::TrashDay(day));
+        }
     }
 
     let mut bottom_search_rect = None;
@@ -171,6 +222,7 @@ pub fn main_screen_button_ui(central_panel_ui: &mut Ui, app: &mut HappyChartStat
                 Vec2::new(120.0, 20.0),
                 egui::widgets::text_edit::TextEdit::singleline(&mut app.filter_term),
             )
+            .on_hover_text("Highlights days with the text in their note, with the mood of that name, or whose id starts with the text.")
             .rect,
         );
     });
@@ -337,11 +389,9 @@ pub fn draw_stat_circles(central_panel_ui: &Ui, app: &HappyChartState, ctx: &Con
     let mut moused_over = false;
     let dist_max = app.program_options.mouse_over_radius;
 
-    for (idx, day) in app.days[0..app.get_day_index_animation()]
-        .to_vec()
-        .iter()
-        .enumerate()
-    {
+    let streak_dates = app.stats.get_streak_stats().get_streak_dates(&app.days);
+
+    for day in app.days[0..app.get_day_index_animation()].to_vec().iter() {
         let x: f32 = improved_calculate_x(
             &app.days,
             day,
@@ -354,18 +404,18 @@ pub fn draw_stat_circles(central_panel_ui: &Ui, app: &HappyChartState, ctx: &Con
         ) - app.program_options.day_stat_height_offset
             + app.get_day_line_y_value();
 
-        let stat_outline_color =
-            if distance(mouse_pos.x, mouse_pos.y, x, y) < dist_max && !moused_over {
-                moused_over = true;
-                app.program_options.color_settings.stat_mouse_over_color
-            } else if idx >= app.stats.get_streak_stats().streak_start_index
-                && idx < app.stats.get_streak_stats().streak_end_index
-                && app.program_options.show_streak
-            {
-                app.program_options.color_settings.stat_outline_streak_color
-            } else {
-                app.program_options.color_settings.stat_outline_color
-            };
+        let stat_outline_color = if distance(mouse_pos.x, mouse_pos.y, x, y) < dist_max
+            && !moused_over
+        {
+            moused_over = true;
+            app.program_options.color_settings.stat_mouse_over_color
+        } else if streak_dates.is_some_and(|(start, end)| (start..=end).contains(day.get_date()))
+            && app.program_options.show_streak
+        {
+            app.program_options.color_settings.stat_outline_streak_color
+        } else {
+            app.program_options.color_settings.stat_outline_color
+        };
 
         //draw circles on each coordinate point
         central_panel_ui.painter().circle_filled(
@@ -374,17 +424,12 @@ pub fn draw_stat_circles(central_panel_ui: &Ui, app: &HappyChartState, ctx: &Con
             stat_outline_color,
         );
 
-        let stat_rating_color = if !app.filter_term.is_empty()
-            && (day.get_note().contains(&app.filter_term) || {
-                match MoodTag::get_mood_by_name(&app.filter_term) {
-                    None => false,
-                    Some(mood_tag) => day.get_mood_tags().contains(&mood_tag),
-                }
-            }) {
-            Color32::BLUE
-        } else {
-            color_setting::get_shape_color_from_rating(day.get_rating())
-        };
+        let stat_rating_color =
+            if !app.filter_term.is_empty() && day.matches_search(&app.filter_term) {
+                Color32::BLUE
+            } else {
+                color_setting::get_shape_color_from_rating(day.get_rating())
+            };
 
         central_panel_ui.painter().circle_filled(
             Pos2::new(x, y),
@@ -414,10 +459,7 @@ pub fn draw_stat_mouse_over_info(
         !right_click_down && left_click_down && ctrl_down
     };
 
-    for (idx, day) in app.days[0..app.get_day_index_animation()]
-        .iter()
-        .enumerate()
-    {
+    for day in &app.days[0..app.get_day_index_animation()] {
         let x: f32 = improved_calculate_x(
             &app.days,
             day,
@@ -433,7 +475,7 @@ pub fn draw_stat_mouse_over_info(
         let rect_pos2 = Pos2::new(770.0, 160.0);
         let text = {
             if cfg!(debug_assertions) {
-                format!("idx: {} {}\n", idx, day)
+                format!("id: {} {}\n", day.get_id(), day)
             } else {
                 day.to_string()
             }
@@ -472,8 +514,8 @@ pub fn draw_stat_mouse_over_info(
             );
 
             if select_note {
-                info!("Note selected to edit: {}", idx);
-                app.note_edit_selected = Some(idx);
+                info!("Note selected to edit: {}", day.get_id());
+                app.note_edit_selected = Some(*day.get_id());
             }
         }
     }
@@ -546,7 +588,7 @@ pub fn draw_bottom_row_buttons(
 ) {
     // quit button layout
     central_panel_ui.with_layout(Layout::bottom_up(egui::Align::BOTTOM), |ui| {
-        if app.starting_length != app.days.len() {
+        if app.has_unsaved_changes() {
             ui.visuals_mut().override_text_color = Option::from(Color32::RED);
         } else {
             ui.style_mut().visuals.override_text_color =
@@ -554,14 +596,16 @@ pub fn draw_bottom_row_buttons(
         }
 
         ui.horizontal(|ui| {
+            let quit_button_text = if app.read_only { "Quit (read-only)" } else { "Save & Quit" };
+            let save_blocked = app.error_states.iter().any(HappyChartError::blocks_saving);
             let quit_button = ui
-                .add_enabled(app.error_states.is_empty(),egui::Button::new("Save & Quit"))
+                .add_enabled(!save_blocked,egui::Button::new(quit_button_text))
                 .on_disabled_hover_text("There are outstanding errors present, please resolve them in order to Save & Quit");
 
             if quit_button.clicked() {
                 info!("Quit button clicked");
-                // Only let the user quit the program through save and quit if there are no outstanding errors
-                if app.error_states.is_empty() {
+                // Only let the user quit the program through save and quit if no outstanding error blocks saving
+                if !save_blocked {
                     // attempt to quit the application, but present an error state if one occurs during the quit process
                     if let Err(err) = quit(ctx, app) {
                         error!("Error during quit program sequence: {}",err);
@@ -570,6 +614,17 @@ pub fn draw_bottom_row_buttons(
                 }
             }
 
+            let last_saved = app.get_last_save_date().map_or_else(
+                || "not saved this session".to_string(),
+                |date| format!("last saved at {}", date.format("%H:%M:%S")),
+            );
+            if app.has_unsaved_changes() {
+                ui.label(format!("Unsaved changes, {}", last_saved));
+            } else if !app.read_only {
+                ui.label(format!("No unsaved changes, {}", last_saved));
+            }
+            draw_persistence_progress(ui, app);
+
             ui.style_mut().visuals.override_text_color =
                 Option::from(app.program_options.color_settings.text_color);
             let old_widget_visuals = ui.style().visuals.widgets.inactive;
@@ -598,6 +653,17 @@ pub fn draw_bottom_row_buttons(
                 app.stats.calc_all_stats(&app.days,app.program_options.streak_leniency);
             }
 
+            if !app.ui_states.showing_history_screen && ui.button("History").on_hover_text("Edits made this session, which can be undone and redone.").clicked() {
+                info!("Edit history screen opened");
+                app.ui_states.showing_history_screen = true;
+            }
+
+            if !app.ui_states.showing_trash_screen && ui.button("Trash").on_hover_text("Deleted days, which can be restored until they are purged.").clicked() {
+                info!("Trash screen opened");
+                app.purge_expired_trash();
+                app.ui_states.showing_trash_screen = true;
+            }
+
             if ui.button("Save Screenshot").clicked() {
                 info!("Screenshot button clicked");
                 // frame.request_screenshot();
@@ -0,0 +1,44 @@
+use crate::common::data_paths::MigrationOutcome;
+use crate::prelude::HappyChartState;
+use egui::{Color32, RichText, Ui};
+use tracing::info;
+
+/// Draw the list of files that were moved out of the working directory into the data folder on launch
+#[tracing::instrument(skip_all)]
+pub fn draw_data_migration_screen(ui: &mut Ui, app: &mut HappyChartState) {
+    ui.label("Happy chart now stores its files in a data folder instead of the folder it was launched from.");
+    ui.label(format!(
+        "Data folder: {}",
+        app.data_paths.data_dir().display()
+    ));
+    ui.separator();
+
+    egui::ScrollArea::vertical()
+        .max_height(300.0)
+        .show(ui, |ui| {
+            for migrated_file in &app.data_migration_report {
+                let (outcome_text, color) = match &migrated_file.outcome {
+                    MigrationOutcome::Moved => ("Moved".to_string(), Color32::LIGHT_GREEN),
+                    MigrationOutcome::AlreadyExists => (
+                        "Left in place, a file already exists in the data folder".to_string(),
+                        Color32::YELLOW,
+                    ),
+                    MigrationOutcome::Failed(err) => {
+                        (format!("Failed to move: {}", err), Color32::LIGHT_RED)
+                    }
+                };
+                ui.label(format!(
+                    "{}\n  -> {}",
+                    migrated_file.from.display(),
+                    migrated_file.to.display()
+                ));
+                ui.label(RichText::new(outcome_text).color(color));
+            }
+        });
+
+    ui.separator();
+    if ui.button("Dismiss").clicked() {
+        info!("Data migration report dismissed");
+        app.data_migration_report.clear();
+    }
+}
@@ -0,0 +1,64 @@
+use chrono::{DateTime, Datelike, Local, NaiveDate, TimeZone, Timelike};
+use egui::{DragValue, Ui};
+
+/// Draws drag values for the date and time of `date`, returns the picked date and time if it was changed.
+/// The seconds of `date` are kept, and a day past the end of the picked month is moved to the last day of the month.
+pub fn draw_date_time_picker(ui: &mut Ui, date: &DateTime<Local>) -> Option<DateTime<Local>> {
+    let local = date.naive_local();
+    let mut year = local.year();
+    let mut month = local.month();
+    let mut day = local.day();
+    let mut hour = local.hour();
+    let mut minute = local.minute();
+
+    let changed = ui
+        .horizontal(|ui| {
+            let mut changed = false;
+            changed |= ui
+                .add(DragValue::new(&mut year).clamp_range(1970..=9999))
+                .on_hover_text("Year")
+                .changed();
+            ui.label("-");
+            changed |= ui
+                .add(DragValue::new(&mut month).clamp_range(1..=12))
+                .on_hover_text("Month")
+                .changed();
+            ui.label("-");
+            changed |= ui
+                .add(DragValue::new(&mut day).clamp_range(1..=31))
+                .on_hover_text("Day")
+                .changed();
+            ui.add_space(8.0);
+            changed |= ui
+                .add(DragValue::new(&mut hour).clamp_range(0..=23))
+                .on_hover_text("Hour")
+                .changed();
+            ui.label(":");
+            changed |= ui
+                .add(DragValue::new(&mut minute).clamp_range(0..=59))
+                .on_hover_text("Minute")
+                .changed();
+            changed
+        })
+        .inner;
+
+    if !changed {
+        return None;
+    }
+
+    let picked = NaiveDate::from_ymd_opt(year, month, day.min(get_days_in_month(year, month)))?
+        .and_hms_nano_opt(hour, minute, local.second(), local.nanosecond())?;
+    // a time skipped by a daylight saving change has no local time, and a repeated one uses its first occurrence
+    Local.from_local_datetime(&picked).earliest()
+}
+
+fn get_days_in_month(year: i32, month: u32) -> u32 {
+    let next_month = if month == 12 {
+        NaiveDate::from_ymd_opt(year + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd_opt(year, month + 1, 1)
+    };
+    next_month
+        .and_then(|next_month| next_month.pred_opt())
+        .map_or(31, |last_day| last_day.day())
+}
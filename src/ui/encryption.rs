@@ -1,60 +1,49 @@
 use crate::common::color::tutorial_button_colors;
-use crate::common::encryption::{decrypt_save_file, encryption_save_file_checks};
-use crate::common::first_load;
+use crate::common::encryption::{encryption_key_checks, encryption_save_file_checks};
+use crate::common::rekey::queue_password_change;
+use crate::common::unlock::queue_unlock;
 use crate::prelude::HappyChartState;
 use crate::state::error_states::HappyChartError;
+use crate::ui::persistence_screen::draw_persistence_progress;
 use crate::{MAX_ENCRYPT_KEY_LENGTH, MIN_ENCRYPT_KEY_LENGTH};
 use eframe::epaint::Color32;
 use egui::{Context, RichText, TextEdit, Ui};
-use tracing::info;
+use tracing::{error, info};
 
+/// Draw the screen asking for the encryption key of the save file, the save file is unlocked on the persistence worker
 #[tracing::instrument(skip_all)]
-pub fn draw_decryption_screen(
-    ui: &mut Ui,
-    app: &mut HappyChartState,
-    ctx: &Context,
-) -> Result<(), HappyChartError> {
-    let mut save_file_decrypted_successfully: Option<usize> = None;
-    if let Some((index, HappyChartError::EncryptedSaveFile(encrypted_data))) = app
+pub fn draw_decryption_screen(ui: &mut Ui, app: &mut HappyChartState, ctx: &Context) {
+    if !app
         .error_states
         .iter()
-        .enumerate()
-        .find(|(_, err)| matches!(err, HappyChartError::EncryptedSaveFile(_)))
+        .any(|err| matches!(err, HappyChartError::EncryptedSaveFile(_)))
     {
-        ui.label("Encryption key:");
-        let key_input_resp = ui.add(TextEdit::singleline(&mut app.encryption_key).password(true));
-        let unlock_button = ui.button("Unlock");
-
-        // force the user to focus on the password input if they have not put in a key
-        if app.encryption_key.is_empty() {
-            key_input_resp.request_focus();
-        }
-
-        // the user can either click the unlock button, or lose focus on the key text edit object while having a key
-        if unlock_button.clicked()
-            || (!app.encryption_key.is_empty() && key_input_resp.lost_focus())
-        {
-            info!("Unlock button clicked");
-            let decrypted_save = decrypt_save_file(app, encrypted_data)?;
-
-            // set the second key equal to the first key so after the user unlocks the save file, they don't have to re-type their password
-            app.encryption_key_second_check = app.encryption_key.to_string();
+        return;
+    }
 
-            app.days = decrypted_save;
+    let unlocking = app.ui_states.unlocking;
+    ui.label("Encryption key:");
+    let key_input_resp = ui.add_enabled(
+        !unlocking,
+        TextEdit::singleline(&mut app.encryption_key).password(true),
+    );
+    let unlock_button = ui.add_enabled(!unlocking, egui::Button::new("Unlock"));
 
-            save_file_decrypted_successfully = Some(index);
-        }
+    // force the user to focus on the password input if they have not put in a key
+    if app.encryption_key.is_empty() {
+        key_input_resp.request_focus();
     }
 
-    if let Some(_index) = save_file_decrypted_successfully {
-        app.error_states.retain(|err| {
-            !matches!(err, HappyChartError::DecryptionError(_))
-                && !matches!(err, HappyChartError::EncryptedSaveFile(_))
-        });
-        first_load(app, ctx, false);
+    if unlocking {
+        draw_persistence_progress(ui, app);
+        return;
     }
 
-    Ok(())
+    // the user can either click the unlock button, or lose focus on the key text edit object while having a key
+    if unlock_button.clicked() || (!app.encryption_key.is_empty() && key_input_resp.lost_focus()) {
+        info!("Unlock button clicked");
+        queue_unlock(ctx, app);
+    }
 }
 
 #[tracing::instrument(skip_all)]
@@ -124,3 +113,109 @@ pub fn draw_fix_encryption_keys_screen(ui: &mut Ui, app: &mut HappyChartState) {
         ui.label(RichText::new("Encryption keys do not match").color(Color32::LIGHT_RED));
     }
 }
+
+/// Menu for changing the encryption password of the save file and every backup, or decrypting them permanently
+#[tracing::instrument(skip_all)]
+pub fn draw_change_encryption_password_menu(ui: &mut Ui, app: &mut HappyChartState, ctx: &Context) {
+    ui.collapsing("Change encryption password", |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Current encryption key:");
+            ui.add(
+                TextEdit::singleline(&mut app.ui_states.password_change.current_password)
+                    .password(true),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("New encryption key:");
+            ui.add(
+                TextEdit::singleline(&mut app.ui_states.password_change.new_password)
+                    .password(true),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("New encryption key a second time:");
+            ui.add(
+                TextEdit::singleline(&mut app.ui_states.password_change.new_password_check)
+                    .password(true),
+            );
+        });
+
+        let running = app.encryption_change_running;
+        let change_clicked = ui
+            .add_enabled(!running, egui::Button::new("Change password"))
+            .on_hover_text("Re-encrypt the save file and every backup with the new key.")
+            .clicked();
+        let decrypt_clicked = ui
+            .add_enabled(!running, egui::Button::new("Decrypt permanently"))
+            .on_hover_text(
+                "Decrypt the save file and every backup, and turn off save file encryption.",
+            )
+            .clicked();
+
+        if change_clicked || decrypt_clicked {
+            let password_change = app.ui_states.password_change.clone();
+            let new_password = change_clicked.then_some((
+                password_change.new_password.as_str(),
+                password_change.new_password_check.as_str(),
+            ));
+            match queue_password_change(ctx, app, &password_change.current_password, new_password) {
+                Ok(()) => {
+                    info!("Encryption password change queued");
+                    app.ui_states.password_change.status = Some(
+                        if change_clicked {
+                            "Re-encrypting files..."
+                        } else {
+                            "Decrypting files..."
+                        }
+                        .to_string(),
+                    );
+                }
+                Err(err) => {
+                    error!("Error changing encryption password: {}", err);
+                    app.ui_states.password_change.status = None;
+                    app.error_states.push(err);
+                }
+            }
+        }
+
+        if let Some(status) = &app.ui_states.password_change.status {
+            ui.label(status);
+        }
+    });
+}
+
+/// Menu for encrypting backups with a separate key instead of the save file encryption key
+#[tracing::instrument(skip_all)]
+pub fn draw_backup_encryption_key_menu(ui: &mut Ui, app: &mut HappyChartState) {
+    ui.checkbox(
+        &mut app.program_options.separate_backup_key,
+        "Use a separate backup key",
+    )
+    .on_hover_text("Encrypt backups with their own key, which must be entered each session before a backup can be made.");
+
+    if app.program_options.separate_backup_key {
+        ui.horizontal(|ui| {
+            ui.label("Backup key:");
+            ui.add(TextEdit::singleline(&mut app.backup_encryption_key).password(true));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Backup key a second time:");
+            ui.add(
+                TextEdit::singleline(&mut app.backup_encryption_key_second_check).password(true),
+            );
+        });
+        if let Err(err) = encryption_key_checks(
+            &app.backup_encryption_key,
+            &app.backup_encryption_key_second_check,
+        ) {
+            ui.label(
+                RichText::new(format!(
+                    "Backups can not be made until the backup key is valid: {}",
+                    err
+                ))
+                .color(Color32::LIGHT_RED),
+            );
+        }
+        ui.label("Backups made with a separate key are not re-encrypted when the encryption password is changed.");
+    }
+}
@@ -1,13 +1,15 @@
+use crate::common::backup_destination::BackupDestination;
 use crate::options::program_options::ProgramOptions;
 use crate::state::error_states::HappyChartError;
 use crate::state::happy_chart_state::HappyChartState;
 use crate::ui::encryption::draw_fix_encryption_keys_screen;
-use crate::{MAX_ENCRYPT_KEY_LENGTH, MIN_ENCRYPT_KEY_LENGTH};
+use crate::{DEFAULT_PROFILE_NAME, MAX_ENCRYPT_KEY_LENGTH, MIN_ENCRYPT_KEY_LENGTH};
 use egui::Ui;
 use tracing::info;
 
 #[tracing::instrument(skip(ui, app))]
 pub fn draw_error_screen(app: &mut HappyChartState, ui: &mut Ui) {
+    let backup_dir = app.get_backup_dir();
     app.error_states.iter()
         .for_each(|error_state| {
         ui.label(format!("{}", error_state));
@@ -64,23 +66,23 @@ pub fn draw_error_screen(app: &mut HappyChartState, ui: &mut Ui) {
                 ui.label("An error occurred while attempting to save a backup shown above.");
                 ui.horizontal(|ui| {
                     ui.label("Backup path: ");
-                    ui.label(app.program_options.backup_save_path.to_str().unwrap_or_default());
+                    ui.label(backup_dir.to_str().unwrap_or_default());
                 });
 
                 if ui.button("Set new backup path").clicked() {
                     if let Some(path) = rfd::FileDialog::new()
-                        .set_directory("./")
+                        .set_directory(app.data_paths.data_dir())
                         .set_title("Set the location where a backup will be stored")
                         .pick_folder() {
-                        app.program_options.backup_save_path = path;
+                        app.program_options.primary_backup_destination.path = path;
                     }
                 }
 
-                if ui.button("Reset backup path to default").on_hover_text("The default path is a local folder called \'backups\'").clicked() {
-                    app.program_options.backup_save_path = ProgramOptions::default().backup_save_path;
+                if ui.button("Reset backup path to default").on_hover_text("The default path is a folder called \'backups\' inside the data folder").clicked() {
+                    app.program_options.primary_backup_destination.path = BackupDestination::default().path;
                 }
 
-                if app.program_options.backup_save_path
+                if backup_dir
                     .try_exists()
                     .is_ok_and(|exists| exists) {
                     ui.label("Happy chart believes that this path is valid, so its possible that happy chart does not have permission to write and read to that folder.");
@@ -120,6 +122,154 @@ pub fn draw_error_screen(app: &mut HappyChartState, ui: &mut Ui) {
             HappyChartError::EncryptKeyTooLong{ .. } => {
                 ui.label(format!("Your encryption key is too long, you can either disable save file encryption, or remove from its length. The maximum length is {}.", MAX_ENCRYPT_KEY_LENGTH));
             }
+            HappyChartError::CorruptSaveFile { path, corrupt_copy_path, recovered_days, .. } => {
+                ui.horizontal(|ui| {
+                    ui.label("Happy chart was unable to read the save file at this path: ");
+                    ui.label(path.to_str().unwrap_or("UNABLE TO DISPLAY PATH"));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("The unreadable save file was copied to: ");
+                    ui.label(corrupt_copy_path.to_str().unwrap_or("UNABLE TO DISPLAY PATH"));
+                });
+                match recovered_days {
+                    Some(days) => {
+                        ui.label(format!("The previous save was loaded instead, recovering {} journal entries. Entries made in the most recent session may be missing.", days.len()));
+                    }
+                    None => {
+                        ui.label("No previous save could be read either, restoring a backup is recommended before making new entries.");
+                    }
+                }
+            }
+            HappyChartError::SqliteStorage(err, path) => {
+                ui.horizontal(|ui| {
+                    ui.label("Happy chart was unable to use the SQLite database at this path: ");
+                    ui.label(path.to_str().unwrap_or("UNABLE TO DISPLAY PATH"));
+                });
+                ui.label(format!("The full database error is: {}", err));
+                ui.label("Switching back to the JSON file storage in the storage options could be a valid solution.");
+            }
+            HappyChartError::StorageDayNotFound(id) => {
+                ui.label(format!("The journal entry with id {} could not be found in storage, it may have been removed already.", id));
+            }
+            HappyChartError::StorageMigrationMismatch { from, to } => {
+                ui.label(format!("Moving your journal from the {} to the {} did not produce an identical copy, so the {} is still being used.", from, to, from));
+                ui.label("None of your journal entries have been removed.");
+            }
+            HappyChartError::IncorrectEncryptionKey => {
+                ui.label("The current encryption key entered is not the key the save file is encrypted with, nothing was changed.");
+            }
+            HappyChartError::EncryptionChange { path, error } => {
+                ui.horizontal(|ui| {
+                    ui.label("Happy chart was unable to re-encrypt the file at this path: ");
+                    ui.label(path.to_str().unwrap_or("UNABLE TO DISPLAY PATH"));
+                });
+                ui.label(format!("The error was: {}", error));
+                ui.label("Every file was left as it was, and the previous encryption key is still in use.");
+                ui.label("If the file is a backup made with a different key, moving it out of the backup folder will let the encryption key be changed.");
+            }
+            HappyChartError::BackupEncryptionKey(err) => {
+                ui.label("Backups are encrypted with a separate backup key, which has not been entered this session or is invalid, so no backup was made.");
+                ui.label(format!("The problem with the backup key is: {}", err));
+                ui.label("The backup key can be entered in the encryption settings of the options menu.");
+            }
+            HappyChartError::KeyDerivation(err) => {
+                ui.label(format!("Happy chart was unable to derive an encryption key from the password: {}", err));
+                ui.label("The key derivation settings in the encryption settings may be out of range, resetting them to their defaults should fix this.");
+                if ui.button("Reset key derivation settings").clicked() {
+                    app.program_options.key_derivation_settings = ProgramOptions::default().key_derivation_settings;
+                }
+            }
+            HappyChartError::UnsupportedEncryptionHeader(version) => {
+                ui.label(format!("The save file was encrypted using encryption header version {}, which this version of happy chart does not understand.", version));
+                ui.label("Updating happy chart should allow the save file to be decrypted.");
+            }
+            HappyChartError::UnsupportedKeyDerivationSettings(settings) => {
+                ui.label(format!("The encrypted data asks for its key to be derived using {} KiB of memory, {} iterations and a parallelism of {}, which is more than happy chart allows.", settings.memory_kib, settings.iterations, settings.parallelism));
+                ui.label("The data may be damaged, or was not written by happy chart. Nothing was decrypted.");
+            }
+            HappyChartError::UnsupportedSaveFormatVersion { found, supported, app_version } => {
+                ui.label(format!("The save file was written by happy chart version {} using save format version {}, but this version of happy chart only understands save format versions up to {}.", app_version, found, supported));
+                ui.label("Updating happy chart should allow the save file to be read. The save file will not be overwritten by this version of happy chart.");
+            }
+            HappyChartError::EncryptedBackup(path) => {
+                ui.horizontal(|ui| {
+                    ui.label("The backup at this path is encrypted: ");
+                    ui.label(path.to_str().unwrap_or("UNABLE TO DISPLAY PATH"));
+                });
+                ui.label("Enter the encryption key the backup was made with to read it.");
+            }
+            HappyChartError::BackupMissingSaveFile(path) => {
+                ui.horizontal(|ui| {
+                    ui.label("The backup at this path does not contain a save file: ");
+                    ui.label(path.to_str().unwrap_or("UNABLE TO DISPLAY PATH"));
+                });
+                ui.label("The backup may have been made by a different program, or modified after it was made.");
+            }
+            HappyChartError::CorruptBackup { path, problem } => {
+                ui.horizontal(|ui| {
+                    ui.label("The backup at this path failed verification: ");
+                    ui.label(path.to_str().unwrap_or("UNABLE TO DISPLAY PATH"));
+                });
+                ui.label(format!("The problem was: {}", problem));
+                ui.label("The backup may be corrupt or incomplete, and may not restore correctly. Backups made before backup manifests were added will always report a missing manifest.");
+            }
+            HappyChartError::BackupDestination { name, path, error } => {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Happy chart was unable to back up to the destination \"{}\" at this path: ", name));
+                    ui.label(path.to_str().unwrap_or("UNABLE TO DISPLAY PATH"));
+                });
+                ui.label(format!("The error was: {}", error));
+                ui.label("Every other backup destination was still backed up to. If this destination is a removable drive or network share, it may not be connected.");
+            }
+            HappyChartError::StaleBackupDestination { name, path, last_backup, warn_after_days } => {
+                ui.horizontal(|ui| {
+                    ui.label(format!("The backup destination \"{}\" has not received a backup in over {} days: ", name, warn_after_days));
+                    ui.label(path.to_str().unwrap_or("UNABLE TO DISPLAY PATH"));
+                });
+                match last_backup {
+                    None => ui.label("There are no backups in this destination."),
+                    Some(last_backup) => ui.label(format!("The newest backup in this destination was made {}", last_backup)),
+                };
+                ui.label("Check that the destination is connected, and that its auto backup day count is set, or make a manual backup from the backup options.");
+            }
+            HappyChartError::InstanceLocked { path, owner } => {
+                ui.horizontal(|ui| {
+                    ui.label("Another copy of happy chart has this profile open: ");
+                    ui.label(path.to_str().unwrap_or("UNABLE TO DISPLAY PATH"));
+                });
+                match owner {
+                    Some(owner) => ui.label(format!("It is running as process {} on {}, and was opened {}.", owner.pid, owner.hostname, owner.acquired)),
+                    None => ui.label("The lock file could not be read, it may still be being written by the other copy."),
+                };
+                ui.label("This copy is read-only, so neither copy overwrites the entries made in the other.");
+            }
+            HappyChartError::EncryptionChangeRunning => {
+                ui.label("The save file and backups are being re-encrypted with the new password, nothing was saved.");
+                ui.label("The journal is saved with the new password once re-encrypting has finished.");
+            }
+            HappyChartError::ReadOnlySession => {
+                ui.label("This profile is open read-only, as another copy of happy chart has it open, nothing was saved.");
+                ui.label("Close the other copy and restart happy chart to make changes.");
+            }
+            HappyChartError::SaveFileChangedExternally(path) => {
+                ui.label(format!("The save file at {} was changed by another program, such as a file sync tool, so it was not overwritten.", path.display()));
+                ui.label("Nothing is saved over it until you have chosen which version to keep when asked.");
+            }
+            HappyChartError::EncryptedJournal(path) => {
+                ui.label(format!("The journal at {} is encrypted.", path.display()));
+                ui.label("Enter the encryption key it was saved with in the merge screen to read it.");
+            }
+            HappyChartError::UnrecognizedJournal(path) => {
+                ui.label(format!("The file at {} is not a happy chart save file, SQLite database, or backup.", path.display()));
+            }
+            HappyChartError::EntryLocked(date) => {
+                ui.label(format!("The entry from {} is read-only, as it is older than the entry edit policy allows changes to, so it was not added or changed.", date.format("%Y-%m-%d %H:%M")));
+                ui.label("Unlock the entry in the note editor to change it anyway, or change the entry edit policy in the options menu.");
+            }
+            HappyChartError::InvalidProfileName(name) => {
+                ui.label(format!("The profile name \"{}\" can not be used.", name));
+                ui.label(format!("Profile names must not be empty, must not be \"{}\", must not already be in use, and may only contain letters, numbers, spaces, dashes and underscores.", DEFAULT_PROFILE_NAME));
+            }
         }
         ui.separator();
     });
@@ -0,0 +1,159 @@
+use crate::common::external_change::{combine_versions, keep_disk_version, keep_local_version};
+use crate::prelude::{HappyChartState, ImprovedDayStat};
+use egui::{RichText, ScrollArea, Ui};
+use tracing::info;
+use uuid::Uuid;
+
+/// Number of characters of a note shown when comparing two versions of a day
+const NOTE_PREVIEW_LENGTH: usize = 80;
+
+/// Which version of the journal the user chose to keep
+enum VersionChoice {
+    Local,
+    Disk,
+    Combined,
+}
+
+/// Draw the screen shown when another program changed the save file, letting the user choose which version to keep
+#[tracing::instrument(skip_all)]
+pub fn draw_external_change_screen(ui: &mut Ui, app: &mut HappyChartState) {
+    let choice = draw_version_comparison(ui, app);
+    match choice {
+        Some(VersionChoice::Local) => {
+            info!("Keeping local version over save file changed by another program");
+            keep_local_version(app);
+        }
+        Some(VersionChoice::Disk) => {
+            info!("Keeping version of save file changed by another program");
+            keep_disk_version(app);
+        }
+        Some(VersionChoice::Combined) => {
+            info!("Combining local version with save file changed by another program");
+            combine_versions(app);
+        }
+        None => {}
+    }
+}
+
+fn draw_version_comparison(ui: &mut Ui, app: &mut HappyChartState) -> Option<VersionChoice> {
+    let has_unsaved_changes = app.has_unsaved_changes();
+    let mut choice = None;
+    let Some(external_change) = &mut app.external_change else {
+        return None;
+    };
+
+    ui.label("The save file was changed by another program, such as a file sync tool.");
+
+    if !has_unsaved_changes {
+        ui.label(format!(
+            "This window has no unsaved changes, reloading changes the journal of {} days into {} days.",
+            app.days.len(),
+            external_change.days.len()
+        ));
+        ui.horizontal(|ui| {
+            if ui.button("Reload").clicked() {
+                choice = Some(VersionChoice::Disk);
+            }
+            if ui
+                .button("Keep this window's version")
+                .on_hover_text("The save file is overwritten with the journal in this window.")
+                .clicked()
+            {
+                choice = Some(VersionChoice::Local);
+            }
+        });
+        return choice;
+    }
+
+    ui.label("This window also has unsaved changes, keep either version or combine them:");
+    ui.label(format!(
+        "Only on disk: {}, only in this window: {}, different in each: {}",
+        external_change.preview.added.len(),
+        external_change.preview.removed.len(),
+        external_change.preview.changed.len()
+    ));
+
+    if !external_change.preview.changed.is_empty() {
+        ui.separator();
+        ui.label("Days in both versions with different contents, choose which version each keeps when combining:");
+        ScrollArea::vertical()
+            .id_source("external_change_list")
+            .max_height(300.0)
+            .show(ui, |ui| {
+                for changed_day in &external_change.preview.changed {
+                    let id = &changed_day.id;
+                    let local_day = find_day(&app.days, id);
+                    let disk_day = find_day(&external_change.days, id);
+                    ui.label(
+                        RichText::new(changed_day.date.format("%Y-%m-%d %H:%M").to_string())
+                            .strong(),
+                    );
+                    ui.label(format!("This window: {}", describe_day(local_day)));
+                    ui.label(format!("On disk: {}", describe_day(disk_day)));
+                    let mut use_disk_version = external_change.use_disk_version.contains(id);
+                    if ui
+                        .checkbox(&mut use_disk_version, "Use the version on disk")
+                        .changed()
+                    {
+                        if use_disk_version {
+                            external_change.use_disk_version.insert(*id);
+                        } else {
+                            external_change.use_disk_version.remove(id);
+                        }
+                    }
+                    ui.separator();
+                }
+            });
+    }
+
+    ui.horizontal(|ui| {
+        if ui
+            .button("Keep this window's version")
+            .on_hover_text("The save file is overwritten with the journal in this window, changes made on disk are lost.")
+            .clicked()
+        {
+            choice = Some(VersionChoice::Local);
+        }
+        if ui
+            .button("Keep the version on disk")
+            .on_hover_text("The journal in this window is replaced with the one on disk, unsaved changes to days are lost.")
+            .clicked()
+        {
+            choice = Some(VersionChoice::Disk);
+        }
+        if ui
+            .button("Combine both")
+            .on_hover_text("Every day from both versions is kept, days in both use the version chosen above.")
+            .clicked()
+        {
+            choice = Some(VersionChoice::Combined);
+        }
+    });
+    choice
+}
+
+fn find_day<'a>(days: &'a [ImprovedDayStat], id: &Uuid) -> Option<&'a ImprovedDayStat> {
+    days.iter().find(|day| day.get_id() == id)
+}
+
+/// Returns a single line summary of a day, used to tell two versions of it apart
+fn describe_day(day: Option<&ImprovedDayStat>) -> String {
+    let Some(day) = day else {
+        return "missing".to_string();
+    };
+    let mut note = day
+        .get_note()
+        .chars()
+        .take(NOTE_PREVIEW_LENGTH)
+        .collect::<String>();
+    if day.get_note().chars().count() > NOTE_PREVIEW_LENGTH {
+        note.push_str("...");
+    }
+    format!(
+        "rating {:.1}, {} moods, {} activities, note: {}",
+        day.get_rating(),
+        day.get_mood_tags().len(),
+        day.get_activities().len(),
+        note
+    )
+}
@@ -9,7 +9,13 @@ pub fn draw_help_dropdown(ui: &mut Ui, _app: &HappyChartState) {
         help_ui.add_space(8.0);
         help_ui.label("Right click + left click to scale and move the journal graph");
         help_ui.add_space(8.0);
-        help_ui.label("Control + left click a journal entry to change things about the entry");
+        help_ui.label("Control + left click a journal entry to change things about the entry, including its date and time");
+        help_ui.add_space(8.0);
+        help_ui.label("Check Pick date next to the Add day button to add a day you forgot at the date and time it happened");
+        help_ui.add_space(8.0);
+        help_ui.label("Control + Z undoes the last change to the journal, and Control + Shift + Z redoes it, the History button lists the changes made this session");
+        help_ui.add_space(8.0);
+        help_ui.label("Deleted entries go to the trash, the Trash button lists them so they can be restored");
         help_ui.add_space(8.0);
         help_ui.label("Enable save file backups in the settings menu so you can be sure you wont lose your data");
     });
@@ -0,0 +1,61 @@
+use crate::common::history::{redo, undo};
+use crate::prelude::HappyChartState;
+use egui::{Color32, RichText, ScrollArea, Ui};
+use tracing::info;
+
+/// Screen listing the edits made this session, with buttons to undo and redo them
+#[tracing::instrument(skip_all)]
+pub fn draw_history_screen(ui: &mut Ui, app: &mut HappyChartState) {
+    ui.horizontal(|ui| {
+        if ui
+            .add_enabled(app.edit_history.can_undo(), egui::Button::new("Undo"))
+            .on_hover_text("Ctrl+Z")
+            .clicked()
+        {
+            undo(app);
+        }
+        if ui
+            .add_enabled(app.edit_history.can_redo(), egui::Button::new("Redo"))
+            .on_hover_text("Ctrl+Shift+Z")
+            .clicked()
+        {
+            redo(app);
+        }
+    });
+
+    ui.separator();
+
+    if !app.edit_history.can_undo() && !app.edit_history.can_redo() {
+        ui.label("No edits made this session");
+    }
+
+    ScrollArea::vertical()
+        .id_source("edit_history_list")
+        .max_height(300.0)
+        .show(ui, |ui| {
+            // undone edits are listed above the edits they were undone back to, in the order they were made
+            for entry in app.edit_history.get_redo_entries().rev() {
+                ui.label(
+                    RichText::new(format!(
+                        "{} {} (undone)",
+                        entry.time.format("%H:%M:%S"),
+                        entry.command.get_description()
+                    ))
+                    .color(Color32::GRAY),
+                );
+            }
+            for entry in app.edit_history.get_undo_entries() {
+                ui.label(format!(
+                    "{} {}",
+                    entry.time.format("%H:%M:%S"),
+                    entry.command.get_description()
+                ));
+            }
+        });
+
+    ui.separator();
+    if ui.button("Close").clicked() {
+        info!("Edit history screen closed");
+        app.ui_states.showing_history_screen = false;
+    }
+}
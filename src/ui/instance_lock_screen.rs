@@ -0,0 +1,68 @@
+use crate::common::instance_lock::take_over_instance_lock;
+use crate::state::error_states::HappyChartError;
+use crate::state::happy_chart_state::HappyChartState;
+use egui::{Context, Ui, ViewportCommand};
+use std::time::SystemTime;
+use tracing::{error, info};
+
+/// Draw the screen shown when another running copy of happy chart holds the lock on the profile
+#[tracing::instrument(skip_all)]
+pub fn draw_instance_locked_screen(ui: &mut Ui, app: &mut HappyChartState, ctx: &Context) {
+    let Some(HappyChartError::InstanceLocked { path, owner }) = app
+        .error_states
+        .iter()
+        .find(|err| matches!(err, HappyChartError::InstanceLocked { .. }))
+    else {
+        return;
+    };
+
+    ui.label("This profile is already open in another copy of happy chart.");
+    match owner {
+        Some(owner) => ui.label(format!(
+            "Process {} on {}, opened {}, last seen {}",
+            owner.pid, owner.hostname, owner.acquired, owner.heartbeat
+        )),
+        None => ui.label(
+            "The lock file could not be read, it is taken as held until it has not been written for a few minutes.",
+        ),
+    };
+    ui.label(format!("Lock file: {}", path.display()));
+    ui.label("Changes made here can not be saved while the other copy has the profile open.");
+    ui.separator();
+
+    ui.horizontal(|ui| {
+        if ui.button("Continue read-only").clicked() {
+            info!("Continuing read-only");
+            app.error_states
+                .retain(|err| !matches!(err, HappyChartError::InstanceLocked { .. }));
+        }
+
+        if ui
+            .button("Open anyway")
+            .on_hover_text("Only do this if the other copy is no longer running, for example after a crash on another computer sharing this data folder, whichever copy saves last overwrites the other.")
+            .clicked()
+        {
+            match take_over_instance_lock(&app.data_paths) {
+                Ok(()) => {
+                    app.instance_lock_held = true;
+                    app.last_lock_heartbeat = SystemTime::now();
+                    app.read_only = false;
+                    app.error_states.retain(|err| {
+                        !matches!(
+                            err,
+                            HappyChartError::InstanceLocked { .. } | HappyChartError::ReadOnlySession
+                        )
+                    });
+                }
+                Err(err) => {
+                    error!("Unable to take over instance lock: {}", err);
+                    app.error_states.push(err);
+                }
+            }
+        }
+
+        if ui.button("Quit").clicked() {
+            ctx.send_viewport_cmd(ViewportCommand::Close);
+        }
+    });
+}
@@ -0,0 +1,231 @@
+use crate::common::encryption::SaveCipher;
+use crate::common::merge::{
+    apply_merge, queue_journal_read, MergeConflict, MergeConflictKind, MergeResolution,
+};
+use crate::prelude::{HappyChartState, ImprovedDayStat};
+use crate::state::happy_chart_state::MergeUIState;
+use egui::{Context, RichText, ScrollArea, TextEdit, Ui};
+use std::collections::HashSet;
+use tracing::error;
+use uuid::Uuid;
+
+/// Screen for merging another journal into the current one, conflicting days are shown side by side for the user to choose between
+#[tracing::instrument(skip_all)]
+pub fn draw_merge_screen(ui: &mut Ui, app: &mut HappyChartState, ctx: &Context) {
+    ui.horizontal(|ui| {
+        ui.label("Encryption key:");
+        ui.add(TextEdit::singleline(&mut app.ui_states.merge_ui_state.encryption_key).password(true))
+            .on_hover_text("The key the journal to merge was encrypted with, leave empty to use the encryption key currently in use.");
+    });
+
+    if ui
+        .add_enabled(
+            !app.ui_states.merge_ui_state.reading,
+            egui::Button::new("Choose journal to merge"),
+        )
+        .on_hover_text("A save file from any version of happy chart, plain or encrypted, a SQLite save database, or a backup archive.")
+        .clicked()
+    {
+        if let Some(path) = rfd::FileDialog::new().pick_file() {
+            let cipher = get_merge_cipher(app);
+            queue_journal_read(ctx, app, path, cipher);
+        }
+    }
+    if app.ui_states.merge_ui_state.reading {
+        ui.label("Reading journal...");
+    }
+
+    // days the entry edit policy has made read-only can only be kept as they are, unless unlocked
+    let locked_days = app
+        .ui_states
+        .merge_ui_state
+        .merge
+        .as_ref()
+        .map(|merge| {
+            merge
+                .conflicts
+                .iter()
+                .filter(|conflict| app.is_entry_locked(&conflict.current))
+                .map(|conflict| *conflict.current.get_id())
+                .collect::<HashSet<Uuid>>()
+        })
+        .unwrap_or_default();
+    let mut unlocked_days = vec![];
+
+    let mut apply = false;
+    let mut cancel = false;
+    if let Some(merge) = &mut app.ui_states.merge_ui_state.merge {
+        ui.separator();
+        if let Some(source) = &app.ui_states.merge_ui_state.source {
+            ui.label(format!("Merging from {}", source));
+        }
+        ui.label(format!(
+            "Identical days: {}, days only in the merged journal: {}, conflicts: {}",
+            merge.identical_count,
+            merge.added_count,
+            merge.conflicts.len()
+        ));
+
+        if !merge.conflicts.is_empty() {
+            ui.separator();
+            ScrollArea::vertical()
+                .id_source("merge_conflict_list")
+                .max_height(400.0)
+                .show(ui, |ui| {
+                    for conflict in &mut merge.conflicts {
+                        let locked = locked_days.contains(conflict.current.get_id());
+                        if draw_merge_conflict(ui, conflict, locked) {
+                            unlocked_days.push(conflict.current.clone());
+                        }
+                        ui.separator();
+                    }
+                });
+        }
+
+        ui.horizontal(|ui| {
+            if ui
+                .button("Merge")
+                .on_hover_text("Replace the journal with the merged journal.")
+                .clicked()
+            {
+                apply = true;
+            }
+            if ui.button("Cancel merge").clicked() {
+                cancel = true;
+            }
+        });
+    }
+
+    for day in unlocked_days {
+        app.override_entry_lock(&day, "unlocked while merging a journal");
+        // the current day now has the override in its revision history, which the merge must not drop
+        if let (Some(merge), Some(stored_day)) = (
+            &mut app.ui_states.merge_ui_state.merge,
+            app.days
+                .iter()
+                .find(|stored_day| stored_day.get_id() == day.get_id()),
+        ) {
+            for conflict in &mut merge.conflicts {
+                if conflict.current.get_id() == stored_day.get_id() {
+                    conflict.current = stored_day.clone();
+                }
+            }
+        }
+    }
+
+    if apply {
+        if let Some(merge) = app.ui_states.merge_ui_state.merge.take() {
+            let day_count = app.days.len();
+            match apply_merge(app, &merge) {
+                Ok(()) => {
+                    app.ui_states.merge_ui_state.status = Some(format!(
+                        "Merged journal of {} days into {} days",
+                        day_count,
+                        app.days.len()
+                    ));
+                }
+                Err(err) => {
+                    error!("Error applying merge: {}", err);
+                    app.error_states.push(err);
+                    app.ui_states.merge_ui_state.merge = Some(merge);
+                }
+            }
+        }
+    }
+    if cancel {
+        app.ui_states.merge_ui_state.merge = None;
+        app.ui_states.merge_ui_state.source = None;
+    }
+
+    if let Some(status) = &app.ui_states.merge_ui_state.status {
+        ui.separator();
+        ui.label(status);
+    }
+
+    ui.separator();
+    if ui.button("Close").clicked() {
+        app.ui_states.showing_merge_screen = false;
+        app.ui_states.merge_ui_state = MergeUIState::default();
+    }
+}
+
+/// Draws both days of a conflict side by side, with the choice of which to keep.
+/// A current day that is read-only can only be kept, returns true if the user chose to unlock it.
+fn draw_merge_conflict(ui: &mut Ui, conflict: &mut MergeConflict, locked: bool) -> bool {
+    let mut unlock = false;
+    ui.label(match conflict.kind {
+        MergeConflictKind::Edited => "Edited in one of the journals:",
+        MergeConflictKind::NearDuplicate => "Possibly the same day logged in both journals:",
+    });
+    ui.columns(2, |columns| {
+        columns[0].label(RichText::new("Current journal").strong());
+        draw_merge_day(&mut columns[0], &conflict.current);
+        columns[1].label(RichText::new("Merged journal").strong());
+        draw_merge_day(&mut columns[1], &conflict.incoming);
+    });
+    ui.horizontal(|ui| {
+        ui.radio_value(
+            &mut conflict.resolution,
+            MergeResolution::Current,
+            "Keep current",
+        );
+        ui.add_enabled_ui(!locked, |ui| {
+            ui.radio_value(
+                &mut conflict.resolution,
+                MergeResolution::Incoming,
+                "Keep merged",
+            );
+        });
+        if conflict.kind == MergeConflictKind::NearDuplicate {
+            ui.radio_value(&mut conflict.resolution, MergeResolution::Both, "Keep both");
+        }
+        if locked
+            && ui
+                .button("Unlock")
+                .on_hover_text("The current day is read-only under the entry edit policy. Unlocking it allows it to be replaced until happy chart is closed, the override is logged.")
+                .clicked()
+        {
+            unlock = true;
+        }
+    });
+    unlock
+}
+
+fn draw_merge_day(ui: &mut Ui, day: &ImprovedDayStat) {
+    ui.label(day.get_date().format("%Y-%m-%d %H:%M:%S").to_string());
+    ui.label(format!("Rating: {:.1}", day.get_rating()));
+    if !day.get_mood_tags().is_empty() {
+        ui.label(format!(
+            "Moods: {}",
+            day.get_mood_tags()
+                .iter()
+                .map(|mood| mood.get_text())
+                .collect::<Vec<String>>()
+                .join(", ")
+        ));
+    }
+    if !day.get_activities().is_empty() {
+        ui.label(format!(
+            "Activities: {}",
+            day.get_activities()
+                .iter()
+                .map(|activity| activity.get_activity_name())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        ));
+    }
+    ui.label(format!("Note: {}", day.get_note()));
+}
+
+/// Returns the cipher used to read an encrypted journal, the entered key if there is one, otherwise the save encryption key in use
+fn get_merge_cipher(app: &HappyChartState) -> Option<SaveCipher> {
+    let encryption_key = &app.ui_states.merge_ui_state.encryption_key;
+    if encryption_key.is_empty() {
+        app.get_save_cipher()
+    } else {
+        Some(SaveCipher::new(
+            encryption_key,
+            app.program_options.key_derivation_settings,
+        ))
+    }
+}
@@ -1,12 +1,22 @@
 pub mod about_screen;
 pub mod activity_screen;
 pub mod central_screen;
+pub mod data_migration_screen;
+pub mod date_time_picker;
 pub mod encryption;
 pub mod error_screen;
+pub mod external_change_screen;
 pub mod help_screen;
+pub mod history_screen;
+pub mod instance_lock_screen;
+pub mod merge_screen;
 pub mod mood_selector_menu;
 pub mod note_edit_screen;
 pub mod options_menu;
+pub mod persistence_screen;
+pub mod profile_screen;
+pub mod restore_screen;
 pub mod statistics_screen;
+pub mod trash_screen;
 pub mod tutorial_screen;
 pub mod user_prompt_screens;
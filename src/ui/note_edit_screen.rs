@@ -1,45 +1,251 @@
-use crate::prelude::HappyChartState;
-use crate::NOTE_OLD_NUM_DAYS;
-use chrono::Local;
-use egui::{Color32, RichText, Ui};
+use crate::common::history::{execute_command, DayEditKind, JournalCommand};
+use crate::common::text_diff::DiffLine;
+use crate::options::entry_edit_policy::EntryEditState;
+use crate::prelude::{HappyChartState, ImprovedDayStat};
+use crate::state::happy_chart_state::{NoteDiff, NoteDraft};
+use crate::ui::date_time_picker::draw_date_time_picker;
+use egui::{Color32, RichText, ScrollArea, Ui};
 use tracing::info;
 
 #[tracing::instrument(skip_all)]
 pub fn draw_note_edit_screen(ui: &mut Ui, app: &mut HappyChartState) {
-    if let Some(index) = app.note_edit_selected {
-        if let Some(note) = app.days.get_mut(index) {
-            ui.label(note.to_string());
-            ui.separator();
-            if note
-                .get_date()
-                .signed_duration_since(Local::now())
-                .num_days()
-                .abs()
-                > i64::from(NOTE_OLD_NUM_DAYS)
-            {
-                ui.label(RichText::new(format!("This note is older than {} days, it is not recommended to edit old notes as your memory of them may not be representative.", NOTE_OLD_NUM_DAYS)).color(Color32::LIGHT_RED));
+    // edits are made to a copy of the day, and applied as a command so they can be undone
+    let mut edit = None;
+    if let Some(note) = app
+        .note_edit_selected
+        .and_then(|id| app.get_day(&id))
+        .cloned()
+    {
+        ui.label(note.to_string());
+        ui.separator();
+        let policy_days = app.program_options.entry_edit_policy_days;
+        let locked = app.is_entry_locked(&note);
+        match app.program_options.get_entry_edit_state(&note) {
+            EntryEditState::Editable => {}
+            EntryEditState::Warn => {
+                ui.label(RichText::new(format!("This note is older than {} days, it is not recommended to edit old notes as your memory of them may not be representative. The original values are kept in the revision history.", policy_days)).color(Color32::LIGHT_RED));
             }
+            EntryEditState::Locked if locked => {
+                ui.label(
+                    RichText::new(format!(
+                        "This note is older than {} days, so it is read-only.",
+                        policy_days
+                    ))
+                    .color(Color32::LIGHT_RED),
+                );
+                if ui
+                    .button("Unlock for editing")
+                    .on_hover_text("Allows this entry to be changed until happy chart is closed, going against the entry edit policy. The override is logged.")
+                    .clicked()
+                {
+                    app.override_entry_lock(&note, "unlocked in the note editor");
+                }
+            }
+            EntryEditState::Locked => {
+                ui.label(RichText::new(format!("This note is older than {} days, it has been unlocked for editing until happy chart is closed.", policy_days)).color(Color32::LIGHT_RED));
+            }
+        }
 
+        // the draft is kept while the same day is edited, unless the note was changed elsewhere, such as by an undo, before the draft was modified
+        let mut draft = app
+            .ui_states
+            .note_draft
+            .take()
+            .filter(|draft| {
+                draft.day == *note.get_id()
+                    && (draft.is_modified() || draft.original == note.get_note())
+            })
+            .unwrap_or_else(|| NoteDraft::new(&note));
+
+        let mut edited = note.clone();
+        ui.add_enabled_ui(!locked, |ui| {
             ui.horizontal(|ui| {
                 ui.label("Rating:");
-                ui.add(egui::Slider::new(note.modify_rating(), 0.0..=100.0));
+                let mut rating = note.get_rating();
+                if ui
+                    .add(egui::Slider::new(&mut rating, 0.0..=100.0))
+                    .changed()
+                {
+                    edited.edit_rating(rating);
+                    edit = Some(DayEditKind::Rating);
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Date:");
+                if let Some(date) = draw_date_time_picker(ui, note.get_date()) {
+                    edited.edit_date(app.get_unique_entry_time(date, Some(note.get_id())));
+                    edit = Some(DayEditKind::Date);
+                }
             });
             ui.add_space(8.0);
             if ui.button("Set selected moods").on_hover_text("Sets moods that are currently selected from the mood selection screen to this day stat").clicked() {
                 info!("Moods modified on day stat");
-                *note.get_moods_mut() = app.mood_selection_list.clone();
+                edited.edit_moods(app.mood_selection_list.clone());
+                edit = Some(DayEditKind::Moods);
             }
             ui.add_space(8.0);
             if ui.button("Set selected activities").on_hover_text("Sets activities that are currently selected from the activities selection screen to this day stat").clicked() {
                 info!("Activities modified on day stat");
-                *note.get_activities_mut() = app.ui_states.activity_ui_state.added_activity_list.get_activity_list().clone();
+                edited.edit_activities(app.ui_states.activity_ui_state.added_activity_list.get_activity_list().clone());
+                edit = Some(DayEditKind::Activities);
             }
+            ui.add_space(8.0);
+            ui.label("Note:");
+            ui.add(
+                egui::TextEdit::multiline(&mut draft.text)
+                    .desired_rows(4)
+                    .desired_width(f32::INFINITY),
+            );
+            ui.horizontal(|ui| {
+                let changed = draft.text != note.get_note();
+                if ui
+                    .add_enabled(changed, egui::Button::new("Save note"))
+                    .on_hover_text("Saves the note to this day stat, the previous note is kept in the revision history")
+                    .clicked()
+                {
+                    info!("Note modified on day stat");
+                    edited.edit_note(&draft.text);
+                    edit = Some(DayEditKind::Note);
+                    draft.original = draft.text.clone();
+                }
+                if ui
+                    .add_enabled(changed, egui::Button::new("Discard changes"))
+                    .clicked()
+                {
+                    draft = NoteDraft::new(&note);
+                }
+                ui.checkbox(&mut app.ui_states.showing_note_diff, "Show changes");
+            });
+            if app.ui_states.showing_note_diff && draft.text != note.get_note() {
+                let note_diff = match app.ui_states.note_diff.take() {
+                    Some(note_diff) if note_diff.is_for(note.get_note(), &draft.text) => note_diff,
+                    _ => NoteDiff::new(note.get_note(), &draft.text),
+                };
+                draw_note_diff(ui, &note_diff);
+                app.ui_states.note_diff = Some(note_diff);
+            }
+        });
+        app.ui_states.note_draft = Some(draft);
+
+        if let Some(revision_count) = draw_revision_history(ui, &note, locked) {
+            info!("Day reverted to revision {}", revision_count);
+            edited.revert_to_revision(revision_count, |date| {
+                app.get_unique_entry_time(date, Some(note.get_id()))
+            });
+            edit = Some(DayEditKind::Revert);
+        }
+
+        ui.add_space(8.0);
+        if ui
+            .add_enabled(!locked, egui::Button::new("Delete entry"))
+            .on_hover_text(
+                "Moves this day to the trash, where it can be restored from until it is purged.",
+            )
+            .clicked()
+        {
+            info!("Day deleted from the note editor");
+            execute_command(app, JournalCommand::TrashDay(note.clone()));
+        }
+
+        if let Some(kind) = edit {
+            execute_command(
+                app,
+                JournalCommand::EditDay {
+                    kind,
+                    before: note,
+                    after: edited,
+                },
+            );
         }
     }
 
     ui.separator();
-    if ui.button("Close edit screen").clicked() {
+    if ui
+        .button("Close edit screen")
+        .on_hover_text("Unsaved changes to the note are discarded")
+        .clicked()
+    {
         info!("Edit note screen closed");
         app.note_edit_selected = None;
+        app.ui_states.note_draft = None;
+        app.ui_states.note_diff = None;
     }
 }
+
+/// Shows the lines of the note that the unsaved edit removes and adds
+fn draw_note_diff(ui: &mut Ui, note_diff: &NoteDiff) {
+    ScrollArea::vertical()
+        .id_source("note_diff")
+        .max_height(150.0)
+        .show(ui, |ui| {
+            for line in &note_diff.lines {
+                match line {
+                    DiffLine::Unchanged(line) => {
+                        ui.label(RichText::new(format!("  {}", line)).color(Color32::GRAY));
+                    }
+                    DiffLine::Removed(line) => {
+                        ui.label(RichText::new(format!("- {}", line)).color(Color32::LIGHT_RED));
+                    }
+                    DiffLine::Added(line) => {
+                        ui.label(RichText::new(format!("+ {}", line)).color(Color32::LIGHT_GREEN));
+                    }
+                }
+            }
+        });
+}
+
+/// Lists the edits made to a day, returns the number of revisions to revert the day to if a revert button was clicked.
+/// The revert buttons are disabled while the day is read-only.
+fn draw_revision_history(ui: &mut Ui, day: &ImprovedDayStat, locked: bool) -> Option<usize> {
+    let mut revert_to = None;
+    ui.add_space(8.0);
+    ui.collapsing(
+        format!("Revision history ({})", day.get_revisions().len()),
+        |ui| {
+            if day.get_revisions().is_empty() {
+                ui.label("This day has not been edited");
+                return;
+            }
+            ScrollArea::vertical()
+                .id_source("revision_history_list")
+                .max_height(200.0)
+                .show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "{} Added",
+                            day.get_date().format("%Y-%m-%d %H:%M:%S")
+                        ));
+                        if ui
+                            .add_enabled(!locked, egui::Button::new("Revert to this"))
+                            .on_hover_text("Restores the values this day was added with")
+                            .clicked()
+                        {
+                            revert_to = Some(0);
+                        }
+                    });
+                    let revision_count = day.get_revisions().len();
+                    for (index, revision) in day.get_revisions().iter().enumerate() {
+                        ui.horizontal(|ui| {
+                            ui.label(format!(
+                                "{} {}",
+                                revision.time.format("%Y-%m-%d %H:%M:%S"),
+                                revision.change.get_description()
+                            ));
+                            // the newest revision is the current state of the day
+                            if index + 1 < revision_count
+                                && ui
+                                    .add_enabled(!locked, egui::Button::new("Revert to this"))
+                                    .on_hover_text(
+                                        "Restores the values this day had after this edit",
+                                    )
+                                    .clicked()
+                            {
+                                revert_to = Some(index + 1);
+                            }
+                        });
+                    }
+                });
+        },
+    );
+    revert_to
+}
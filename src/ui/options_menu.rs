@@ -2,15 +2,33 @@ use chrono::Local;
 use eframe::epaint::Color32;
 use egui::{Context, RichText, Ui};
 use self_update::Status;
-use tracing::error;
+use strum::IntoEnumIterator;
+use tracing::{error, info};
 
 use crate::common::auto_update_status::AutoUpdateStatus;
-use crate::common::backup::backup_program_state;
+use crate::common::backup_destination::BackupDestination;
+use crate::common::backup_retention::BackupRetentionPolicy;
+use crate::common::data_paths::DataPaths;
+use crate::common::encryption::encryption_save_file_checks;
+use crate::common::persistence::{queue_backup, PersistenceJob};
+use crate::common::profiles::{create_profile, write_profile_settings};
+use crate::common::sync::queue_sync;
 use crate::common::toggle_ui_compact;
 use crate::options::color_setting::ColorSettings;
+use crate::options::entry_edit_policy::EntryEditPolicy;
 use crate::options::program_options::ProgramOptions;
 use crate::state::happy_chart_state::HappyChartState;
-use crate::ui::encryption::draw_fix_encryption_keys_screen;
+use crate::storage::{queue_storage_migration, StorageBackendKind};
+use crate::ui::encryption::{
+    draw_backup_encryption_key_menu, draw_change_encryption_password_menu,
+    draw_fix_encryption_keys_screen,
+};
+use crate::ui::persistence_screen::draw_persistence_progress;
+use crate::ui::profile_screen::draw_profile_list;
+use crate::{
+    DEFAULT_PROFILE_NAME, MAX_KEY_DERIVATION_ITERATIONS, MAX_KEY_DERIVATION_MEMORY_KIB,
+    MAX_KEY_DERIVATION_PARALLELISM,
+};
 
 /// Draw an indicator in the options menu for if an update is taking place, or needed
 #[tracing::instrument(skip(options_panel_ui, app))]
@@ -172,17 +190,51 @@ pub fn draw_graphing_options_menu(
 }
 
 #[tracing::instrument(skip_all)]
-pub fn draw_encryption_settings_menu(options_panel_ui: &mut Ui, app: &mut HappyChartState) {
+pub fn draw_encryption_settings_menu(
+    options_panel_ui: &mut Ui,
+    app: &mut HappyChartState,
+    ctx: &Context,
+) {
     options_panel_ui.collapsing("Encryption Settings",|collapsing_encryption_settings| {
-        collapsing_encryption_settings.checkbox(
-            &mut app.program_options.encrypt_save_file,
-            "Encrypt save file:",
-        ).on_hover_text("It is strongly recommended to enable regular save file backups if encryption is enabled,\
-                 it is also recommended to write down or otherwise store the encryption password,\
-                  as there is no way to recover a save file otherwise.");
+        // once a valid key is set, encryption can only be turned off by decrypting the save file and backups permanently
+        let keys_valid = encryption_save_file_checks(app).is_ok();
+        if !app.program_options.encrypt_save_file || !keys_valid {
+            collapsing_encryption_settings.checkbox(
+                &mut app.program_options.encrypt_save_file,
+                "Encrypt save file:",
+            ).on_hover_text("It is strongly recommended to enable regular save file backups if encryption is enabled,\
+                     it is also recommended to write down or otherwise store the encryption password,\
+                      as there is no way to recover a save file otherwise.");
+        }
 
         if app.program_options.encrypt_save_file {
-            draw_fix_encryption_keys_screen(collapsing_encryption_settings, app);
+            if keys_valid {
+                draw_change_encryption_password_menu(collapsing_encryption_settings, app, ctx);
+                draw_backup_encryption_key_menu(collapsing_encryption_settings, app);
+            } else {
+                draw_fix_encryption_keys_screen(collapsing_encryption_settings, app);
+            }
+            collapsing_encryption_settings.collapsing("Key derivation settings", |key_derivation_ui| {
+                let settings = &mut app.program_options.key_derivation_settings;
+                key_derivation_ui.horizontal(|key_derivation_ui| {
+                    key_derivation_ui.label("Memory (KiB): ");
+                    key_derivation_ui.add(egui::DragValue::new(&mut settings.memory_kib).clamp_range(8 * 1024..=MAX_KEY_DERIVATION_MEMORY_KIB).speed(256))
+                        .on_hover_text("Memory used while turning the password into an encryption key, higher values make guessing the password slower.");
+                });
+                key_derivation_ui.horizontal(|key_derivation_ui| {
+                    key_derivation_ui.label("Iterations: ");
+                    key_derivation_ui.add(egui::DragValue::new(&mut settings.iterations).clamp_range(1..=MAX_KEY_DERIVATION_ITERATIONS))
+                        .on_hover_text("Number of passes made over the memory, higher values make guessing the password slower.");
+                });
+                key_derivation_ui.horizontal(|key_derivation_ui| {
+                    key_derivation_ui.label("Parallelism: ");
+                    key_derivation_ui.add(egui::DragValue::new(&mut settings.parallelism).clamp_range(1..=MAX_KEY_DERIVATION_PARALLELISM));
+                });
+                key_derivation_ui.label("Changes apply the next time the save file is written, higher settings make saving and unlocking slower.");
+                if key_derivation_ui.button("Reset key derivation settings").clicked() {
+                    app.program_options.key_derivation_settings = ProgramOptions::default().key_derivation_settings;
+                }
+            });
             collapsing_encryption_settings.label(RichText::new("There is no way to recover a save file that is encrypted if the password is lost apart from a backup.")
                 .color(Color32::LIGHT_RED));
         }
@@ -255,50 +307,338 @@ pub fn draw_backup_settings_options_menu(
     ctx: &Context,
 ) {
     options_panel_ui.collapsing("Backup options", |options_panel_ui| {
-        options_panel_ui.horizontal(|options_panel_ui| {
-            options_panel_ui.label("Backup folder ");
-            if options_panel_ui.button("Browse path").on_hover_text(format!("Current backup folder: {:?}", app.program_options.backup_save_path.clone().into_os_string())).clicked() {
-                if let Some(path) = rfd::FileDialog::new()
-                    .set_directory("./")
-                    .set_title("Set the location where a backup will be stored")
-                    .pick_folder() {
-                    app.program_options.backup_save_path = path;
-                }
+        draw_backup_destination_settings(options_panel_ui, &mut app.program_options.primary_backup_destination, &app.data_paths, true);
+
+        let mut removed_destination = None;
+        for (index, destination) in app.program_options.additional_backup_destinations.iter_mut().enumerate() {
+            egui::CollapsingHeader::new(format!("Backup destination: {}", destination.name))
+                .id_source(("backup_destination", index))
+                .show(options_panel_ui, |options_panel_ui| {
+                    draw_backup_destination_settings(options_panel_ui, destination, &app.data_paths, false);
+                    if options_panel_ui.button("Remove destination").on_hover_text("Stop backing up to this folder, backups already in it are left as they are.").clicked() {
+                        removed_destination = Some(index);
+                    }
+                });
+        }
+        if let Some(index) = removed_destination {
+            app.program_options.additional_backup_destinations.remove(index);
+        }
+
+        if options_panel_ui.button("Add backup destination").on_hover_text("Mirror backups to another folder, such as a USB drive or network share.").clicked() {
+            if let Some(path) = rfd::FileDialog::new()
+                .set_directory(app.data_paths.data_dir())
+                .set_title("Set the location where a backup will be stored")
+                .pick_folder() {
+                let name = format!("Destination {}", app.program_options.additional_backup_destinations.len() + 2);
+                app.program_options.additional_backup_destinations.push(BackupDestination {
+                    name,
+                    path,
+                    ..BackupDestination::default()
+                });
             }
+        }
 
-        });
+        if options_panel_ui.button("Preview backup removal").on_hover_text("List the backups the retention settings would remove, without removing anything. Backups are removed when happy chart starts.").clicked() {
+            app.ui_states.backup_prune_preview = Some(app.get_backups_to_prune(Local::now()));
+        }
 
-        if options_panel_ui.button("Reset backup path").clicked() {
-            app.program_options.backup_save_path = ProgramOptions::default().backup_save_path;
+        if let Some(prune_preview) = &app.ui_states.backup_prune_preview {
+            if prune_preview.is_empty() {
+                options_panel_ui.label("No backups would be removed");
+            } else {
+                options_panel_ui.collapsing(format!("{} backups would be removed", prune_preview.len()), |options_panel_ui| {
+                    for backup in prune_preview {
+                        options_panel_ui.label(backup.path.to_str().unwrap_or("UNABLE TO DISPLAY PATH"));
+                    }
+                });
+            }
         }
 
-        options_panel_ui.horizontal(|options_panel_ui| {
-            options_panel_ui.label("Auto backup day count: ");
-            options_panel_ui.add(
-                egui::DragValue::new(&mut app.program_options.auto_backup_days)
-            ).on_hover_text("The number of days to elapse between auto backups, if less than 0, no automatic backups will take place.");
-        });
+        if options_panel_ui.button("Backup program state").on_hover_text("Compress the save state and the last session data into a zip file titled with the current date, in every backup destination.").clicked() {
+            if let Err(err) = queue_backup(ctx, app, true) {
+                error!("Error backing up program state: {}",err);
+                app.error_states.push(err);
+            }
+        }
+
+        if options_panel_ui.button("Verify backups").on_hover_text("Check every backup in every backup destination against the checksums stored in it, and report any that are corrupt or incomplete.").clicked() {
+            app.ui_states.backup_verification_status = Some("Verifying backups...".to_string());
+            let backup_dirs = app.get_backup_dirs();
+            app.persistence.queue(ctx, PersistenceJob::VerifyBackups(backup_dirs));
+        }
+
+        if let Some(status) = &app.ui_states.backup_verification_status {
+            options_panel_ui.label(status);
+        }
+
+        if options_panel_ui.button("Restore from backup").on_hover_text("Preview and restore the journal from one of the backups in any backup destination.").clicked() {
+            app.ui_states.showing_restore_screen = true;
+        }
+    });
+}
+
+/// Folder, schedule, and retention settings of a single backup destination
+fn draw_backup_destination_settings(
+    options_panel_ui: &mut Ui,
+    destination: &mut BackupDestination,
+    data_paths: &DataPaths,
+    is_primary: bool,
+) {
+    options_panel_ui.horizontal(|options_panel_ui| {
+        options_panel_ui.label("Destination name: ");
+        options_panel_ui.text_edit_singleline(&mut destination.name);
+    });
+
+    options_panel_ui.horizontal(|options_panel_ui| {
+        options_panel_ui.label("Backup folder ");
+        if options_panel_ui
+            .button("Browse path")
+            .on_hover_text(format!(
+                "Current backup folder: {:?}",
+                data_paths.resolve(&destination.path).into_os_string()
+            ))
+            .clicked()
+        {
+            if let Some(path) = rfd::FileDialog::new()
+                .set_directory(data_paths.data_dir())
+                .set_title("Set the location where a backup will be stored")
+                .pick_folder()
+            {
+                destination.path = path;
+            }
+        }
+    });
+
+    if is_primary && options_panel_ui.button("Reset backup path").clicked() {
+        destination.path = BackupDestination::default().path;
+    }
+
+    options_panel_ui.horizontal(|options_panel_ui| {
+        options_panel_ui.label("Auto backup day count: ");
+        options_panel_ui.add(
+            egui::DragValue::new(&mut destination.auto_backup_days)
+        ).on_hover_text("The number of days to elapse between auto backups, if less than 0, no automatic backups will take place.");
+    });
+
+    options_panel_ui.horizontal(|options_panel_ui| {
+        options_panel_ui.label("Warn after days without a backup: ");
+        options_panel_ui.add(
+            egui::DragValue::new(&mut destination.warn_after_days)
+        ).on_hover_text("Show a warning when happy chart starts if this folder has not received a backup in this many days, -1 for disabled.");
+    });
+
+    options_panel_ui.horizontal(|options_panel_ui| {
+        options_panel_ui.label("Backup retention: ");
+        for policy in BackupRetentionPolicy::iter() {
+            options_panel_ui.radio_value(&mut destination.backup_retention_policy, policy, policy.to_string());
+        }
+    }).response.on_hover_text("How old automatic backups are removed, manual backups and backups taken before restoring are never removed.");
+
+    match destination.backup_retention_policy {
+        BackupRetentionPolicy::AgeAndCount => {
+            options_panel_ui.horizontal(|options_panel_ui| {
+                options_panel_ui.label("Backup age before removal: ");
+                options_panel_ui
+                    .add(egui::DragValue::new(&mut destination.backup_age_keep_days))
+                    .on_hover_text(
+                        "The number of days to elapse before deleting a backup, < 0 = never remove",
+                    );
+            });
+
+            options_panel_ui.horizontal(|options_panel_ui| {
+                options_panel_ui.label("Number of stale backups before removal: ");
+                options_panel_ui.add(
+                    egui::DragValue::new(&mut destination.number_of_kept_backups)
+                ).on_hover_text("The minimum number of stale backups needed to be present in the backups folder before the program will remove any, -1 for disabled.");
+            });
+        }
+        BackupRetentionPolicy::GrandfatherFatherSon => {
+            let counts = &mut destination.gfs_retention_counts;
+            options_panel_ui.horizontal(|options_panel_ui| {
+                options_panel_ui.label("Daily backups kept: ");
+                options_panel_ui
+                    .add(egui::DragValue::new(&mut counts.daily))
+                    .on_hover_text(
+                        "The newest backup of each of this many most recent days is kept.",
+                    );
+            });
+            options_panel_ui.horizontal(|options_panel_ui| {
+                options_panel_ui.label("Weekly backups kept: ");
+                options_panel_ui
+                    .add(egui::DragValue::new(&mut counts.weekly))
+                    .on_hover_text(
+                        "The newest backup of each of this many most recent weeks is kept.",
+                    );
+            });
+            options_panel_ui.horizontal(|options_panel_ui| {
+                options_panel_ui.label("Monthly backups kept: ");
+                options_panel_ui
+                    .add(egui::DragValue::new(&mut counts.monthly))
+                    .on_hover_text(
+                        "The newest backup of each of this many most recent months is kept.",
+                    );
+            });
+        }
+    }
+}
 
+/// Storage options collapsing menu
+#[tracing::instrument(skip(options_panel_ui, app, ctx))]
+pub fn draw_storage_options_menu(
+    options_panel_ui: &mut Ui,
+    app: &mut HappyChartState,
+    ctx: &Context,
+) {
+    options_panel_ui.collapsing("Storage options", |options_panel_ui| {
         options_panel_ui.horizontal(|options_panel_ui| {
-            options_panel_ui.label("Backup age before removal: ");
-            options_panel_ui.add(
-                egui::DragValue::new(&mut app.program_options.backup_age_keep_days)
-            ).on_hover_text("The number of days to elapse before deleting a backup, < 0 = never remove");
+            options_panel_ui.label("Autosave after seconds without changes: ");
+            options_panel_ui
+                .add(egui::DragValue::new(&mut app.program_options.autosave_idle_seconds).clamp_range(-1..=3600))
+                .on_hover_text("Unsaved changes are saved once nothing has changed for this many seconds, if less than 0, changes are only saved when quitting or closing the window.");
         });
 
+        let current_backend = app.program_options.storage_backend;
+        options_panel_ui.label(format!("Current storage: {}", current_backend));
+
+        let migrating = app.ui_states.migrating_storage;
+        for backend in StorageBackendKind::iter().filter(|backend| *backend != current_backend) {
+            if options_panel_ui.add_enabled(!migrating, egui::Button::new(format!("Migrate to {}", backend))).on_hover_text("Copy every day into the selected storage and verify it, the current storage is left in place.").clicked() {
+                queue_storage_migration(ctx, app, backend);
+            }
+        }
+        if migrating {
+            draw_persistence_progress(options_panel_ui, app);
+        }
+    });
+}
+
+/// Profile options collapsing menu, for switching between and creating profiles
+#[tracing::instrument(skip(options_panel_ui, app, ctx))]
+pub fn draw_profile_options_menu(
+    options_panel_ui: &mut Ui,
+    app: &mut HappyChartState,
+    ctx: &Context,
+) {
+    options_panel_ui.collapsing("Profiles", |options_panel_ui| {
+        options_panel_ui.label(format!(
+            "Current profile: {}",
+            app.data_paths.profile().unwrap_or(DEFAULT_PROFILE_NAME)
+        ));
+        options_panel_ui.label(
+            "Each profile has its own journal, options, activities, encryption key and backups.",
+        );
+        options_panel_ui.label("Switch to profile:");
+        draw_profile_list(options_panel_ui, app, ctx);
+        options_panel_ui.separator();
+
         options_panel_ui.horizontal(|options_panel_ui| {
-            options_panel_ui.label("Number of stale backups before removal: ");
-            options_panel_ui.add(
-                egui::DragValue::new(&mut app.program_options.number_of_kept_backups)
-            ).on_hover_text("The minimum number of stale backups needed to be present in the backups folder before the program will remove any, -1 for disabled.");
+            options_panel_ui.label("New profile name: ");
+            options_panel_ui.text_edit_singleline(&mut app.ui_states.new_profile_name);
+            if options_panel_ui.button("Create profile").clicked() {
+                match create_profile(&app.data_paths, &app.ui_states.new_profile_name) {
+                    Ok(_) => app.ui_states.new_profile_name.clear(),
+                    Err(err) => {
+                        error!("Error creating profile: {}", err);
+                        app.error_states.push(err);
+                    }
+                }
+            }
         });
 
-        if options_panel_ui.button("Backup program state").on_hover_text("Compress the save state and the last session data into a zip file titled with the current date.").clicked() {
-            if let Err(err) = backup_program_state(ctx, app, true) {
-                error!("Error backing up program state: {}",err);
+        if options_panel_ui
+            .checkbox(
+                &mut app.profile_settings.choose_profile_at_startup,
+                "Ask which profile to open at startup",
+            )
+            .changed()
+        {
+            if let Err(err) = write_profile_settings(&app.data_paths, &app.profile_settings) {
+                error!("Error writing profile settings: {}", err);
                 app.error_states.push(err);
             }
-            app.last_backup_date = Local::now();
         }
     });
 }
+
+/// Sync options collapsing menu, for syncing the journal with other devices through a shared folder
+#[tracing::instrument(skip(options_panel_ui, app, ctx))]
+pub fn draw_sync_options_menu(options_panel_ui: &mut Ui, app: &mut HappyChartState, ctx: &Context) {
+    options_panel_ui.collapsing("Sync options", |options_panel_ui| {
+        match &app.program_options.sync_folder {
+            Some(sync_folder) => {
+                options_panel_ui.label(format!("Syncing through: {}", app.data_paths.resolve(sync_folder).display()));
+            }
+            None => {
+                options_panel_ui.label("Not syncing with other devices");
+            }
+        }
+
+        if options_panel_ui.button("Choose sync folder").on_hover_text("Choose a folder kept in sync between your devices by a file sync tool, each device writes its changes to its own file in it. When encryption is enabled, every device must use the same encryption key.").clicked() {
+            if let Some(path) = rfd::FileDialog::new()
+                .set_directory(app.data_paths.data_dir())
+                .set_title("Set the folder the journal is synced through")
+                .pick_folder()
+            {
+                if app.program_options.sync_device_id.is_empty() {
+                    app.program_options.sync_device_id = format!("{:016x}", rand::random::<u64>());
+                }
+                app.program_options.sync_folder = Some(path);
+                app.ui_states.sync_status = None;
+            }
+        }
+
+        if app.program_options.sync_folder.is_some() {
+            options_panel_ui.horizontal(|options_panel_ui| {
+                options_panel_ui.label("Minutes between syncs: ");
+                options_panel_ui
+                    .add(egui::DragValue::new(&mut app.program_options.sync_interval_minutes).clamp_range(-1..=1440))
+                    .on_hover_text("If less than 0, the journal is only synced when \"Sync now\" is clicked.");
+            });
+
+            if options_panel_ui.button("Sync now").clicked() {
+                if let Err(err) = queue_sync(ctx, app) {
+                    error!("Error syncing journal: {}", err);
+                    app.error_states.push(err);
+                }
+            }
+
+            if options_panel_ui.button("Stop syncing").on_hover_text("The journal on this device is kept, nothing is removed from the sync folder.").clicked() {
+                app.program_options.sync_folder = None;
+                app.ui_states.sync_status = None;
+            }
+        }
+
+        if let Some(status) = &app.ui_states.sync_status {
+            options_panel_ui.label(status);
+        }
+    });
+}
+
+/// Entry editing options collapsing menu, for the policy on changing older journal entries
+#[tracing::instrument(skip(options_panel_ui, app))]
+pub fn draw_entry_editing_options_menu(options_panel_ui: &mut Ui, app: &mut HappyChartState) {
+    options_panel_ui.collapsing("Entry editing options", |options_panel_ui| {
+        let previous_policy = app.program_options.entry_edit_policy;
+        options_panel_ui.horizontal(|options_panel_ui| {
+            options_panel_ui.label("Older entries: ");
+            for policy in EntryEditPolicy::iter() {
+                options_panel_ui.radio_value(&mut app.program_options.entry_edit_policy, policy, policy.to_string());
+            }
+        }).response.on_hover_text("Whether entries can still be edited, removed, or replaced by a merge once they are older than the number of days below. Read-only entries can be unlocked from the note editor, which is logged.");
+        if app.program_options.entry_edit_policy != previous_policy {
+            info!("Entry edit policy changed from {:?} to {:?}", previous_policy, app.program_options.entry_edit_policy);
+        }
+
+        if app.program_options.entry_edit_policy != EntryEditPolicy::AlwaysEditable {
+            options_panel_ui.horizontal(|options_panel_ui| {
+                options_panel_ui.label("Days before an entry is old: ");
+                options_panel_ui.add(egui::DragValue::new(&mut app.program_options.entry_edit_policy_days).clamp_range(0..=3650));
+            });
+        }
+
+        options_panel_ui.horizontal(|options_panel_ui| {
+            options_panel_ui.label("Days to keep deleted entries in the trash: ");
+            options_panel_ui.add(egui::DragValue::new(&mut app.program_options.trash_retention_days).clamp_range(-1..=3650));
+        }).response.on_hover_text("Deleted entries older than this are purged from the trash on launch and when the trash is opened, -1 keeps them until the trash is emptied.");
+    });
+}
@@ -0,0 +1,32 @@
+use crate::state::happy_chart_state::HappyChartState;
+use egui::Ui;
+
+/// Draw the screen shown while the save file of the profile is being read
+#[tracing::instrument(skip_all)]
+pub fn draw_loading_screen(ui: &mut Ui, app: &HappyChartState) {
+    ui.horizontal(|ui| {
+        ui.spinner();
+        ui.label(format!("Loading {}", app.data_paths.data_dir().display()));
+    });
+    draw_persistence_progress(ui, app);
+}
+
+/// Draw what the persistence worker is doing, nothing is drawn while it is idle
+#[tracing::instrument(skip_all)]
+pub fn draw_persistence_progress(ui: &mut Ui, app: &HappyChartState) {
+    if let Some(progress) = app.persistence.get_progress() {
+        ui.horizontal(|ui| {
+            ui.spinner();
+            ui.label(format!(
+                "{} ({:.0}%)",
+                progress.description,
+                progress.fraction * 100.0
+            ));
+        });
+    } else if app.quit_requested {
+        ui.horizontal(|ui| {
+            ui.spinner();
+            ui.label("Waiting for saving to finish before quitting");
+        });
+    }
+}
@@ -0,0 +1,46 @@
+use crate::common::profiles::{list_profiles, switch_profile};
+use crate::state::happy_chart_state::HappyChartState;
+use crate::DEFAULT_PROFILE_NAME;
+use egui::{Context, Ui};
+use tracing::error;
+
+/// Draw the profile chooser shown on launch, before any profile has been loaded
+#[tracing::instrument(skip(ui, app, ctx))]
+pub fn draw_profile_switcher_screen(ui: &mut Ui, app: &mut HappyChartState, ctx: &Context) {
+    ui.label("Choose the profile to open:");
+    draw_profile_list(ui, app, ctx);
+    ui.separator();
+    ui.checkbox(
+        &mut app.profile_settings.choose_profile_at_startup,
+        "Ask which profile to open at startup",
+    );
+}
+
+/// Draw a button for every profile that switches to it, the profile in use is shown but can not be switched to
+#[tracing::instrument(skip(ui, app, ctx))]
+pub fn draw_profile_list(ui: &mut Ui, app: &mut HappyChartState, ctx: &Context) {
+    let current_profile = app.data_paths.profile().map(ToString::to_string);
+    let profiles = std::iter::once(None)
+        .chain(list_profiles(&app.data_paths).into_iter().map(Some))
+        .collect::<Vec<Option<String>>>();
+
+    for profile in profiles {
+        let name = profile.as_deref().unwrap_or(DEFAULT_PROFILE_NAME);
+        let is_current = profile == current_profile && !app.ui_states.showing_profile_switcher;
+        if ui
+            .add_enabled(
+                !is_current && app.profile_switch_requested.is_none(),
+                egui::Button::new(name),
+            )
+            .on_disabled_hover_text("This profile is open, or another profile is being switched to")
+            .clicked()
+        {
+            if let Err(err) = switch_profile(ctx, app, profile.as_deref()) {
+                error!("Error switching to profile {:?}: {}", profile, err);
+                app.error_states.push(err);
+            }
+            // the program state may have been replaced, so the rest of the old list must not be drawn against it
+            return;
+        }
+    }
+}
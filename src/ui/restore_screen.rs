@@ -0,0 +1,209 @@
+use crate::common::encryption::SaveCipher;
+use crate::common::merge::start_merge;
+use crate::common::restore::{queue_backup_listing, queue_restore, PreviewDay, RestorePreview};
+use crate::prelude::HappyChartState;
+use crate::state::happy_chart_state::RestoreUIState;
+use crate::ui::persistence_screen::draw_persistence_progress;
+use eframe::epaint::Color32;
+use egui::{Context, RichText, ScrollArea, TextEdit, Ui};
+use std::rc::Rc;
+use tracing::error;
+
+/// Number of dates listed for each kind of difference in the restore preview before the rest are summarized
+const PREVIEW_DATE_LIST_LENGTH: usize = 10;
+
+/// Screen listing every backup archive, with a preview of what restoring one would change
+#[tracing::instrument(skip_all)]
+pub fn draw_restore_screen(ui: &mut Ui, app: &mut HappyChartState, ctx: &Context) {
+    ui.horizontal(|ui| {
+        ui.label("Backup encryption key:");
+        ui.add(TextEdit::singleline(&mut app.ui_states.restore_ui_state.encryption_key).password(true))
+            .on_hover_text("The key encrypted backups were made with, leave empty to use the backup key currently in use.");
+        if ui
+            .add_enabled(
+                !app.ui_states.restore_ui_state.listing,
+                egui::Button::new("Read backups"),
+            )
+            .clicked()
+        {
+            app.ui_states.restore_ui_state.archives = None;
+        }
+    });
+
+    let Some(archives) = app.ui_states.restore_ui_state.archives.clone() else {
+        if !app.ui_states.restore_ui_state.listing {
+            let cipher = get_restore_cipher(app);
+            queue_backup_listing(ctx, app, cipher);
+        }
+        ui.separator();
+        ui.label("Reading backups...");
+        draw_close_button(ui, app);
+        return;
+    };
+
+    ui.separator();
+
+    if archives.is_empty() {
+        ui.label(format!(
+            "No backups were found in {}",
+            app.get_backup_dir().display()
+        ));
+    }
+
+    ScrollArea::vertical()
+        .id_source("restore_backup_list")
+        .max_height(250.0)
+        .show(ui, |ui| {
+            for (index, archive) in archives.iter().enumerate() {
+                let file_name = archive
+                    .path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .unwrap_or("UNABLE TO DISPLAY FILE NAME");
+                let selected = app.ui_states.restore_ui_state.selected_archive == Some(index);
+
+                match &archive.contents {
+                    Ok(contents) => {
+                        let date_range = contents.get_date_range().map_or(
+                            "no days".to_string(),
+                            |(first, last)| {
+                                format!("{} to {}", first.date_naive(), last.date_naive())
+                            },
+                        );
+                        if ui
+                            .selectable_label(
+                                selected,
+                                format!(
+                                    "{} - {} days, {}",
+                                    file_name,
+                                    contents.days.len(),
+                                    date_range
+                                ),
+                            )
+                            .clicked()
+                        {
+                            app.ui_states.restore_ui_state.selected_archive = Some(index);
+                            app.ui_states.restore_ui_state.preview =
+                                Some(Rc::new(RestorePreview::new(&app.days, &contents.days)));
+                            app.ui_states.restore_ui_state.status = None;
+                        }
+                    }
+                    Err(err) => {
+                        ui.label(
+                            RichText::new(format!("{} - unable to read: {}", file_name, err))
+                                .color(Color32::LIGHT_RED),
+                        );
+                    }
+                }
+            }
+        });
+
+    let selected_contents = app
+        .ui_states
+        .restore_ui_state
+        .selected_archive
+        .and_then(|index| archives.get(index))
+        .and_then(|archive| archive.contents.as_ref().ok());
+
+    if let (Some(contents), Some(preview)) = (
+        selected_contents,
+        app.ui_states.restore_ui_state.preview.clone(),
+    ) {
+        ui.separator();
+        ui.label(format!(
+            "Restoring this backup would change the journal of {} days into {} days:",
+            app.days.len(),
+            contents.days.len()
+        ));
+        draw_preview_date_list(ui, "Added", &preview.added);
+        draw_preview_date_list(ui, "Removed", &preview.removed);
+        draw_preview_date_list(ui, "Changed", &preview.changed);
+        ui.label(format!("Unchanged: {}", preview.unchanged_count));
+
+        ui.add_enabled(
+            contents.program_options.is_some(),
+            egui::Checkbox::new(
+                &mut app.ui_states.restore_ui_state.restore_program_options,
+                "Restore program options",
+            ),
+        )
+        .on_hover_text("Restore the options from the backup as well as the day list, encryption and storage options are never restored.")
+        .on_disabled_hover_text("This backup has no program options that can be read.");
+
+        if ui
+            .button("Merge into journal")
+            .on_hover_text("Keep the current journal and add the days from this backup, choosing between days that differ.")
+            .clicked()
+        {
+            let source = archives
+                .get(app.ui_states.restore_ui_state.selected_archive.unwrap_or_default())
+                .map(|archive| archive.path.display().to_string())
+                .unwrap_or_default();
+            start_merge(app, source, &contents.days);
+        }
+
+        let restoring = app.ui_states.restore_ui_state.pending_restore.is_some();
+        if ui
+            .add_enabled(!restoring, egui::Button::new("Restore backup"))
+            .on_hover_text("A backup of the current program state is made before restoring.")
+            .clicked()
+        {
+            let restore_program_options = app.ui_states.restore_ui_state.restore_program_options;
+            if let Err(err) = queue_restore(ctx, app, contents, restore_program_options) {
+                error!("Error restoring backup: {}", err);
+                app.ui_states.restore_ui_state.status = None;
+                app.error_states.push(err);
+            }
+        }
+        if restoring {
+            draw_persistence_progress(ui, app);
+        }
+    }
+
+    if let Some(status) = &app.ui_states.restore_ui_state.status {
+        ui.separator();
+        ui.label(status);
+    }
+
+    ui.separator();
+    draw_close_button(ui, app);
+}
+
+fn draw_close_button(ui: &mut Ui, app: &mut HappyChartState) {
+    if ui.button("Close").clicked() {
+        app.ui_states.showing_restore_screen = false;
+        app.ui_states.restore_ui_state = RestoreUIState::default();
+    }
+}
+
+/// Lists the dates of one kind of difference in the restore preview
+fn draw_preview_date_list(ui: &mut Ui, label: &str, days: &[PreviewDay]) {
+    if days.is_empty() {
+        ui.label(format!("{}: 0", label));
+        return;
+    }
+    ui.collapsing(format!("{}: {}", label, days.len()), |ui| {
+        for day in days.iter().take(PREVIEW_DATE_LIST_LENGTH) {
+            ui.label(day.date.format("%Y-%m-%d %H:%M").to_string());
+        }
+        if days.len() > PREVIEW_DATE_LIST_LENGTH {
+            ui.label(format!(
+                "and {} more",
+                days.len() - PREVIEW_DATE_LIST_LENGTH
+            ));
+        }
+    });
+}
+
+/// Returns the cipher used to read encrypted backups, the entered key if there is one, otherwise the backup key in use
+fn get_restore_cipher(app: &HappyChartState) -> Option<SaveCipher> {
+    let encryption_key = &app.ui_states.restore_ui_state.encryption_key;
+    if encryption_key.is_empty() {
+        app.get_backup_cipher().ok().flatten()
+    } else {
+        Some(SaveCipher::new(
+            encryption_key,
+            app.program_options.key_derivation_settings,
+        ))
+    }
+}
@@ -111,11 +111,13 @@ pub fn draw_previous_duration_stats_screen(_ctx: &Context, ui: &mut Ui, app: &mu
             "Longest streak {}",
             app.stats.get_streak_stats().longest_streak
         ));
-        ui.label(format!(
-            "Streak start-end {}-{}",
-            app.stats.get_streak_stats().streak_start_index,
-            app.stats.get_streak_stats().streak_end_index
-        ));
+        if let Some((start, end)) = app.stats.get_streak_stats().get_streak_dates(&app.days) {
+            ui.label(format!(
+                "Streak start-end {} - {}",
+                start.format("%Y-%m-%d"),
+                end.format("%Y-%m-%d")
+            ));
+        }
         // TODO: heatmap using a calendar widget to show quality on each day average?
     }
 
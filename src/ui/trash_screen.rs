@@ -0,0 +1,88 @@
+use crate::common::history::{execute_command, JournalCommand};
+use crate::prelude::HappyChartState;
+use egui::{ScrollArea, Ui};
+use tracing::info;
+
+/// Screen listing the days in the trash, with buttons to restore them or delete them permanently
+#[tracing::instrument(skip_all)]
+pub fn draw_trash_screen(ui: &mut Ui, app: &mut HappyChartState) {
+    let retention_days = app.program_options.trash_retention_days;
+    if retention_days < 0 {
+        ui.label("Deleted days are kept until they are deleted permanently.");
+    } else {
+        ui.label(format!(
+            "Deleted days are deleted permanently after {} days in the trash.",
+            retention_days
+        ));
+    }
+
+    ui.separator();
+
+    if app.trash.is_empty() {
+        ui.label("The trash is empty");
+    }
+
+    let mut restore = None;
+    let mut delete_permanently = None;
+    ScrollArea::vertical()
+        .id_source("trash_list")
+        .max_height(300.0)
+        .show(ui, |ui| {
+            // most recently deleted first
+            for trashed in app.trash.iter().rev() {
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "{} rating {:.1}, deleted {}",
+                        trashed.day.get_date().format("%Y-%m-%d %H:%M"),
+                        trashed.day.get_rating(),
+                        trashed.deleted.format("%Y-%m-%d %H:%M")
+                    ))
+                    .on_hover_text(trashed.day.get_note());
+                    if ui
+                        .button("Restore")
+                        .on_hover_text("Moves the day back into the journal")
+                        .clicked()
+                    {
+                        restore = Some(trashed.day.clone());
+                    }
+                    if ui
+                        .button("Delete permanently")
+                        .on_hover_text("Deletes the day for good, this can not be undone")
+                        .clicked()
+                    {
+                        delete_permanently = Some(*trashed.day.get_id());
+                    }
+                });
+            }
+        });
+
+    if let Some(day) = restore {
+        execute_command(app, JournalCommand::RestoreDay(day));
+    }
+    if let Some(id) = delete_permanently {
+        if let Some(trashed) = app.take_from_trash(&id) {
+            info!(
+                "Day {} deleted permanently from the trash",
+                trashed.day.get_date()
+            );
+            app.mark_changed();
+        }
+    }
+
+    ui.separator();
+    ui.horizontal(|ui| {
+        if ui
+            .add_enabled(!app.trash.is_empty(), egui::Button::new("Empty trash"))
+            .on_hover_text("Deletes every day in the trash for good, this can not be undone")
+            .clicked()
+        {
+            info!("Emptied the trash of {} days", app.trash.len());
+            app.trash.clear();
+            app.mark_changed();
+        }
+        if ui.button("Close").clicked() {
+            info!("Trash screen closed");
+            app.ui_states.showing_trash_screen = false;
+        }
+    });
+}
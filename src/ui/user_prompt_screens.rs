@@ -1,7 +1,6 @@
 use crate::common::set_file_logging_state;
 use crate::prelude::HappyChartState;
 use crate::ui::options_menu::draw_backup_settings_options_menu;
-use crate::LOG_FILE_NAME;
 use egui::Context;
 
 #[tracing::instrument(skip_all)]
@@ -14,13 +13,13 @@ pub fn draw_user_prompts(ctx: &Context, app: &mut HappyChartState) {
         && !app.program_options.log_to_file
     {
         egui::Window::new("Logging").show(ctx,|ui| {
-            ui.label(format!("Happy chart supports logging info and errors to a file named {}", LOG_FILE_NAME));
+            ui.label(format!("Happy chart supports logging info and errors to a file at {}", app.data_paths.log_file().display()));
             ui.label("The log does its best to not expose any identifying information that I would consider private, however you are free to disable it, or check yourself what data it logs.");
             ui.label("If you see any information that should not be logged due to any reason please make an issue on the github page here:");
             ui.hyperlink("https://github.com/CoryRobertson/happy_chart_rs");
             if ui.button("Enable logging").clicked() {
                 app.program_options.log_to_file = true;
-                set_file_logging_state(app.program_options.log_to_file);
+                set_file_logging_state(app.program_options.log_to_file, &app.data_paths.log_file());
                 app.program_options.user_prompts.tried_logging = true;
             }
 